@@ -1,9 +1,16 @@
 DROP TABLE demo.notification;
 
 CREATE TABLE demo.notification (
-    experiment_id TEXT,
-    measurement_id TEXT, 
+    id BIGSERIAL PRIMARY KEY,
+    experiment_id TEXT NOT NULL,
+    measurement_id TEXT NOT NULL, 
     group_id TEXT,
-    latency DOUBLE PRECISION,
-    PRIMARY KEY(experiment_id, measurement_id, group_id)
+    notification_type TEXT NOT NULL,
+    researcher TEXT NOT NULL,
+    received_at DOUBLE PRECISION NOT NULL,
+    latency DOUBLE PRECISION NOT NULL
 );
+
+-- Notifications without a group are duplicates of each other as well
+CREATE UNIQUE INDEX notification_measurement_group
+    ON demo.notification (experiment_id, measurement_id, COALESCE(group_id, ''));
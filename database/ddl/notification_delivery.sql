@@ -0,0 +1,10 @@
+DROP TABLE demo.notification_delivery;
+
+CREATE TABLE demo.notification_delivery (
+    experiment_id TEXT,
+    measurement_id TEXT, 
+    group_id TEXT,
+    channel TEXT,
+    error TEXT,
+    delivered_at TIMESTAMP DEFAULT now()
+);
@@ -3,6 +3,7 @@ DROP TABLE demo.notification_ground_truth;
 CREATE TABLE demo.notification_ground_truth (
     experiment_id TEXT,
     measurement_id TEXT, 
+    notification_type TEXT,
     insert_timestamp TIMESTAMP DEFAULT now(),
     PRIMARY KEY(experiment_id, measurement_id)
 );
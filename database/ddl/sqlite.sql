@@ -0,0 +1,34 @@
+-- Schema of the notifications-service with DATABASE_URL=sqlite:<path>, created on start
+
+CREATE TABLE IF NOT EXISTS notification (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    experiment_id TEXT NOT NULL,
+    measurement_id TEXT NOT NULL,
+    group_id TEXT,
+    notification_type TEXT NOT NULL,
+    researcher TEXT NOT NULL,
+    received_at REAL NOT NULL,
+    latency REAL NOT NULL
+);
+
+-- Notifications without a group are duplicates of each other as well
+CREATE UNIQUE INDEX IF NOT EXISTS notification_measurement_group
+    ON notification (experiment_id, measurement_id, COALESCE(group_id, ''));
+
+-- `insert_timestamp` is seconds since the epoch
+CREATE TABLE IF NOT EXISTS notification_ground_truth (
+    experiment_id TEXT NOT NULL,
+    measurement_id TEXT NOT NULL,
+    notification_type TEXT,
+    insert_timestamp REAL DEFAULT ((julianday('now') - 2440587.5) * 86400.0),
+    PRIMARY KEY(experiment_id, measurement_id)
+);
+
+CREATE TABLE IF NOT EXISTS notification_delivery (
+    experiment_id TEXT,
+    measurement_id TEXT,
+    group_id TEXT,
+    channel TEXT,
+    error TEXT,
+    delivered_at REAL DEFAULT ((julianday('now') - 2440587.5) * 86400.0)
+);
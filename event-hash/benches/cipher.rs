@@ -0,0 +1,60 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use event_hash::{Algorithm, Envelope, HashData, Keyring, NotificationType};
+
+const KEY: &[u8] = b"QJUHsPhnA0eiqHuJqsPgzhDozYO4f1zh";
+
+fn hash_data() -> HashData {
+    HashData {
+        notification_type: Some(NotificationType::OutOfRange),
+        researcher: "d.landau@uu.nl".into(),
+        experiment_id: "5678".into(),
+        measurement_id: "1234".into(),
+        timestamp: 1692029115.4314,
+        issued_at: Some(1692029115.4314),
+        expires_at: None,
+    }
+}
+
+/// The keyrings of every algorithm, and of AES-256-GCM in the v1 envelope.
+fn keyrings() -> Vec<(&'static str, Keyring)> {
+    let aes_v1 = Keyring::new(KEY).unwrap();
+    let with = |algorithm| {
+        let mut keyring = Keyring::new(KEY).unwrap();
+        keyring.set_envelope(Envelope::V2);
+        keyring.set_algorithm(algorithm);
+        keyring
+    };
+    vec![
+        ("aes-256-gcm/v1", aes_v1),
+        ("aes-256-gcm/v2", with(Algorithm::Aes256Gcm)),
+        ("chacha20-poly1305/v2", with(Algorithm::ChaCha20Poly1305)),
+        ("xchacha20-poly1305/v2", with(Algorithm::XChaCha20Poly1305)),
+    ]
+}
+
+fn encrypt(c: &mut Criterion) {
+    let mut group = c.benchmark_group("encrypt");
+    group.throughput(Throughput::Elements(1));
+    let hash_data = hash_data();
+    for (name, keyring) in keyrings() {
+        group.bench_with_input(BenchmarkId::from_parameter(name), &keyring, |b, keyring| {
+            b.iter(|| hash_data.encrypt_with(keyring))
+        });
+    }
+    group.finish();
+}
+
+fn decrypt(c: &mut Criterion) {
+    let mut group = c.benchmark_group("decrypt");
+    group.throughput(Throughput::Elements(1));
+    for (name, keyring) in keyrings() {
+        let cipher = hash_data().encrypt_with(&keyring);
+        group.bench_with_input(BenchmarkId::from_parameter(name), &cipher, |b, cipher| {
+            b.iter(|| HashData::decrypt_with(&keyring, cipher, "5678", "1234").unwrap())
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, encrypt, decrypt);
+criterion_main!(benches);
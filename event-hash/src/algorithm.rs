@@ -0,0 +1,91 @@
+use aes_gcm::{
+    aead::{Aead, AeadCore, KeyInit, OsRng, Payload},
+    Aes256Gcm,
+};
+use chacha20poly1305::{ChaCha20Poly1305, XChaCha20Poly1305};
+use generic_array::GenericArray;
+
+use crate::SecretKey;
+
+/// AEAD of the measurement hashes. ChaCha20-Poly1305 is faster than AES-256-GCM on CPUs
+/// without AES instructions, and XChaCha20-Poly1305 has nonces long enough to be random
+/// for any number of messages.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum Algorithm {
+    #[default]
+    #[cfg_attr(feature = "cli", value(name = "aes-256-gcm"))]
+    Aes256Gcm,
+    #[cfg_attr(feature = "cli", value(name = "chacha20-poly1305"))]
+    ChaCha20Poly1305,
+    #[cfg_attr(feature = "cli", value(name = "xchacha20-poly1305"))]
+    XChaCha20Poly1305,
+}
+
+impl Algorithm {
+    /// Identifier of the algorithm in a v2 cipher.
+    pub(crate) fn id(self) -> u8 {
+        match self {
+            Algorithm::Aes256Gcm => 0,
+            Algorithm::ChaCha20Poly1305 => 1,
+            Algorithm::XChaCha20Poly1305 => 2,
+        }
+    }
+
+    pub(crate) fn from_id(id: u8) -> Option<Self> {
+        match id {
+            0 => Some(Algorithm::Aes256Gcm),
+            1 => Some(Algorithm::ChaCha20Poly1305),
+            2 => Some(Algorithm::XChaCha20Poly1305),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn nonce_len(self) -> usize {
+        match self {
+            Algorithm::Aes256Gcm | Algorithm::ChaCha20Poly1305 => 12,
+            Algorithm::XChaCha20Poly1305 => 24,
+        }
+    }
+
+    /// Encrypts with a random nonce, returning the nonce and ciphertext.
+    pub(crate) fn encrypt(self, key: &SecretKey, payload: Payload) -> (Vec<u8>, Vec<u8>) {
+        match self {
+            Algorithm::Aes256Gcm => encrypt::<Aes256Gcm>(key, payload),
+            Algorithm::ChaCha20Poly1305 => encrypt::<ChaCha20Poly1305>(key, payload),
+            Algorithm::XChaCha20Poly1305 => encrypt::<XChaCha20Poly1305>(key, payload),
+        }
+    }
+
+    /// `None` when `key` does not authenticate the ciphertext. `nonce` is `nonce_len` bytes.
+    pub(crate) fn decrypt(
+        self,
+        key: &SecretKey,
+        nonce: &[u8],
+        payload: Payload,
+    ) -> Option<Vec<u8>> {
+        match self {
+            Algorithm::Aes256Gcm => decrypt::<Aes256Gcm>(key, nonce, payload),
+            Algorithm::ChaCha20Poly1305 => decrypt::<ChaCha20Poly1305>(key, nonce, payload),
+            Algorithm::XChaCha20Poly1305 => decrypt::<XChaCha20Poly1305>(key, nonce, payload),
+        }
+    }
+}
+
+fn encrypt<C: Aead + AeadCore + KeyInit>(key: &SecretKey, payload: Payload) -> (Vec<u8>, Vec<u8>) {
+    let cipher = C::new_from_slice(key.as_bytes()).expect("Key should be 32 bytes");
+    let nonce = C::generate_nonce(&mut OsRng);
+    let ciphertext = cipher.encrypt(&nonce, payload).unwrap();
+    (nonce.to_vec(), ciphertext)
+}
+
+fn decrypt<C: Aead + AeadCore + KeyInit>(
+    key: &SecretKey,
+    nonce: &[u8],
+    payload: Payload,
+) -> Option<Vec<u8>> {
+    let cipher = C::new_from_slice(key.as_bytes()).expect("Key should be 32 bytes");
+    cipher
+        .decrypt(GenericArray::from_slice(nonce), payload)
+        .ok()
+}
@@ -0,0 +1,268 @@
+use serde::Deserialize;
+use std::{fs, time::Duration};
+
+use crate::{Algorithm, Envelope, SecretKey, SecretKeyError};
+
+#[derive(Clone)]
+pub(crate) struct KeyringEntry {
+    pub(crate) id: Option<String>,
+    pub(crate) key: SecretKey,
+}
+
+/// Keys of the measurement hashes. The active key encrypts, and every key decrypts, so
+/// the key can be rotated without breaking the messages in flight.
+///
+/// A key without ID produces ciphers without key ID (`nonce.ciphertext`), i.e. the
+/// format before key rotation, and is tried for any cipher whose key ID is unknown.
+#[derive(Clone)]
+pub struct Keyring {
+    pub(crate) entries: Vec<KeyringEntry>,
+    active: usize,
+    envelope: Envelope,
+    algorithm: Algorithm,
+    ttl: Option<Duration>,
+}
+
+/// Shows the key IDs, never the keys.
+impl std::fmt::Debug for Keyring {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("Keyring")
+            .field(
+                "key_ids",
+                &self
+                    .entries
+                    .iter()
+                    .map(|entry| &entry.id)
+                    .collect::<Vec<_>>(),
+            )
+            .field("active", &self.active_key_id())
+            .field("envelope", &self.envelope)
+            .field("algorithm", &self.algorithm)
+            .field("ttl", &self.ttl)
+            .finish()
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum KeyringError {
+    InvalidKeyLength(Option<String>),
+    InvalidKey(String, SecretKeyError),
+    InvalidKeyId(String),
+    ReservedKeyId(String),
+    DuplicateKeyId(String),
+    UnknownActiveKey(String),
+    NoKeys,
+    ReadError(String),
+    JsonDeserializationError(String),
+}
+
+impl std::error::Error for KeyringError {}
+
+impl std::fmt::Display for KeyringError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            KeyringError::InvalidKeyLength(Some(id)) => write!(f, "Key `{}` is not 32 bytes", id),
+            KeyringError::InvalidKeyLength(None) => write!(f, "Key is not 32 bytes"),
+            KeyringError::InvalidKey(id, err) => write!(f, "Key `{}`: {}", id, err),
+            KeyringError::InvalidKeyId(id) => {
+                write!(f, "Key ID `{}` must have 1 to 255 bytes and no `.`", id)
+            }
+            KeyringError::ReservedKeyId(id) => {
+                write!(f, "Key ID `{}` is reserved for envelope versions", id)
+            }
+            KeyringError::DuplicateKeyId(id) => write!(f, "Key ID `{}` is not unique", id),
+            KeyringError::UnknownActiveKey(id) => write!(f, "Active key `{}` is not a key", id),
+            KeyringError::NoKeys => write!(f, "Keyring has no keys"),
+            KeyringError::ReadError(err) => write!(f, "Could not read keyring: {}", err),
+            KeyringError::JsonDeserializationError(err) => write!(f, "Invalid keyring: {}", err),
+        }
+    }
+}
+
+/// `{"active": "2023-10", "keys": [{"id": "2023-09", "key": "..."}, {"id": "2023-10", "key": "..."}]}`
+#[derive(Deserialize)]
+struct KeyringFile {
+    active: String,
+    keys: Vec<KeyFileEntry>,
+}
+
+#[derive(Deserialize)]
+struct KeyFileEntry {
+    id: String,
+    key: String,
+}
+
+/// Whether `key_id` looks like the tag of an envelope version, e.g. `v2` of `v2.<envelope>`.
+/// A cipher `v2.<nonce>.<ciphertext>` of such a key would be opened as a v2 envelope.
+fn is_envelope_tag(key_id: &str) -> bool {
+    key_id
+        .strip_prefix('v')
+        .is_some_and(|version| !version.is_empty() && version.bytes().all(|b| b.is_ascii_digit()))
+}
+
+fn key(id: Option<&str>, key: &[u8]) -> Result<SecretKey, KeyringError> {
+    SecretKey::from_bytes(key).map_err(|_| KeyringError::InvalidKeyLength(id.map(Into::into)))
+}
+
+/// Parses a key of the keyring file, in any format of `SecretKey`.
+fn parse_key(id: &str, key: &str) -> Result<SecretKey, KeyringError> {
+    key.parse().map_err(|err| match err {
+        SecretKeyError::InvalidLength(_) => KeyringError::InvalidKeyLength(Some(id.into())),
+        err => KeyringError::InvalidKey(id.into(), err),
+    })
+}
+
+impl From<SecretKey> for Keyring {
+    /// Keyring of a single key without ID.
+    fn from(key: SecretKey) -> Self {
+        Self {
+            entries: vec![KeyringEntry { id: None, key }],
+            active: 0,
+            envelope: Envelope::default(),
+            algorithm: Algorithm::default(),
+            ttl: None,
+        }
+    }
+}
+
+impl Keyring {
+    /// Keyring of a single key without ID.
+    pub fn new(key: &[u8]) -> Result<Self, KeyringError> {
+        Ok(self::key(None, key)?.into())
+    }
+
+    /// Reads a JSON keyring file. The keys are in the formats of `--secret-key`, e.g. 32
+    /// character strings.
+    pub fn from_file(file_path: &str) -> Result<Self, KeyringError> {
+        let contents = fs::read_to_string(file_path)
+            .map_err(|err| KeyringError::ReadError(format!("`{}`: {}", file_path, err)))?;
+        Self::from_json(&contents)
+    }
+
+    pub fn from_json(contents: &str) -> Result<Self, KeyringError> {
+        let file: KeyringFile = serde_json::from_str(contents)
+            .map_err(|err| KeyringError::JsonDeserializationError(err.to_string()))?;
+        let mut keyring: Option<Self> = None;
+        for entry in file.keys {
+            let key = parse_key(&entry.id, &entry.key)?;
+            match &mut keyring {
+                Some(keyring) => keyring.add_key(&entry.id, key)?,
+                None => keyring = Some(Self::with_key(&entry.id, key)?),
+            }
+        }
+        let mut keyring = keyring.ok_or(KeyringError::NoKeys)?;
+        keyring.set_active(&file.active)?;
+        Ok(keyring)
+    }
+
+    /// Keyring of a single key, encrypting with its `key_id`.
+    pub fn with_id(key_id: &str, key: &[u8]) -> Result<Self, KeyringError> {
+        Self::with_key(key_id, self::key(Some(key_id), key)?)
+    }
+
+    pub fn with_key(key_id: &str, key: SecretKey) -> Result<Self, KeyringError> {
+        if key_id.is_empty() || key_id.len() > u8::MAX.into() || key_id.contains('.') {
+            return Err(KeyringError::InvalidKeyId(key_id.into()));
+        }
+        if is_envelope_tag(key_id) {
+            return Err(KeyringError::ReservedKeyId(key_id.into()));
+        }
+        Ok(Self {
+            entries: vec![KeyringEntry {
+                id: Some(key_id.into()),
+                key,
+            }],
+            active: 0,
+            envelope: Envelope::default(),
+            algorithm: Algorithm::default(),
+            ttl: None,
+        })
+    }
+
+    /// Adds a key accepted for decryption.
+    pub fn add(&mut self, key_id: &str, key: &[u8]) -> Result<(), KeyringError> {
+        self.add_key(key_id, self::key(Some(key_id), key)?)
+    }
+
+    pub fn add_key(&mut self, key_id: &str, key: SecretKey) -> Result<(), KeyringError> {
+        if self
+            .entries
+            .iter()
+            .any(|entry| entry.id.as_deref() == Some(key_id))
+        {
+            return Err(KeyringError::DuplicateKeyId(key_id.into()));
+        }
+        let mut entries = Self::with_key(key_id, key)?.entries;
+        self.entries.append(&mut entries);
+        Ok(())
+    }
+
+    /// Encrypts with the key `key_id` from now on.
+    pub fn set_active(&mut self, key_id: &str) -> Result<(), KeyringError> {
+        self.active = self
+            .entries
+            .iter()
+            .position(|entry| entry.id.as_deref() == Some(key_id))
+            .ok_or_else(|| KeyringError::UnknownActiveKey(key_id.into()))?;
+        Ok(())
+    }
+
+    /// Encrypts in `envelope` from now on.
+    pub fn set_envelope(&mut self, envelope: Envelope) {
+        self.envelope = envelope;
+    }
+
+    pub fn envelope(&self) -> Envelope {
+        self.envelope
+    }
+
+    /// Encrypts with `algorithm` from now on. Only AES-256-GCM has a v1 envelope, so the
+    /// other algorithms always encrypt in v2.
+    pub fn set_algorithm(&mut self, algorithm: Algorithm) {
+        self.algorithm = algorithm;
+    }
+
+    pub fn algorithm(&self) -> Algorithm {
+        self.algorithm
+    }
+
+    /// Ciphers issued from now on expire `ttl` after they are issued, or never when `None`.
+    pub fn set_ttl(&mut self, ttl: Option<Duration>) {
+        self.ttl = ttl;
+    }
+
+    /// Expiry of a cipher issued at `issued_at` (epoch seconds).
+    pub fn expires_at(&self, issued_at: f64) -> Option<f64> {
+        self.ttl.map(|ttl| issued_at + ttl.as_secs_f64())
+    }
+
+    pub fn active_key_id(&self) -> Option<&str> {
+        self.entries[self.active].id.as_deref()
+    }
+
+    pub(crate) fn active(&self) -> &KeyringEntry {
+        &self.entries[self.active]
+    }
+
+    /// Keys to try for a cipher: the key with its ID, or else those without ID.
+    pub(crate) fn candidates(&self, key_id: Option<&str>) -> Vec<&SecretKey> {
+        let with_id = |id: Option<&str>| -> Vec<&SecretKey> {
+            self.entries
+                .iter()
+                .filter(|entry| entry.id.as_deref() == id)
+                .map(|entry| &entry.key)
+                .collect()
+        };
+        match key_id {
+            Some(key_id) => {
+                let keys = with_id(Some(key_id));
+                if keys.is_empty() {
+                    with_id(None)
+                } else {
+                    keys
+                }
+            }
+            None => self.entries.iter().map(|entry| &entry.key).collect(),
+        }
+    }
+}
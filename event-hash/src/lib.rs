@@ -1,18 +1,37 @@
-use aes_gcm::{
-    aead::{Aead, AeadCore, KeyInit, OsRng},
-    Aes256Gcm,
-    Key, // Or `Aes128Gcm`
-};
+use aes_gcm::aead::Payload;
 use base64::{engine::general_purpose, Engine as _};
-use generic_array::GenericArray;
 use serde::{Deserialize, Serialize};
 
+mod algorithm;
+mod keyring;
+mod secret_key;
+mod v2;
+mod validation;
+
+pub use algorithm::Algorithm;
+pub use keyring::{Keyring, KeyringError};
+pub use secret_key::{SecretKey, SecretKeyError};
+pub use validation::ValidationError;
+
 #[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
 pub enum NotificationType {
     OutOfRange,
     Stabilized,
 }
 
+/// Format of the ciphers produced by encryption.
+///
+/// - `V1`: `[key_id.]nonce.ciphertext` of the JSON encoded `HashData`, always AES-256-GCM.
+/// - `V2`: `v2.` and the base64 encoding of the algorithm, key ID, nonce and ciphertext of
+///   a binary encoded `HashData`, with the experiment and measurement IDs as associated
+///   data.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Envelope {
+    #[default]
+    V1,
+    V2,
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct HashData {
     pub notification_type: Option<NotificationType>,
@@ -20,6 +39,12 @@ pub struct HashData {
     pub experiment_id: String,
     pub measurement_id: String,
     pub timestamp: f64,
+    /// Epoch seconds of the encryption. Not set in ciphers issued before expiry existed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub issued_at: Option<f64>,
+    /// Epoch seconds after which the notification is rejected. Never when not set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<f64>,
 }
 
 #[derive(Debug, Serialize)]
@@ -30,34 +55,71 @@ pub enum DecryptError {
     DecryptionError,
     Utf8DecodingError,
     JsonDeserializationError,
+    UnknownKeyId,
+    MalformedBinaryPayload,
+    UnknownAlgorithm,
 }
 
 impl std::error::Error for DecryptError {}
 
 impl std::fmt::Display for DecryptError {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        write!(f, "{}", self)
+        write!(f, "{:?}", self)
     }
 }
 
 impl HashData {
-    pub fn decrypt(key: &[u8], hash_data: &str) -> Result<HashData, DecryptError> {
-        let cipher_components: Vec<_> = hash_data.split(".").collect();
-        if cipher_components.len() != 2 {
-            return Err(DecryptError::MalformedHashDataString);
+    pub fn is_expired(&self, now: f64) -> bool {
+        self.expires_at.is_some_and(|expires_at| now > expires_at)
+    }
+
+    pub fn decrypt(
+        key: &SecretKey,
+        hash_data: &str,
+        experiment_id: &str,
+        measurement_id: &str,
+    ) -> Result<HashData, DecryptError> {
+        let keyring = Keyring::from(key.clone());
+        Self::decrypt_with(&keyring, hash_data, experiment_id, measurement_id)
+    }
+
+    /// Decrypts either envelope. A v1 cipher is `key_id.nonce.ciphertext`, decrypted with
+    /// the key `key_id`, or `nonce.ciphertext` (no key ID), decrypted with any key of the
+    /// keyring. A v2 cipher only decrypts for the `experiment_id` and `measurement_id` it
+    /// was encrypted for; those of a v1 cipher are checked by the caller.
+    pub fn decrypt_with(
+        keyring: &Keyring,
+        hash_data: &str,
+        experiment_id: &str,
+        measurement_id: &str,
+    ) -> Result<HashData, DecryptError> {
+        if let Some(envelope) = hash_data.strip_prefix(v2::PREFIX) {
+            return v2::open(keyring, envelope, experiment_id, measurement_id);
         }
-        let key = Key::<Aes256Gcm>::from_slice(key);
-        let cipher = Aes256Gcm::new(&key);
+        let cipher_components: Vec<_> = hash_data.split(".").collect();
+        let (key_id, b64_nonce, b64_ciphertext) = match cipher_components[..] {
+            [b64_nonce, b64_ciphertext] => (None, b64_nonce, b64_ciphertext),
+            [key_id, b64_nonce, b64_ciphertext] => (Some(key_id), b64_nonce, b64_ciphertext),
+            _ => return Err(DecryptError::MalformedHashDataString),
+        };
         let nonce = general_purpose::STANDARD_NO_PAD
-            .decode(cipher_components[0])
+            .decode(b64_nonce)
             .map_err(|_| DecryptError::MalformedB64Nonce)?;
-        let nonce = GenericArray::clone_from_slice(&nonce[..]);
+        if nonce.len() != Algorithm::Aes256Gcm.nonce_len() {
+            return Err(DecryptError::MalformedB64Nonce);
+        }
         let ciphertext = general_purpose::STANDARD_NO_PAD
-            .decode(cipher_components[1])
+            .decode(b64_ciphertext)
             .map_err(|_| DecryptError::MalformedB64Ciphertext)?;
-        let plaintext = cipher
-            .decrypt(&nonce, ciphertext.as_ref())
-            .map_err(|_| DecryptError::DecryptionError)?;
+
+        let plaintext = decrypt(
+            keyring,
+            Algorithm::Aes256Gcm,
+            key_id,
+            &nonce,
+            &ciphertext,
+            b"",
+        )?;
         let plaintext =
             String::from_utf8(plaintext).map_err(|_| DecryptError::Utf8DecodingError)?;
 
@@ -66,16 +128,163 @@ impl HashData {
         Ok(hash_data)
     }
 
-    pub fn encrypt(&self, key: &[u8]) -> String {
-        let key = Key::<Aes256Gcm>::from_slice(key);
-        let cipher = Aes256Gcm::new(&key);
-        let nonce = Aes256Gcm::generate_nonce(&mut OsRng); // 96-bits; unique per message
-        let ciphertext = cipher
-            .encrypt(&nonce, serde_json::to_string(&self).unwrap().as_bytes())
-            .unwrap();
+    pub fn encrypt(&self, key: &SecretKey) -> String {
+        self.encrypt_with(&Keyring::from(key.clone()))
+    }
+
+    /// Encrypts with the active key and the algorithm of the keyring, in the keyring's
+    /// envelope.
+    pub fn encrypt_with(&self, keyring: &Keyring) -> String {
+        if keyring.envelope() == Envelope::V2 || keyring.algorithm() != Algorithm::Aes256Gcm {
+            return v2::seal(self, keyring);
+        }
+        let active = keyring.active();
+        let (nonce, ciphertext) = Algorithm::Aes256Gcm.encrypt(
+            &active.key,
+            serde_json::to_string(&self).unwrap().as_bytes().into(),
+        );
 
         let b64_cipher: String = general_purpose::STANDARD_NO_PAD.encode(ciphertext);
         let b64_nonce: String = general_purpose::STANDARD_NO_PAD.encode(nonce);
-        b64_nonce + "." + &b64_cipher
+        match &active.id {
+            Some(key_id) => format!("{}.{}.{}", key_id, b64_nonce, b64_cipher),
+            None => b64_nonce + "." + &b64_cipher,
+        }
+    }
+}
+
+/// Decrypts with the first candidate key of `key_id` that authenticates the ciphertext.
+fn decrypt(
+    keyring: &Keyring,
+    algorithm: Algorithm,
+    key_id: Option<&str>,
+    nonce: &[u8],
+    ciphertext: &[u8],
+    aad: &[u8],
+) -> Result<Vec<u8>, DecryptError> {
+    let keys = keyring.candidates(key_id);
+    if keys.is_empty() {
+        return Err(DecryptError::UnknownKeyId);
+    }
+    keys.into_iter()
+        .find_map(|key| {
+            algorithm.decrypt(
+                key,
+                nonce,
+                Payload {
+                    msg: ciphertext,
+                    aad,
+                },
+            )
+        })
+        .ok_or(DecryptError::DecryptionError)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OLD_KEY: &[u8] = b"QJUHsPhnA0eiqHuJqsPgzhDozYO4f1zh";
+    const NEW_KEY: &[u8] = b"tX0nB1xGmQ3r9Zk7LcVwPa5sYe2HdJ8u";
+
+    fn hash_data() -> HashData {
+        HashData {
+            notification_type: Some(NotificationType::Stabilized),
+            researcher: "d.landau@uu.nl".into(),
+            experiment_id: "5678".into(),
+            measurement_id: "1234".into(),
+            timestamp: 1692029115.4314,
+            issued_at: None,
+            expires_at: None,
+        }
+    }
+
+    #[test]
+    fn rotated_keyring_decrypts_in_flight_ciphers() {
+        let old = Keyring::with_id("old", OLD_KEY).unwrap();
+        let in_flight = hash_data().encrypt_with(&old);
+        assert!(in_flight.starts_with("old."));
+        let legacy = hash_data().encrypt(&SecretKey::from_bytes(OLD_KEY).unwrap());
+
+        let mut rotated = old.clone();
+        rotated.add("new", NEW_KEY).unwrap();
+        rotated.set_active("new").unwrap();
+        let cipher = hash_data().encrypt_with(&rotated);
+        assert!(cipher.starts_with("new."));
+
+        for cipher in [in_flight, legacy, cipher.clone()] {
+            let decrypted = HashData::decrypt_with(&rotated, &cipher, "5678", "1234").unwrap();
+            assert_eq!(decrypted.measurement_id, "1234");
+        }
+        assert!(matches!(
+            HashData::decrypt_with(&old, &cipher, "5678", "1234"),
+            Err(DecryptError::UnknownKeyId)
+        ));
+    }
+
+    #[test]
+    fn expiry_is_encrypted() {
+        let mut keyring = Keyring::new(OLD_KEY).unwrap();
+        keyring.set_ttl(Some(std::time::Duration::from_secs(60)));
+        let issued_at = 1692029115.5;
+        let issued = HashData {
+            issued_at: Some(issued_at),
+            expires_at: keyring.expires_at(issued_at),
+            ..hash_data()
+        };
+
+        for envelope in [Envelope::V1, Envelope::V2] {
+            keyring.set_envelope(envelope);
+            let cipher = issued.encrypt_with(&keyring);
+            let decrypted = HashData::decrypt_with(&keyring, &cipher, "5678", "1234").unwrap();
+            assert_eq!(decrypted.expires_at, Some(issued_at + 60.0));
+            assert!(!decrypted.is_expired(issued_at + 60.0));
+            assert!(decrypted.is_expired(issued_at + 61.0));
+        }
+        assert!(!hash_data().is_expired(f64::MAX));
+    }
+
+    #[test]
+    fn keyring_file() {
+        let keyring = Keyring::from_json(
+            r#"{"active": "b", "keys": [
+                {"id": "a", "key": "QJUHsPhnA0eiqHuJqsPgzhDozYO4f1zh"},
+                {"id": "b", "key": "tX0nB1xGmQ3r9Zk7LcVwPa5sYe2HdJ8u"}
+            ]}"#,
+        )
+        .unwrap();
+        assert_eq!(keyring.active_key_id(), Some("b"));
+
+        let invalid =
+            Keyring::from_json(r#"{"active": "a", "keys": [{"id": "a", "key": "short"}]}"#);
+        assert_eq!(
+            invalid.err(),
+            Some(KeyringError::InvalidKeyLength(Some("a".into())))
+        );
+        let unknown = Keyring::from_json(
+            r#"{"active": "c", "keys": [{"id": "a", "key": "QJUHsPhnA0eiqHuJqsPgzhDozYO4f1zh"}]}"#,
+        );
+        assert_eq!(
+            unknown.err(),
+            Some(KeyringError::UnknownActiveKey("c".into()))
+        );
+    }
+
+    #[test]
+    fn envelope_tags_are_not_key_ids() {
+        for key_id in ["v1", "v2", "v10"] {
+            assert_eq!(
+                Keyring::with_id(key_id, OLD_KEY).err(),
+                Some(KeyringError::ReservedKeyId(key_id.into()))
+            );
+        }
+        let mut keyring = Keyring::new(OLD_KEY).unwrap();
+        assert_eq!(
+            keyring.add("v2", NEW_KEY),
+            Err(KeyringError::ReservedKeyId("v2".into()))
+        );
+        for key_id in ["v", "va", "2023-10"] {
+            assert!(Keyring::with_id(key_id, OLD_KEY).is_ok());
+        }
     }
 }
@@ -0,0 +1,480 @@
+use clap::{command, value_parser, Arg, ArgAction, ArgMatches, Command};
+use event_hash::{
+    Algorithm, DecryptError, Envelope, HashData, Keyring, NotificationType, SecretKey,
+    ValidationError,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::File,
+    io::{self, BufRead, BufReader, Read, Write},
+    process,
+    str::FromStr,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Exit code of an unreadable or invalid input, e.g. a `HashData` that is not valid JSON.
+/// Usage errors exit with 2.
+const EXIT_INVALID_INPUT: i32 = 1;
+const EXIT_VALIDATION_ERROR: i32 = 20;
+const EXIT_EXPIRED: i32 = 21;
+
+/// Exit code of each `DecryptError`.
+fn decrypt_exit_code(e: &DecryptError) -> i32 {
+    match e {
+        DecryptError::MalformedHashDataString => 10,
+        DecryptError::MalformedB64Nonce => 11,
+        DecryptError::MalformedB64Ciphertext => 12,
+        DecryptError::DecryptionError => 13,
+        DecryptError::Utf8DecodingError => 14,
+        DecryptError::JsonDeserializationError => 15,
+        DecryptError::UnknownKeyId => 16,
+        DecryptError::MalformedBinaryPayload => 17,
+        DecryptError::UnknownAlgorithm => 18,
+    }
+}
+
+enum Failure {
+    Decrypt(DecryptError),
+    Validation(ValidationError),
+    Expired(f64),
+    InvalidInput(String),
+}
+
+impl Failure {
+    fn exit_code(&self) -> i32 {
+        match self {
+            Failure::Decrypt(e) => decrypt_exit_code(e),
+            Failure::Validation(_) => EXIT_VALIDATION_ERROR,
+            Failure::Expired(_) => EXIT_EXPIRED,
+            Failure::InvalidInput(_) => EXIT_INVALID_INPUT,
+        }
+    }
+}
+
+impl std::fmt::Display for Failure {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Failure::Decrypt(e) => write!(f, "{}", e),
+            Failure::Validation(e) => write!(f, "{}", e),
+            Failure::Expired(expires_at) => write!(f, "Expired at {}", expires_at),
+            Failure::InvalidInput(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+/// A line of the batch file: the body of a `/api/notify` request. Lines with the
+/// researcher and notification type are validated, the others only decrypted.
+#[derive(Deserialize)]
+struct BatchEntry {
+    cipher_data: String,
+    experiment_id: Option<String>,
+    measurement_id: Option<String>,
+    researcher: Option<String>,
+    notification_type: Option<NotificationType>,
+}
+
+#[derive(Serialize)]
+struct BatchResult {
+    line: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    hash_data: Option<HashData>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    exit_code: i32,
+}
+
+fn main() {
+    let mut matches = cli().get_matches();
+    let keyring = load_keyring(&matches);
+
+    let result = match matches.remove_subcommand().expect("required") {
+        (name, mut matches) if name == "decrypt" => decrypt(&keyring, &mut matches),
+        (name, mut matches) if name == "encrypt" => encrypt(keyring, &mut matches),
+        (name, mut matches) if name == "validate" => validate(&keyring, &mut matches),
+        (name, mut matches) if name == "batch" => {
+            batch(&keyring, &mut matches, &mut io::stdout().lock())
+        }
+        (name, _) => unreachable!("unknown subcommand `{}`", name),
+    };
+    if let Err(failure) = result {
+        eprintln!("{}", failure);
+        process::exit(failure.exit_code());
+    }
+}
+
+/// The keyring of `--keyring-file`, or else the single `--secret-key`.
+fn load_keyring(matches: &ArgMatches) -> Keyring {
+    match matches.get_one::<String>("keyring-file") {
+        Some(keyring_file) => Keyring::from_file(keyring_file).unwrap_or_else(|err| {
+            eprintln!("{}", err);
+            process::exit(EXIT_INVALID_INPUT);
+        }),
+        None => Keyring::from(
+            matches
+                .get_one::<SecretKey>("secret-key")
+                .expect("required")
+                .clone(),
+        ),
+    }
+}
+
+fn current_epoch() -> f64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("Time went backwards")
+        .as_secs_f64()
+}
+
+fn decrypt_cipher(
+    keyring: &Keyring,
+    cipher: &str,
+    experiment_id: &str,
+    measurement_id: &str,
+) -> Result<HashData, Failure> {
+    HashData::decrypt_with(keyring, cipher, experiment_id, measurement_id).map_err(Failure::Decrypt)
+}
+
+/// Validates like the notifications-service, including the expiry.
+fn validate_cipher(
+    keyring: &Keyring,
+    cipher: &str,
+    researcher: &str,
+    experiment_id: &str,
+    measurement_id: &str,
+    notification_type: &NotificationType,
+) -> Result<HashData, Failure> {
+    let hash_data = decrypt_cipher(keyring, cipher, experiment_id, measurement_id)?;
+    hash_data
+        .validate(researcher, experiment_id, measurement_id, notification_type)
+        .map_err(Failure::Validation)?;
+    if hash_data.is_expired(current_epoch()) {
+        return Err(Failure::Expired(
+            hash_data.expires_at.expect("expired hashes have an expiry"),
+        ));
+    }
+    Ok(hash_data)
+}
+
+fn print_hash_data(hash_data: &HashData) {
+    println!(
+        "{}",
+        serde_json::to_string_pretty(hash_data).expect("Serializable HashData")
+    );
+}
+
+fn decrypt(keyring: &Keyring, matches: &mut ArgMatches) -> Result<(), Failure> {
+    let hash_data = decrypt_cipher(
+        keyring,
+        &matches.remove_one::<String>("cipher").expect("required"),
+        &matches
+            .remove_one::<String>("experiment-id")
+            .unwrap_or_default(),
+        &matches
+            .remove_one::<String>("measurement-id")
+            .unwrap_or_default(),
+    )?;
+    print_hash_data(&hash_data);
+    Ok(())
+}
+
+fn encrypt(mut keyring: Keyring, matches: &mut ArgMatches) -> Result<(), Failure> {
+    let json = match matches.remove_one::<String>("hash-data") {
+        Some(json) => json,
+        None => {
+            let mut json = String::new();
+            io::stdin()
+                .read_to_string(&mut json)
+                .map_err(|err| Failure::InvalidInput(err.to_string()))?;
+            json
+        }
+    };
+    let hash_data: HashData = serde_json::from_str(&json)
+        .map_err(|err| Failure::InvalidInput(format!("Invalid HashData: {}", err)))?;
+    if matches.remove_one::<String>("envelope").as_deref() == Some("v2") {
+        keyring.set_envelope(Envelope::V2);
+    }
+    keyring.set_algorithm(
+        matches
+            .remove_one::<Algorithm>("algorithm")
+            .expect("required"),
+    );
+    println!("{}", hash_data.encrypt_with(&keyring));
+    Ok(())
+}
+
+fn validate(keyring: &Keyring, matches: &mut ArgMatches) -> Result<(), Failure> {
+    let notification_type = match matches.remove_one::<String>("notification-type").as_deref() {
+        Some("OutOfRange") => NotificationType::OutOfRange,
+        Some("Stabilized") => NotificationType::Stabilized,
+        _ => unreachable!("clap only accepts the notification types"),
+    };
+    let hash_data = validate_cipher(
+        keyring,
+        &matches.remove_one::<String>("cipher").expect("required"),
+        &matches
+            .remove_one::<String>("researcher")
+            .expect("required"),
+        &matches
+            .remove_one::<String>("experiment-id")
+            .expect("required"),
+        &matches
+            .remove_one::<String>("measurement-id")
+            .expect("required"),
+        &notification_type,
+    )?;
+    print_hash_data(&hash_data);
+    Ok(())
+}
+
+/// Writes a `BatchResult` JSON line per input line to `out`, and fails with the first
+/// failure.
+fn batch(keyring: &Keyring, matches: &mut ArgMatches, out: &mut impl Write) -> Result<(), Failure> {
+    let file = matches.remove_one::<String>("file").expect("required");
+    let reader: Box<dyn BufRead> = match file.as_str() {
+        "-" => Box::new(BufReader::new(io::stdin())),
+        file => Box::new(BufReader::new(File::open(file).map_err(|err| {
+            Failure::InvalidInput(format!("Could not read file `{}`: {}", file, err))
+        })?)),
+    };
+
+    let mut first_failure = None;
+    for (i, line) in reader.lines().enumerate() {
+        let line = line.map_err(|err| Failure::InvalidInput(err.to_string()))?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let result = serde_json::from_str::<BatchEntry>(&line)
+            .map_err(|err| Failure::InvalidInput(format!("Invalid line: {}", err)))
+            .and_then(|entry| batch_entry(keyring, entry));
+        let result = match result {
+            Ok(hash_data) => BatchResult {
+                line: i + 1,
+                hash_data: Some(hash_data),
+                error: None,
+                exit_code: 0,
+            },
+            Err(failure) => {
+                let result = BatchResult {
+                    line: i + 1,
+                    hash_data: None,
+                    error: Some(failure.to_string()),
+                    exit_code: failure.exit_code(),
+                };
+                first_failure.get_or_insert(failure);
+                result
+            }
+        };
+        writeln!(
+            out,
+            "{}",
+            serde_json::to_string(&result).expect("Serializable BatchResult")
+        )
+        .map_err(|err| Failure::InvalidInput(err.to_string()))?;
+    }
+    match first_failure {
+        Some(failure) => Err(failure),
+        None => Ok(()),
+    }
+}
+
+fn batch_entry(keyring: &Keyring, entry: BatchEntry) -> Result<HashData, Failure> {
+    let experiment_id = entry.experiment_id.unwrap_or_default();
+    let measurement_id = entry.measurement_id.unwrap_or_default();
+    match (entry.researcher, entry.notification_type) {
+        (Some(researcher), Some(notification_type)) => validate_cipher(
+            keyring,
+            &entry.cipher_data,
+            &researcher,
+            &experiment_id,
+            &measurement_id,
+            &notification_type,
+        ),
+        _ => decrypt_cipher(keyring, &entry.cipher_data, &experiment_id, &measurement_id),
+    }
+}
+
+fn cli() -> Command {
+    let experiment_id = Arg::new("experiment-id")
+        .long("experiment-id")
+        .action(ArgAction::Set);
+    let measurement_id = Arg::new("measurement-id")
+        .long("measurement-id")
+        .action(ArgAction::Set);
+
+    command!() // requires `cargo` feature
+        .next_line_help(true)
+        .about("Inspects measurement hashes. Exits with 1 on invalid input, 10 to 18 on a DecryptError (in declaration order), 20 on a validation error and 21 on an expired hash")
+        .subcommand_required(true)
+        .arg(Arg::new("secret-key")
+            .required(false)
+            .long("secret-key")
+            .global(true)
+            .action(ArgAction::Set)
+            .default_value("QJUHsPhnA0eiqHuJqsPgzhDozYO4f1zh")
+            .value_parser(SecretKey::from_str)
+            .help("<key> is a 32 character string, or `hex:<key>`, `base64:<key>`, `file:<path>` or `passphrase:<passphrase>`, that must match the key of the experiment-producer")
+        )
+        .arg(Arg::new("keyring-file")
+            .required(false)
+            .long("keyring-file")
+            .global(true)
+            .action(ArgAction::Set)
+            .help("JSON keyring used instead of --secret-key")
+        )
+        .subcommand(Command::new("decrypt")
+            .about("Decrypts a cipher and prints its HashData")
+            .arg(Arg::new("cipher").required(true).action(ArgAction::Set))
+            .arg(experiment_id.clone().help("Experiment ID the cipher is bound to. Required by v2 ciphers"))
+            .arg(measurement_id.clone().help("Measurement ID the cipher is bound to. Required by v2 ciphers"))
+        )
+        .subcommand(Command::new("encrypt")
+            .about("Encrypts a HashData, e.g. `{\"notification_type\": \"OutOfRange\", \"researcher\": \"d.landau@uu.nl\", \"experiment_id\": \"5678\", \"measurement_id\": \"1234\", \"timestamp\": 1692029115.4314}`")
+            .arg(Arg::new("hash-data")
+                .required(false)
+                .action(ArgAction::Set)
+                .help("HashData JSON. Read from standard input when not set")
+            )
+            .arg(Arg::new("envelope")
+                .required(false)
+                .long("envelope")
+                .default_value("v1")
+                .action(ArgAction::Set)
+                .value_parser(["v1", "v2"])
+            )
+            .arg(Arg::new("algorithm")
+                .required(false)
+                .long("algorithm")
+                .default_value("aes-256-gcm")
+                .action(ArgAction::Set)
+                .value_parser(value_parser!(Algorithm))
+                .help("The ChaCha20 algorithms imply `--envelope v2`")
+            )
+        )
+        .subcommand(Command::new("validate")
+            .about("Checks a notification against its cipher like the notifications-service")
+            .arg(Arg::new("cipher").required(true).action(ArgAction::Set))
+            .arg(Arg::new("researcher")
+                .required(true)
+                .long("researcher")
+                .action(ArgAction::Set)
+            )
+            .arg(experiment_id.required(true))
+            .arg(measurement_id.required(true))
+            .arg(Arg::new("notification-type")
+                .required(true)
+                .long("notification-type")
+                .action(ArgAction::Set)
+                .value_parser(["OutOfRange", "Stabilized"])
+            )
+        )
+        .subcommand(Command::new("batch")
+            .about("Decrypts the cipher_data of each line of a JSONL file of /api/notify bodies, validating the lines with a researcher and notification_type")
+            .arg(Arg::new("file")
+                .required(true)
+                .action(ArgAction::Set)
+                .help("JSONL file, or `-` for standard input")
+            )
+        )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: &[u8] = b"QJUHsPhnA0eiqHuJqsPgzhDozYO4f1zh";
+
+    #[test]
+    fn exit_codes() {
+        let decrypt_errors = [
+            DecryptError::MalformedHashDataString,
+            DecryptError::MalformedB64Nonce,
+            DecryptError::MalformedB64Ciphertext,
+            DecryptError::DecryptionError,
+            DecryptError::Utf8DecodingError,
+            DecryptError::JsonDeserializationError,
+            DecryptError::UnknownKeyId,
+            DecryptError::MalformedBinaryPayload,
+            DecryptError::UnknownAlgorithm,
+        ];
+        for (e, exit_code) in decrypt_errors.into_iter().zip(10..) {
+            assert_eq!(Failure::Decrypt(e).exit_code(), exit_code);
+        }
+        assert_eq!(Failure::InvalidInput("".into()).exit_code(), 1);
+        let validation =
+            Failure::Validation(ValidationError::UnexpectedNotification("1234".into()));
+        assert_eq!(validation.exit_code(), 20);
+        assert_eq!(Failure::Expired(0.0).exit_code(), 21);
+    }
+
+    #[test]
+    fn algorithm_names() {
+        for (name, algorithm) in [
+            ("aes-256-gcm", Algorithm::Aes256Gcm),
+            ("chacha20-poly1305", Algorithm::ChaCha20Poly1305),
+            ("xchacha20-poly1305", Algorithm::XChaCha20Poly1305),
+        ] {
+            let mut matches = cli()
+                .try_get_matches_from(["event-hash", "encrypt", "--algorithm", name])
+                .unwrap();
+            let (_, matches) = matches.remove_subcommand().unwrap();
+            assert_eq!(matches.get_one::<Algorithm>("algorithm"), Some(&algorithm));
+        }
+    }
+
+    #[test]
+    fn batch_reports_each_line() {
+        let keyring = Keyring::new(KEY).unwrap();
+        let cipher = HashData {
+            notification_type: Some(NotificationType::Stabilized),
+            researcher: "d.landau@uu.nl".into(),
+            experiment_id: "5678".into(),
+            measurement_id: "1234".into(),
+            timestamp: 1692029115.4314,
+            issued_at: None,
+            expires_at: None,
+        }
+        .encrypt_with(&keyring);
+        let file = std::env::temp_dir().join(format!("event-hash-batch-{}.jsonl", process::id()));
+        std::fs::write(
+            &file,
+            [
+                serde_json::json!({"cipher_data": cipher}).to_string(),
+                String::new(),
+                serde_json::json!({
+                    "cipher_data": cipher,
+                    "experiment_id": "5678",
+                    "measurement_id": "1234",
+                    "researcher": "someone@uu.nl",
+                    "notification_type": "Stabilized",
+                })
+                .to_string(),
+                serde_json::json!({"cipher_data": "not a cipher"}).to_string(),
+                "not json".into(),
+            ]
+            .join("\n"),
+        )
+        .unwrap();
+
+        let mut matches = cli()
+            .try_get_matches_from(["event-hash", "batch", file.to_str().unwrap()])
+            .unwrap();
+        let (_, mut matches) = matches.remove_subcommand().unwrap();
+        let mut out = Vec::new();
+        let failure = batch(&keyring, &mut matches, &mut out).unwrap_err();
+        std::fs::remove_file(&file).unwrap();
+
+        assert_eq!(failure.exit_code(), 20);
+        let results: Vec<(usize, i32)> = String::from_utf8(out)
+            .unwrap()
+            .lines()
+            .map(|line| {
+                let result: serde_json::Value = serde_json::from_str(line).unwrap();
+                (
+                    result["line"].as_u64().unwrap() as usize,
+                    result["exit_code"].as_i64().unwrap() as i32,
+                )
+            })
+            .collect();
+        assert_eq!(results, [(1, 0), (3, 20), (4, 10), (5, 1)]);
+    }
+}
@@ -0,0 +1,176 @@
+use argon2::Argon2;
+use base64::{engine::general_purpose, Engine as _};
+use std::{fs, str::FromStr};
+use zeroize::{Zeroize, ZeroizeOnDrop, Zeroizing};
+
+/// Salt of the keys derived from a `passphrase:`. Every service derives the same key from
+/// the same passphrase, so the salt cannot be random.
+const PASSPHRASE_SALT: &[u8] = b"event-hash/measurement-hash";
+
+/// 256-bit key of the measurement hashes, zeroized on drop.
+///
+/// Parsed from a 32 character string, or from `hex:<64 hex digits>`, `base64:<32 bytes>`,
+/// `file:<path>` (a file holding any of the former) or `passphrase:<passphrase>` (derived
+/// with Argon2id).
+#[derive(Clone, Zeroize, ZeroizeOnDrop)]
+pub struct SecretKey([u8; SecretKey::LEN]);
+
+/// Never shows the key.
+impl std::fmt::Debug for SecretKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str("SecretKey(..)")
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum SecretKeyError {
+    InvalidLength(usize),
+    InvalidHex,
+    InvalidBase64,
+    ReadError(String),
+    KeyDerivationError(String),
+}
+
+impl std::error::Error for SecretKeyError {}
+
+impl std::fmt::Display for SecretKeyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            SecretKeyError::InvalidLength(len) => {
+                write!(f, "Key is {} bytes instead of {}", len, SecretKey::LEN)
+            }
+            SecretKeyError::InvalidHex => write!(f, "Key is not valid hex"),
+            SecretKeyError::InvalidBase64 => write!(f, "Key is not valid base64"),
+            SecretKeyError::ReadError(err) => write!(f, "Could not read key: {}", err),
+            SecretKeyError::KeyDerivationError(err) => {
+                write!(f, "Could not derive key from passphrase: {}", err)
+            }
+        }
+    }
+}
+
+impl SecretKey {
+    pub const LEN: usize = 32;
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, SecretKeyError> {
+        let key: [u8; Self::LEN] = bytes
+            .try_into()
+            .map_err(|_| SecretKeyError::InvalidLength(bytes.len()))?;
+        Ok(Self(key))
+    }
+
+    pub fn from_hex(hex: &str) -> Result<Self, SecretKeyError> {
+        let bytes = Zeroizing::new(hex::decode(hex).map_err(|_| SecretKeyError::InvalidHex)?);
+        Self::from_bytes(&bytes)
+    }
+
+    /// Standard base64, with or without padding.
+    pub fn from_base64(b64: &str) -> Result<Self, SecretKeyError> {
+        let bytes = Zeroizing::new(
+            general_purpose::STANDARD_NO_PAD
+                .decode(b64.trim_end_matches('='))
+                .map_err(|_| SecretKeyError::InvalidBase64)?,
+        );
+        Self::from_bytes(&bytes)
+    }
+
+    /// Reads a key in any format but `file:`, ignoring the trailing newline.
+    pub fn from_file(file_path: &str) -> Result<Self, SecretKeyError> {
+        let contents = Zeroizing::new(
+            fs::read_to_string(file_path)
+                .map_err(|err| SecretKeyError::ReadError(format!("`{}`: {}", file_path, err)))?,
+        );
+        let contents = contents.trim_end_matches(['\r', '\n']);
+        if contents.starts_with("file:") {
+            return Err(SecretKeyError::ReadError(format!(
+                "`{}` refers to another key file",
+                file_path
+            )));
+        }
+        contents.parse()
+    }
+
+    /// Derives the key with Argon2id. `salt` is at least 8 bytes.
+    pub fn from_passphrase(passphrase: &str, salt: &[u8]) -> Result<Self, SecretKeyError> {
+        let mut key = Self([0; Self::LEN]);
+        Argon2::default()
+            .hash_password_into(passphrase.as_bytes(), salt, &mut key.0)
+            .map_err(|err| SecretKeyError::KeyDerivationError(err.to_string()))?;
+        Ok(key)
+    }
+
+    pub(crate) fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl FromStr for SecretKey {
+    type Err = SecretKeyError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(hex) = s.strip_prefix("hex:") {
+            Self::from_hex(hex)
+        } else if let Some(b64) = s.strip_prefix("base64:") {
+            Self::from_base64(b64)
+        } else if let Some(file_path) = s.strip_prefix("file:") {
+            Self::from_file(file_path)
+        } else if let Some(passphrase) = s.strip_prefix("passphrase:") {
+            Self::from_passphrase(passphrase, PASSPHRASE_SALT)
+        } else {
+            Self::from_bytes(s.as_bytes())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: &str = "QJUHsPhnA0eiqHuJqsPgzhDozYO4f1zh";
+
+    #[test]
+    fn formats_parse_to_the_same_key() {
+        let raw: SecretKey = KEY.parse().unwrap();
+        let hex: SecretKey = format!("hex:{}", hex::encode(KEY)).parse().unwrap();
+        let b64: SecretKey = format!("base64:{}", general_purpose::STANDARD.encode(KEY))
+            .parse()
+            .unwrap();
+        assert_eq!(raw.0, hex.0);
+        assert_eq!(raw.0, b64.0);
+
+        let file_path = std::env::temp_dir().join("event-hash-secret-key");
+        fs::write(&file_path, format!("hex:{}\n", hex::encode(KEY))).unwrap();
+        let file: SecretKey = format!("file:{}", file_path.display()).parse().unwrap();
+        fs::remove_file(&file_path).unwrap();
+        assert_eq!(raw.0, file.0);
+
+        let passphrase: SecretKey = "passphrase:correct horse battery staple".parse().unwrap();
+        let again: SecretKey = "passphrase:correct horse battery staple".parse().unwrap();
+        assert_eq!(passphrase.0, again.0);
+        assert_ne!(passphrase.0, raw.0);
+    }
+
+    #[test]
+    fn invalid_keys_are_errors() {
+        assert_eq!(
+            "short".parse::<SecretKey>().err(),
+            Some(SecretKeyError::InvalidLength(5))
+        );
+        assert_eq!(
+            "hex:zz".parse::<SecretKey>().err(),
+            Some(SecretKeyError::InvalidHex)
+        );
+        assert_eq!(
+            "base64:!".parse::<SecretKey>().err(),
+            Some(SecretKeyError::InvalidBase64)
+        );
+        assert!(matches!(
+            "file:/nonexistent/key".parse::<SecretKey>(),
+            Err(SecretKeyError::ReadError(_))
+        ));
+        assert!(matches!(
+            SecretKey::from_passphrase("passphrase", b"salt"),
+            Err(SecretKeyError::KeyDerivationError(_))
+        ));
+    }
+}
@@ -0,0 +1,218 @@
+use aes_gcm::aead::Payload;
+use base64::{engine::general_purpose, Engine as _};
+
+use crate::{Algorithm, DecryptError, HashData, Keyring, NotificationType};
+
+pub(crate) const PREFIX: &str = "v2.";
+
+/// Binds the ciphertext to the record it travels in. The length prefix keeps
+/// (`ab`, `c`) and (`a`, `bc`) apart.
+fn associated_data(experiment_id: &str, measurement_id: &str) -> Vec<u8> {
+    let mut aad = (experiment_id.len() as u32).to_le_bytes().to_vec();
+    aad.extend_from_slice(experiment_id.as_bytes());
+    aad.extend_from_slice(measurement_id.as_bytes());
+    aad
+}
+
+/// `None` is NaN, which is never a timestamp.
+fn encode_time(time: Option<f64>) -> [u8; 8] {
+    time.unwrap_or(f64::NAN).to_le_bytes()
+}
+
+fn decode_time(bytes: &[u8]) -> Option<f64> {
+    let time = f64::from_le_bytes(bytes.try_into().expect("8 bytes"));
+    (!time.is_nan()).then_some(time)
+}
+
+const TIMES_END: usize = 25;
+
+/// Notification type (1 byte), timestamp, issued-at and expiry (8 bytes each) and
+/// researcher (the rest). The IDs are the associated data, so they are not encrypted.
+fn encode_payload(hash_data: &HashData) -> Vec<u8> {
+    let notification_type = match hash_data.notification_type {
+        None => 0,
+        Some(NotificationType::OutOfRange) => 1,
+        Some(NotificationType::Stabilized) => 2,
+    };
+    let mut payload = vec![notification_type];
+    payload.extend_from_slice(&hash_data.timestamp.to_le_bytes());
+    payload.extend_from_slice(&encode_time(hash_data.issued_at));
+    payload.extend_from_slice(&encode_time(hash_data.expires_at));
+    payload.extend_from_slice(hash_data.researcher.as_bytes());
+    payload
+}
+
+fn decode_payload(
+    payload: &[u8],
+    experiment_id: &str,
+    measurement_id: &str,
+) -> Result<HashData, DecryptError> {
+    if payload.len() < TIMES_END {
+        return Err(DecryptError::MalformedBinaryPayload);
+    }
+    let notification_type = match payload[0] {
+        0 => None,
+        1 => Some(NotificationType::OutOfRange),
+        2 => Some(NotificationType::Stabilized),
+        _ => return Err(DecryptError::MalformedBinaryPayload),
+    };
+    let timestamp = f64::from_le_bytes(payload[1..9].try_into().expect("8 bytes"));
+    let issued_at = decode_time(&payload[9..17]);
+    let expires_at = decode_time(&payload[17..TIMES_END]);
+    let researcher = String::from_utf8(payload[TIMES_END..].to_vec())
+        .map_err(|_| DecryptError::Utf8DecodingError)?;
+    Ok(HashData {
+        notification_type,
+        researcher,
+        experiment_id: experiment_id.into(),
+        measurement_id: measurement_id.into(),
+        timestamp,
+        issued_at,
+        expires_at,
+    })
+}
+
+/// `v2.` and the base64 encoding of the algorithm (1 byte), key ID length (1 byte), key
+/// ID, nonce and ciphertext.
+pub(crate) fn seal(hash_data: &HashData, keyring: &Keyring) -> String {
+    let active = keyring.active();
+    let algorithm = keyring.algorithm();
+    let aad = associated_data(&hash_data.experiment_id, &hash_data.measurement_id);
+    let (nonce, ciphertext) = algorithm.encrypt(
+        &active.key,
+        Payload {
+            msg: &encode_payload(hash_data),
+            aad: &aad,
+        },
+    );
+
+    let key_id = active.id.as_deref().unwrap_or_default();
+    let mut envelope = vec![algorithm.id(), key_id.len() as u8];
+    envelope.extend_from_slice(key_id.as_bytes());
+    envelope.extend_from_slice(&nonce);
+    envelope.extend_from_slice(&ciphertext);
+    format!(
+        "{}{}",
+        PREFIX,
+        general_purpose::STANDARD_NO_PAD.encode(envelope)
+    )
+}
+
+pub(crate) fn open(
+    keyring: &Keyring,
+    envelope: &str,
+    experiment_id: &str,
+    measurement_id: &str,
+) -> Result<HashData, DecryptError> {
+    let envelope = general_purpose::STANDARD_NO_PAD
+        .decode(envelope)
+        .map_err(|_| DecryptError::MalformedB64Ciphertext)?;
+    let (algorithm, key_id_len, rest) = match &envelope[..] {
+        [algorithm, key_id_len, rest @ ..] => (*algorithm, usize::from(*key_id_len), rest),
+        _ => return Err(DecryptError::MalformedHashDataString),
+    };
+    let algorithm = Algorithm::from_id(algorithm).ok_or(DecryptError::UnknownAlgorithm)?;
+    if rest.len() < key_id_len + algorithm.nonce_len() {
+        return Err(DecryptError::MalformedHashDataString);
+    }
+    let (key_id, rest) = rest.split_at(key_id_len);
+    let (nonce, ciphertext) = rest.split_at(algorithm.nonce_len());
+    let key_id = match key_id {
+        [] => None,
+        key_id => Some(std::str::from_utf8(key_id).map_err(|_| DecryptError::UnknownKeyId)?),
+    };
+
+    let aad = associated_data(experiment_id, measurement_id);
+    let payload = crate::decrypt(keyring, algorithm, key_id, nonce, ciphertext, &aad)?;
+    decode_payload(&payload, experiment_id, measurement_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SecretKey;
+
+    const KEY: &[u8] = b"QJUHsPhnA0eiqHuJqsPgzhDozYO4f1zh";
+
+    fn hash_data() -> HashData {
+        HashData {
+            notification_type: Some(NotificationType::OutOfRange),
+            researcher: "d.landau@uu.nl".into(),
+            experiment_id: "5678".into(),
+            measurement_id: "1234".into(),
+            timestamp: 1692029115.4314,
+            issued_at: Some(1692029115.5),
+            expires_at: None,
+        }
+    }
+
+    #[test]
+    fn v2_is_bound_to_its_record() {
+        let mut keyring = Keyring::with_id("2023-10", KEY).unwrap();
+        keyring.set_envelope(crate::Envelope::V2);
+        let cipher = hash_data().encrypt_with(&keyring);
+        assert!(cipher.starts_with(PREFIX));
+        assert!(cipher.len() < hash_data().encrypt_with(&Keyring::new(KEY).unwrap()).len());
+
+        let decrypted = HashData::decrypt_with(&keyring, &cipher, "5678", "1234").unwrap();
+        assert_eq!(
+            decrypted.notification_type,
+            Some(NotificationType::OutOfRange)
+        );
+        assert_eq!(decrypted.researcher, "d.landau@uu.nl");
+        assert_eq!(decrypted.timestamp, 1692029115.4314);
+        assert_eq!(decrypted.issued_at, Some(1692029115.5));
+        assert_eq!(decrypted.expires_at, None);
+        assert_eq!(
+            (
+                decrypted.experiment_id.as_str(),
+                decrypted.measurement_id.as_str()
+            ),
+            ("5678", "1234")
+        );
+
+        for (experiment_id, measurement_id) in [("5678", "1235"), ("567", "81234")] {
+            assert!(matches!(
+                HashData::decrypt_with(&keyring, &cipher, experiment_id, measurement_id),
+                Err(DecryptError::DecryptionError)
+            ));
+        }
+    }
+
+    #[test]
+    fn decryption_dispatches_on_the_algorithm() {
+        let mut keyring = Keyring::with_id("2023-10", KEY).unwrap();
+        keyring.set_envelope(crate::Envelope::V2);
+        for algorithm in [
+            Algorithm::Aes256Gcm,
+            Algorithm::ChaCha20Poly1305,
+            Algorithm::XChaCha20Poly1305,
+        ] {
+            keyring.set_algorithm(algorithm);
+            let cipher = hash_data().encrypt_with(&keyring);
+            assert!(cipher.starts_with(PREFIX));
+            let decrypted = HashData::decrypt_with(&keyring, &cipher, "5678", "1234").unwrap();
+            assert_eq!(decrypted.researcher, "d.landau@uu.nl");
+        }
+
+        let mut envelope = vec![3, 0];
+        envelope.extend_from_slice(&[0; 28]);
+        let cipher = format!(
+            "{}{}",
+            PREFIX,
+            general_purpose::STANDARD_NO_PAD.encode(envelope)
+        );
+        assert!(matches!(
+            HashData::decrypt_with(&keyring, &cipher, "5678", "1234"),
+            Err(DecryptError::UnknownAlgorithm)
+        ));
+    }
+
+    #[test]
+    fn v1_is_still_decrypted() {
+        let key = SecretKey::from_bytes(KEY).unwrap();
+        let cipher = hash_data().encrypt(&key);
+        let decrypted = HashData::decrypt(&key, &cipher, "ignored", "ignored").unwrap();
+        assert_eq!(decrypted.measurement_id, "1234");
+    }
+}
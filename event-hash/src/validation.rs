@@ -0,0 +1,149 @@
+use crate::{HashData, NotificationType};
+
+/// A notification that does not match the measurement hash it carries.
+#[derive(Debug, PartialEq)]
+pub enum ValidationError {
+    UnexpectedMeasurementId {
+        got: String,
+        expected: String,
+    },
+    UnexpectedExperimentId {
+        got: String,
+        expected: String,
+    },
+    UnexpectedResearcher {
+        got: String,
+        expected: String,
+    },
+    /// The measurement should not have been notified.
+    UnexpectedNotification(String),
+    UnexpectedNotificationType {
+        got: NotificationType,
+        expected: NotificationType,
+    },
+}
+
+impl std::error::Error for ValidationError {}
+
+impl std::fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ValidationError::UnexpectedMeasurementId { got, expected } => write!(
+                f,
+                "Unexpected measurement_id `{}`. Expected `{}`",
+                got, expected
+            ),
+            ValidationError::UnexpectedExperimentId { got, expected } => write!(
+                f,
+                "Unexpected experiment_id `{}`. Expected `{}`",
+                got, expected
+            ),
+            ValidationError::UnexpectedResearcher { got, expected } => write!(
+                f,
+                "Unexpected researcher `{}`. Expected `{}`",
+                got, expected
+            ),
+            ValidationError::UnexpectedNotification(measurement_id) => write!(
+                f,
+                "Unexpected notification. Measurement `{}` should not have been notified",
+                measurement_id
+            ),
+            ValidationError::UnexpectedNotificationType { got, expected } => write!(
+                f,
+                "Unexpected notification_type `{:?}`. Expected `{:?}`",
+                got, expected
+            ),
+        }
+    }
+}
+
+impl HashData {
+    /// Checks a notification of `notification_type` for the measurement of this hash.
+    pub fn validate(
+        &self,
+        researcher: &str,
+        experiment_id: &str,
+        measurement_id: &str,
+        notification_type: &NotificationType,
+    ) -> Result<(), ValidationError> {
+        if self.measurement_id != measurement_id {
+            return Err(ValidationError::UnexpectedMeasurementId {
+                got: measurement_id.into(),
+                expected: self.measurement_id.clone(),
+            });
+        } else if self.experiment_id != experiment_id {
+            return Err(ValidationError::UnexpectedExperimentId {
+                got: experiment_id.into(),
+                expected: self.experiment_id.clone(),
+            });
+        } else if self.researcher != researcher {
+            return Err(ValidationError::UnexpectedResearcher {
+                got: researcher.into(),
+                expected: self.researcher.clone(),
+            });
+        }
+        match &self.notification_type {
+            None => Err(ValidationError::UnexpectedNotification(
+                self.measurement_id.clone(),
+            )),
+            Some(expected) if expected != notification_type => {
+                Err(ValidationError::UnexpectedNotificationType {
+                    got: notification_type.clone(),
+                    expected: expected.clone(),
+                })
+            }
+            Some(_) => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn notification_must_match_its_hash() {
+        let hash_data = HashData {
+            notification_type: Some(NotificationType::OutOfRange),
+            researcher: "d.landau@uu.nl".into(),
+            experiment_id: "5678".into(),
+            measurement_id: "1234".into(),
+            timestamp: 1692029115.4314,
+            issued_at: None,
+            expires_at: None,
+        };
+        let out_of_range = NotificationType::OutOfRange;
+        assert_eq!(
+            hash_data.validate("d.landau@uu.nl", "5678", "1234", &out_of_range),
+            Ok(())
+        );
+        assert_eq!(
+            hash_data.validate("d.landau@uu.nl", "5678", "4321", &out_of_range),
+            Err(ValidationError::UnexpectedMeasurementId {
+                got: "4321".into(),
+                expected: "1234".into()
+            })
+        );
+        assert_eq!(
+            hash_data
+                .validate(
+                    "d.landau@uu.nl",
+                    "5678",
+                    "1234",
+                    &NotificationType::Stabilized
+                )
+                .unwrap_err()
+                .to_string(),
+            "Unexpected notification_type `Stabilized`. Expected `OutOfRange`"
+        );
+
+        let not_notified = HashData {
+            notification_type: None,
+            ..hash_data
+        };
+        assert_eq!(
+            not_notified.validate("d.landau@uu.nl", "5678", "1234", &out_of_range),
+            Err(ValidationError::UnexpectedNotification("1234".into()))
+        );
+    }
+}
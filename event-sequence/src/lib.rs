@@ -0,0 +1,216 @@
+use std::collections::{BTreeMap, HashMap};
+use std::hash::Hash;
+use std::ops::Range;
+
+/// Outcome of checking a received sequence number against the ones seen before on the
+/// same stream.
+#[derive(Debug, PartialEq)]
+pub enum SequenceStatus {
+    /// The sequence number directly follows the previous one.
+    InOrder,
+    /// Sequence numbers `expected..received` were skipped. They are still accepted later
+    /// on, in which case they are reported as `Reordered`.
+    Gap { expected: u64, received: u64 },
+    /// A previously skipped sequence number arrived late.
+    Reordered { sequence: u64 },
+    /// The sequence number was already received.
+    Duplicate { sequence: u64 },
+}
+
+#[derive(Default)]
+struct Stream {
+    next: u64,
+    /// Whether `u64::MAX` was received, after which no sequence number can follow.
+    ended: bool,
+    /// Skipped sequence numbers as ranges `start..end`, keyed by `start`, so that a large
+    /// gap takes no more memory than a small one.
+    missing: BTreeMap<u64, u64>,
+}
+
+impl Stream {
+    /// Moves the next expected sequence number past `sequence`.
+    fn advance_past(&mut self, sequence: u64) {
+        match sequence.checked_add(1) {
+            Some(next) => self.next = next,
+            None => self.ended = true,
+        }
+    }
+
+    /// Removes `sequence` from the skipped ones, splitting its range.
+    fn remove_missing(&mut self, sequence: u64) -> bool {
+        let Some((&start, &end)) = self.missing.range(..=sequence).next_back() else {
+            return false;
+        };
+        if sequence >= end {
+            return false;
+        }
+        self.missing.remove(&start);
+        if start < sequence {
+            self.missing.insert(start, sequence);
+        }
+        if sequence + 1 < end {
+            self.missing.insert(sequence + 1, end);
+        }
+        true
+    }
+}
+
+/// Tracks sequence numbers of independent streams, e.g. per experiment or per
+/// `(experiment, sensor)`. Every stream is expected to start at sequence `0`.
+pub struct SequenceTracker<K> {
+    streams: HashMap<K, Stream>,
+}
+
+impl<K: Hash + Eq> SequenceTracker<K> {
+    pub fn new() -> Self {
+        Self {
+            streams: HashMap::new(),
+        }
+    }
+
+    pub fn check(&mut self, key: K, sequence: u64) -> SequenceStatus {
+        let stream = self.streams.entry(key).or_default();
+        if stream.ended || sequence < stream.next {
+            return if stream.remove_missing(sequence) {
+                SequenceStatus::Reordered { sequence }
+            } else {
+                SequenceStatus::Duplicate { sequence }
+            };
+        }
+        let expected = stream.next;
+        stream.advance_past(sequence);
+        if sequence == expected {
+            SequenceStatus::InOrder
+        } else {
+            stream.missing.insert(expected, sequence);
+            SequenceStatus::Gap {
+                expected,
+                received: sequence,
+            }
+        }
+    }
+
+    /// Ranges of the sequence numbers of `key` skipped so far that have not arrived yet.
+    pub fn missing(&self, key: &K) -> Vec<Range<u64>> {
+        self.streams
+            .get(key)
+            .map(|stream| {
+                stream
+                    .missing
+                    .iter()
+                    .map(|(&start, &end)| start..end)
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Forgets the stream of `key`, e.g. once its experiment has terminated.
+    pub fn remove(&mut self, key: &K) {
+        self.streams.remove(key);
+    }
+
+    /// Forgets the streams whose key does not satisfy `keep`.
+    pub fn retain(&mut self, mut keep: impl FnMut(&K) -> bool) {
+        self.streams.retain(|key, _| keep(key));
+    }
+}
+
+impl<K: Hash + Eq> Default for SequenceTracker<K> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn in_order_and_duplicates() {
+        let mut tracker = SequenceTracker::new();
+        assert_eq!(tracker.check("a", 0), SequenceStatus::InOrder);
+        assert_eq!(tracker.check("b", 0), SequenceStatus::InOrder);
+        assert_eq!(tracker.check("a", 1), SequenceStatus::InOrder);
+        assert_eq!(
+            tracker.check("a", 1),
+            SequenceStatus::Duplicate { sequence: 1 }
+        );
+    }
+
+    #[test]
+    fn gaps_and_late_arrivals() {
+        let mut tracker = SequenceTracker::new();
+        assert_eq!(tracker.check("a", 0), SequenceStatus::InOrder);
+        assert_eq!(
+            tracker.check("a", 3),
+            SequenceStatus::Gap {
+                expected: 1,
+                received: 3
+            }
+        );
+        assert_eq!(tracker.missing(&"a"), vec![1..3]);
+        assert_eq!(
+            tracker.check("a", 2),
+            SequenceStatus::Reordered { sequence: 2 }
+        );
+        assert_eq!(
+            tracker.check("a", 2),
+            SequenceStatus::Duplicate { sequence: 2 }
+        );
+        assert_eq!(tracker.missing(&"a"), vec![1..2]);
+        assert_eq!(tracker.check("a", 4), SequenceStatus::InOrder);
+    }
+
+    #[test]
+    fn large_gaps_are_kept_as_ranges() {
+        let mut tracker = SequenceTracker::new();
+        assert_eq!(
+            tracker.check("a", u64::MAX - 1),
+            SequenceStatus::Gap {
+                expected: 0,
+                received: u64::MAX - 1
+            }
+        );
+        assert_eq!(
+            tracker.check("a", 5),
+            SequenceStatus::Reordered { sequence: 5 }
+        );
+        assert_eq!(tracker.missing(&"a"), vec![0..5, 6..u64::MAX - 1]);
+        assert_eq!(
+            tracker.check("a", 0),
+            SequenceStatus::Reordered { sequence: 0 }
+        );
+        assert_eq!(
+            tracker.check("a", 5),
+            SequenceStatus::Duplicate { sequence: 5 }
+        );
+        assert_eq!(tracker.missing(&"a"), vec![1..5, 6..u64::MAX - 1]);
+
+        tracker.remove(&"a");
+        assert!(tracker.missing(&"a").is_empty());
+        assert_eq!(tracker.check("a", 0), SequenceStatus::InOrder);
+    }
+
+    #[test]
+    fn last_sequence_ends_the_stream() {
+        let mut tracker = SequenceTracker::new();
+        assert_eq!(
+            tracker.check("a", u64::MAX),
+            SequenceStatus::Gap {
+                expected: 0,
+                received: u64::MAX
+            }
+        );
+        assert_eq!(
+            tracker.check("a", u64::MAX),
+            SequenceStatus::Duplicate { sequence: u64::MAX }
+        );
+        assert_eq!(
+            tracker.check("a", u64::MAX - 1),
+            SequenceStatus::Reordered {
+                sequence: u64::MAX - 1
+            }
+        );
+        assert_eq!(tracker.missing(&"a"), vec![0..u64::MAX - 1]);
+    }
+}
@@ -0,0 +1,138 @@
+use clap::ValueEnum;
+use prometheus_client::metrics::gauge::Gauge;
+use std::sync::{Arc, Mutex};
+use tokio::time::{self, Duration, Instant};
+
+/// What happens to measurements once the event budget is exhausted.
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum BudgetPolicy {
+    /// Measurements are taken later than the sample rate dictates, so their timestamps slip.
+    Delay,
+    /// Measurements keep their timestamps and their events queue until there is budget.
+    Queue,
+}
+
+struct TokenBucket {
+    events_per_second: f64,
+    /// Negative when events were let through on credit.
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    fn refill(&mut self) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+        // At most one second worth of events can be saved up.
+        self.tokens = (self.tokens + elapsed * self.events_per_second).min(self.events_per_second);
+        self.last_refill = now;
+    }
+}
+
+/// Events-per-second budget shared by every experiment of the producer.
+#[derive(Clone)]
+pub struct EventBudget {
+    bucket: Option<Arc<Mutex<TokenBucket>>>,
+    policy: BudgetPolicy,
+    backlog: Gauge,
+}
+
+impl EventBudget {
+    /// Creates the budget, unlimited when `events_per_second` is `None`. `backlog` tracks
+    /// the number of events waiting for budget: those of postponed measurements here, and
+    /// queued ones in `EventQueue`.
+    pub fn new(events_per_second: Option<u64>, policy: BudgetPolicy, backlog: Gauge) -> Self {
+        let bucket = events_per_second.map(|events_per_second| {
+            Arc::new(Mutex::new(TokenBucket {
+                events_per_second: events_per_second as f64,
+                tokens: events_per_second as f64,
+                last_refill: Instant::now(),
+            }))
+        });
+        Self {
+            bucket,
+            policy,
+            backlog,
+        }
+    }
+
+    pub fn queues(&self) -> bool {
+        self.bucket.is_some() && self.policy == BudgetPolicy::Queue
+    }
+
+    pub fn backlog(&self) -> &Gauge {
+        &self.backlog
+    }
+
+    /// Waits before taking a measurement of `events` sensor events, under the `Delay` policy.
+    pub async fn delay_measurement(&self, events: u64) {
+        if self.policy == BudgetPolicy::Delay && self.bucket.is_some() {
+            self.backlog.inc_by(events as i64);
+            self.acquire(events).await;
+            self.backlog.dec_by(events as i64);
+        }
+    }
+
+    /// Waits before sending a single event, under the `Queue` policy.
+    pub async fn queue_event(&self) {
+        if self.policy == BudgetPolicy::Queue {
+            self.acquire(1).await;
+        }
+    }
+
+    async fn acquire(&self, events: u64) {
+        let Some(bucket) = &self.bucket else {
+            return;
+        };
+        loop {
+            let wait = {
+                let mut bucket = bucket.lock().unwrap();
+                bucket.refill();
+                if bucket.tokens >= 0.0 {
+                    // Large requests are let through on credit, which later requests pay off.
+                    bucket.tokens -= events as f64;
+                    None
+                } else {
+                    Some(Duration::from_secs_f64(
+                        -bucket.tokens / bucket.events_per_second,
+                    ))
+                }
+            };
+            match wait {
+                Some(wait) => time::sleep(wait).await,
+                None => break,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn budget_limits_rate() {
+        let budget = EventBudget::new(Some(10), BudgetPolicy::Queue, Gauge::default());
+        let start = Instant::now();
+        for _ in 0..30 {
+            budget.queue_event().await;
+        }
+        // The first events use the initial budget, the rest are paced at 10 per second.
+        let elapsed = start.elapsed();
+        assert!(elapsed >= Duration::from_millis(1800));
+        assert!(elapsed <= Duration::from_millis(2100));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn policy_selects_waiting_point() {
+        let budget = EventBudget::new(Some(1), BudgetPolicy::Delay, Gauge::default());
+        let start = Instant::now();
+        for _ in 0..5 {
+            budget.queue_event().await;
+        }
+        assert_eq!(start.elapsed(), Duration::ZERO);
+        budget.delay_measurement(2).await;
+        budget.delay_measurement(2).await;
+        assert!(start.elapsed() >= Duration::from_millis(900));
+    }
+}
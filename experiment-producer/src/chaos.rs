@@ -0,0 +1,260 @@
+use apache_avro::types::{Record, Value};
+use apache_avro::{Reader, Schema, Writer};
+use clap::ValueEnum;
+use rand::Rng;
+use rdkafka::message::{Headers, OwnedHeaders};
+use uuid::Uuid;
+
+/// Protocol-level faults that can be injected into the topics.
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum Fault {
+    /// The Avro container is cut short.
+    TruncatedAvro,
+    /// A valid Avro container whose record does not match the event's schema.
+    SchemaMismatch,
+    /// The message has no headers.
+    MissingHeaders,
+    /// `record_name` is not the first header.
+    ReorderedHeaders,
+    /// `record_name` names a record that does not exist.
+    UnknownRecordName,
+    /// The message has no key.
+    NullKey,
+}
+
+impl Fault {
+    /// Name of the fault as `--chaos-faults` spells it, e.g. `truncated-avro`.
+    pub fn name(&self) -> String {
+        self.to_possible_value()
+            .expect("No fault is skipped")
+            .get_name()
+            .to_string()
+    }
+}
+
+/// Decides which events are followed by a faulty copy.
+#[derive(Clone)]
+pub struct Chaos {
+    rate: f64,
+    faults: Vec<Fault>,
+}
+
+impl Chaos {
+    /// Injects one of `faults` (any fault when empty) after a `rate` fraction of the events.
+    pub fn new(rate: f64, faults: Vec<Fault>) -> Self {
+        let faults = if faults.is_empty() {
+            Fault::value_variants().to_vec()
+        } else {
+            faults
+        };
+        Self { rate, faults }
+    }
+
+    pub fn pick(&self) -> Option<Fault> {
+        let mut rng = rand::thread_rng();
+        if self.rate <= 0.0 || !rng.gen_bool(self.rate.min(1.0)) {
+            return None;
+        }
+        Some(self.faults[rng.gen_range(0..self.faults.len())])
+    }
+}
+
+/// Faulty copy of an event. Its payload never passes for the event itself: it is either
+/// undecodable, or it names an experiment that does not exist and carries no valid
+/// measurement hash, so a consumer that tolerates the fault does not process the event twice.
+pub struct FaultyRecord {
+    pub payload: Vec<u8>,
+    pub headers: Option<OwnedHeaders>,
+    pub keyed: bool,
+}
+
+impl FaultyRecord {
+    pub fn new(fault: Fault, payload: &[u8], headers: &OwnedHeaders) -> Self {
+        let record_name = record_name(headers);
+        let payload = match fault {
+            Fault::TruncatedAvro => truncated(payload),
+            Fault::SchemaMismatch => mismatched_record(&record_name),
+            _ => disowned(payload),
+        };
+        let headers = match fault {
+            Fault::MissingHeaders => None,
+            Fault::ReorderedHeaders => Some(
+                OwnedHeaders::new()
+                    .add("chaos", "reordered_headers")
+                    .add("record_name", &record_name),
+            ),
+            Fault::UnknownRecordName => {
+                Some(OwnedHeaders::new().add("record_name", "chaos_unknown_record"))
+            }
+            _ => Some(OwnedHeaders::new().add("record_name", &record_name)),
+        };
+        Self {
+            payload,
+            headers,
+            keyed: fault != Fault::NullKey,
+        }
+    }
+}
+
+fn record_name(headers: &OwnedHeaders) -> String {
+    (0..headers.count())
+        .filter_map(|idx| headers.get(idx))
+        .find(|(name, _)| *name == "record_name")
+        .map(|(_, value)| String::from_utf8_lossy(value).into_owned())
+        .unwrap_or_else(|| "unknown".into())
+}
+
+/// Cuts the container somewhere inside its data blocks.
+///
+/// A container ends with the sync marker that also closes its header, and cutting it
+/// right after the header would leave a valid, empty container.
+fn truncated(payload: &[u8]) -> Vec<u8> {
+    const SYNC_LEN: usize = 16;
+    if payload.len() < 2 * SYNC_LEN {
+        return payload[..payload.len() / 2].to_vec();
+    }
+    let sync = &payload[payload.len() - SYNC_LEN..];
+    let header_len = payload
+        .windows(SYNC_LEN)
+        .position(|window| window == sync)
+        .expect("The last sync marker is found")
+        + SYNC_LEN;
+    if header_len >= payload.len() {
+        return payload[..payload.len() - 1].to_vec();
+    }
+    let len = rand::thread_rng().gen_range(header_len + 1..payload.len());
+    payload[..len].to_vec()
+}
+
+/// Avro container with a record named like the event, but with unrelated fields.
+fn mismatched_record(record_name: &str) -> Vec<u8> {
+    let raw_schema = format!(
+        r#"{{"type": "record", "name": "{}", "fields": [{{"name": "chaos", "type": "int"}}]}}"#,
+        record_name
+    );
+    let schema = Schema::parse_str(&raw_schema).unwrap();
+    let mut writer = Writer::new(&schema, Vec::new());
+    let mut record = Record::new(writer.schema()).unwrap();
+    record.put("chaos", rand::thread_rng().gen::<i32>());
+    writer.append(record).unwrap();
+    writer.into_inner().unwrap()
+}
+
+/// Decodable copy of the container whose `experiment` belongs to no experiment, and whose
+/// `measurement_hash`, if any, does not decrypt.
+fn disowned(payload: &[u8]) -> Vec<u8> {
+    let reader = Reader::new(payload).expect("Events are valid containers");
+    let schema = reader.writer_schema().clone();
+    let mut writer = Writer::new(&schema, Vec::new());
+    let experiment = format!("chaos-{}", Uuid::new_v4());
+    for value in reader {
+        let value = match value.expect("Events are valid containers") {
+            Value::Record(fields) => Value::Record(
+                fields
+                    .into_iter()
+                    .map(|(name, value)| match (name.as_str(), value) {
+                        ("experiment", Value::String(_)) => {
+                            (name, Value::String(experiment.clone()))
+                        }
+                        ("measurement_hash", Value::String(_)) => {
+                            (name, Value::String("chaos".into()))
+                        }
+                        (_, value) => (name, value),
+                    })
+                    .collect(),
+            ),
+            value => value,
+        };
+        writer.append(value).unwrap();
+    }
+    writer.into_inner().unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use apache_avro::{from_value, Reader};
+    use serde::Deserialize;
+
+    #[derive(Deserialize)]
+    struct ExperimentStarted {
+        experiment: String,
+        timestamp: f64,
+    }
+
+    fn experiment_started() -> Vec<u8> {
+        let raw_schema = r#"{"type": "record", "name": "experiment_started", "fields": [
+            {"name": "experiment", "type": "string"},
+            {"name": "timestamp", "type": "double"}
+        ]}"#;
+        let schema = Schema::parse_str(raw_schema).unwrap();
+        let mut writer = Writer::new(&schema, Vec::new());
+        let mut record = Record::new(writer.schema()).unwrap();
+        record.put("experiment", "e");
+        record.put("timestamp", 1.0);
+        writer.append(record).unwrap();
+        writer.into_inner().unwrap()
+    }
+
+    #[test]
+    fn truncated_container_is_not_decodable() {
+        let payload = experiment_started();
+        for _ in 0..100 {
+            let truncated = truncated(&payload);
+            assert!(truncated.len() < payload.len());
+            let decoded = Reader::new(&truncated[..])
+                .and_then(|reader| reader.collect::<Result<Vec<_>, _>>());
+            assert!(decoded.is_err(), "{} bytes decoded", truncated.len());
+        }
+    }
+
+    fn decode(payload: &[u8]) -> Result<ExperimentStarted, apache_avro::Error> {
+        let value = Reader::new(payload)?.next().expect("One record")?;
+        from_value::<ExperimentStarted>(&value)
+    }
+
+    #[test]
+    fn mismatched_record_does_not_deserialize() {
+        let event = decode(&experiment_started()).unwrap();
+        assert_eq!((event.experiment.as_str(), event.timestamp), ("e", 1.0));
+
+        let payload = mismatched_record("experiment_started");
+        let values = Reader::new(&payload[..])
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(values.len(), 1);
+        assert!(decode(&payload).is_err());
+    }
+
+    #[test]
+    fn copies_belong_to_no_experiment() {
+        for fault in [
+            Fault::MissingHeaders,
+            Fault::ReorderedHeaders,
+            Fault::NullKey,
+        ] {
+            let headers = OwnedHeaders::new().add("record_name", "experiment_started");
+            let faulty_record = FaultyRecord::new(fault, &experiment_started(), &headers);
+            let event = decode(&faulty_record.payload).unwrap();
+            assert!(event.experiment.starts_with("chaos-"));
+            assert_eq!(event.timestamp, 1.0);
+        }
+    }
+
+    #[test]
+    fn names_match_the_cli() {
+        assert_eq!(Fault::TruncatedAvro.name(), "truncated-avro");
+        for fault in Fault::value_variants() {
+            assert_eq!(Fault::from_str(&fault.name(), false), Ok(*fault));
+        }
+    }
+
+    #[test]
+    fn disabled_chaos_injects_nothing() {
+        let chaos = Chaos::new(0.0, vec![]);
+        assert!((0..100).all(|_| chaos.pick().is_none()));
+        let chaos = Chaos::new(1.0, vec![Fault::NullKey]);
+        assert!((0..100).all(|_| chaos.pick() == Some(Fault::NullKey)));
+    }
+}
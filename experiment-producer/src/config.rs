@@ -1,7 +1,10 @@
 use serde::Deserialize;
-use std::fs;
+use std::{fs, time::Duration};
 
-use crate::simulator::TempRange;
+use event_hash::Keyring;
+
+use crate::heartbeat::Stall;
+use crate::simulator::{TempRange, CONFIGURATION_WAIT};
 
 #[derive(Deserialize, Debug)]
 pub struct UncheckedTempRange {
@@ -9,8 +12,36 @@ pub struct UncheckedTempRange {
     pub upper_threshold: f32,
 }
 
+/// Both the plain list of experiments and the lab layout (rooms + experiments)
+/// are accepted in the configuration file.
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+enum RawConfigFile {
+    Experiments(Vec<ConfigEntry>),
+    Lab {
+        rooms: Vec<RoomEntry>,
+        experiments: Vec<ConfigEntry>,
+    },
+}
+
 #[derive(Deserialize, Debug)]
-pub struct ConfigFile(pub Vec<ConfigEntry>);
+#[serde(from = "RawConfigFile")]
+pub struct ConfigFile {
+    pub rooms: Vec<RoomEntry>,
+    pub experiments: Vec<ConfigEntry>,
+}
+
+impl From<RawConfigFile> for ConfigFile {
+    fn from(raw: RawConfigFile) -> Self {
+        match raw {
+            RawConfigFile::Experiments(experiments) => Self {
+                rooms: Vec::new(),
+                experiments,
+            },
+            RawConfigFile::Lab { rooms, experiments } => Self { rooms, experiments },
+        }
+    }
+}
 
 impl ConfigFile {
     pub fn from_file(file_path: &str) -> Self {
@@ -20,6 +51,14 @@ impl ConfigFile {
     }
 }
 
+#[derive(Deserialize, Debug)]
+pub struct RoomEntry {
+    pub name: String,
+
+    #[serde(default = "ConfigEntry::default_num_sensors")]
+    pub num_sensors: usize,
+}
+
 #[derive(Deserialize, Debug)]
 pub struct ConfigEntry {
     pub start_time: u64,
@@ -43,14 +82,32 @@ pub struct ConfigEntry {
     #[serde(default = "ConfigEntry::default_start_temperature")]
     pub start_temperature: f32,
 
+    /// CSV recording replayed instead of simulated temperatures.
+    #[serde(default)]
+    pub trace_file: Option<String>,
+
+    /// Shifts the recording so that it settles in the middle of `temp_range`.
+    #[serde(default)]
+    pub shift_trace: bool,
+
+    /// Interval of the `experiment_heartbeat` events. No heartbeats when not set.
+    #[serde(default)]
+    pub heartbeat_interval_ms: Option<u64>,
+
+    #[serde(default)]
+    pub stall: Option<Stall>,
+
     #[serde(skip)]
-    pub secret_key: String,
+    pub keyring: Option<Keyring>,
 
     #[serde(skip)]
     pub topic: String,
 
     #[serde(skip)]
     pub topic_document: Option<String>,
+
+    #[serde(skip)]
+    pub sensors: Option<Vec<String>>,
 }
 
 impl ConfigEntry {
@@ -78,14 +135,24 @@ impl ConfigEntry {
         0.0
     }
 
-    pub fn set_secret_key(&mut self, secret_key: &str) {
-        self.secret_key = secret_key.into();
+    pub fn set_keyring(&mut self, keyring: Keyring) {
+        self.keyring = Some(keyring);
     }
 
     pub fn set_topic(&mut self, topic: &str) {
         self.topic = topic.into();
     }
 
+    pub fn set_sensors(&mut self, sensors: Vec<String>) {
+        self.sensors = Some(sensors);
+    }
+
+    /// Time the experiment is expected to take from configuration until termination.
+    pub fn expected_duration(&self) -> Duration {
+        let num_samples = u64::from(self.stabilization_samples) + u64::from(self.carry_out_samples);
+        CONFIGURATION_WAIT + Duration::from_millis(num_samples * self.sample_rate)
+    }
+
     pub fn set_topic_document(&mut self, topic_document: Option<&str>) {
         self.topic_document = topic_document.map(|topic| topic.into());
     }
@@ -0,0 +1,327 @@
+use crossterm::{
+    cursor,
+    event::{self, Event, KeyCode, KeyEventKind, KeyModifiers},
+    execute,
+    terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
+};
+use ratatui::{
+    backend::CrosstermBackend,
+    layout::{Constraint, Layout},
+    style::{Color, Modifier, Style},
+    widgets::{Block, Borders, Cell, Paragraph, Row, Table, TableState},
+    Frame, Terminal,
+};
+use std::collections::VecDeque;
+use std::io::{self, Stdout};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::task::AbortHandle;
+
+use crate::simulator::{ExperimentConfiguration, ExperimentStage, TempRange, TemperatureSample};
+
+/// Average temperatures kept for the sparkline.
+const HISTORY_LEN: usize = 30;
+const SPARKS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+struct ExperimentStatus {
+    experiment_id: String,
+    stage: ExperimentStage,
+    temp_range: TempRange,
+    history: VecDeque<f32>,
+    planned_samples: u64,
+    sent_samples: u64,
+    notifications: u64,
+    send_errors: u64,
+    aborted: bool,
+    abort_handle: Option<AbortHandle>,
+}
+
+impl ExperimentStatus {
+    fn stage_name(&self) -> &'static str {
+        match (self.aborted, self.stage) {
+            (true, _) => "aborted",
+            (false, ExperimentStage::Uninitialized) => "waiting",
+            (false, stage) => stage.name(),
+        }
+    }
+
+    fn remaining_samples(&self) -> u64 {
+        match self.stage {
+            ExperimentStage::Terminated => 0,
+            _ => self.planned_samples.saturating_sub(self.sent_samples),
+        }
+    }
+}
+
+/// Live state of the experiments shown by `--tui`.
+#[derive(Clone, Default)]
+pub struct Dashboard {
+    experiments: Arc<Mutex<Vec<ExperimentStatus>>>,
+}
+
+impl Dashboard {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn register(&self, config: &ExperimentConfiguration) -> StatusHandle {
+        let mut experiments = self.experiments.lock().unwrap();
+        experiments.push(ExperimentStatus {
+            experiment_id: config.experiment_id.clone(),
+            stage: ExperimentStage::Uninitialized,
+            temp_range: config.temp_range(),
+            history: VecDeque::with_capacity(HISTORY_LEN),
+            planned_samples: config.num_samples(),
+            sent_samples: 0,
+            notifications: 0,
+            send_errors: 0,
+            aborted: false,
+            abort_handle: None,
+        });
+        StatusHandle {
+            dashboard: self.clone(),
+            idx: experiments.len() - 1,
+        }
+    }
+
+    /// Counts a failed Kafka send of the experiment keyed by `experiment_id`.
+    pub fn send_error(&self, experiment_id: &str) {
+        let mut experiments = self.experiments.lock().unwrap();
+        if let Some(status) = experiments
+            .iter_mut()
+            .find(|status| status.experiment_id == experiment_id)
+        {
+            status.send_errors += 1;
+        }
+    }
+
+    fn abort(&self, idx: usize) {
+        let mut experiments = self.experiments.lock().unwrap();
+        let status = &mut experiments[idx];
+        if let (Some(abort_handle), false) = (&status.abort_handle, status.aborted) {
+            if status.stage != ExperimentStage::Terminated {
+                abort_handle.abort();
+                status.aborted = true;
+            }
+        }
+    }
+
+    /// Draws the dashboard until `q` is pressed. Blocks the calling thread.
+    pub fn show(&self) -> io::Result<()> {
+        enable_raw_mode()?;
+        let _restore = RestoreTerminal;
+        execute!(io::stdout(), EnterAlternateScreen)?;
+        let mut terminal = Terminal::new(CrosstermBackend::new(io::stdout()))?;
+        self.event_loop(&mut terminal)
+    }
+
+    fn event_loop(&self, terminal: &mut Terminal<CrosstermBackend<Stdout>>) -> io::Result<()> {
+        let mut state = TableState::default();
+        state.select(Some(0));
+        loop {
+            terminal.draw(|frame| self.draw(frame, &mut state))?;
+            if !event::poll(Duration::from_millis(250))? {
+                continue;
+            }
+            let Event::Key(key) = event::read()? else {
+                continue;
+            };
+            if key.kind != KeyEventKind::Press {
+                continue;
+            }
+            let len = self.experiments.lock().unwrap().len();
+            let selected = state.selected().unwrap_or(0);
+            match key.code {
+                KeyCode::Char('q') | KeyCode::Esc => return Ok(()),
+                KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                    return Ok(())
+                }
+                KeyCode::Down | KeyCode::Char('j') if len > 0 => {
+                    state.select(Some((selected + 1) % len))
+                }
+                KeyCode::Up | KeyCode::Char('k') if len > 0 => {
+                    state.select(Some((selected + len - 1) % len))
+                }
+                KeyCode::Char('a') if selected < len => self.abort(selected),
+                _ => {}
+            }
+        }
+    }
+
+    fn draw(&self, frame: &mut Frame, state: &mut TableState) {
+        let experiments = self.experiments.lock().unwrap();
+        let rows = experiments.iter().map(|status| {
+            let temperature = match status.history.back() {
+                Some(cur) => format!(
+                    "{:.2} [{:.1}, {:.1}]",
+                    cur, status.temp_range.lower_threshold, status.temp_range.upper_threshold
+                ),
+                None => "-".into(),
+            };
+            let out_of_range = status.history.back().is_some_and(|cur| {
+                TemperatureSample::new(*cur, status.temp_range).is_out_of_range()
+            });
+            let temperature_style = match out_of_range {
+                true => Style::default().fg(Color::Red),
+                false => Style::default(),
+            };
+            Row::new(vec![
+                Cell::from(status.experiment_id.clone()),
+                Cell::from(status.stage_name()),
+                Cell::from(temperature).style(temperature_style),
+                Cell::from(sparkline(&status.history, status.temp_range)),
+                Cell::from(status.sent_samples.to_string()),
+                Cell::from(status.remaining_samples().to_string()),
+                Cell::from(status.notifications.to_string()),
+                Cell::from(status.send_errors.to_string()),
+            ])
+        });
+        let header = Row::new(vec![
+            "Experiment",
+            "Stage",
+            "Temperature [range]",
+            "Trend",
+            "Sent",
+            "Remaining",
+            "Notifications",
+            "Errors",
+        ])
+        .style(Style::default().add_modifier(Modifier::BOLD));
+        let widths = [
+            Constraint::Length(36),
+            Constraint::Length(13),
+            Constraint::Length(22),
+            Constraint::Length(HISTORY_LEN as u16),
+            Constraint::Length(6),
+            Constraint::Length(9),
+            Constraint::Length(13),
+            Constraint::Length(6),
+        ];
+        let table = Table::new(rows)
+            .header(header)
+            .block(Block::default().borders(Borders::ALL).title("Experiments"))
+            .widths(&widths)
+            .highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+
+        let layout = Layout::default()
+            .constraints([Constraint::Min(3), Constraint::Length(1)])
+            .split(frame.size());
+        frame.render_stateful_widget(table, layout[0], state);
+        frame.render_widget(
+            Paragraph::new("↑/↓ select   a abort selected experiment   q quit"),
+            layout[1],
+        );
+    }
+}
+
+/// Leaves raw mode and the alternate screen when dropped, also on an error or a panic.
+struct RestoreTerminal;
+
+impl Drop for RestoreTerminal {
+    fn drop(&mut self) {
+        let _ = disable_raw_mode();
+        let _ = execute!(io::stdout(), LeaveAlternateScreen, cursor::Show);
+    }
+}
+
+/// Updates the dashboard row of one experiment.
+#[derive(Clone)]
+pub struct StatusHandle {
+    dashboard: Dashboard,
+    idx: usize,
+}
+
+impl StatusHandle {
+    fn update(&self, f: impl FnOnce(&mut ExperimentStatus)) {
+        f(&mut self.dashboard.experiments.lock().unwrap()[self.idx]);
+    }
+
+    pub fn set_abort_handle(&self, abort_handle: AbortHandle) {
+        self.update(|status| status.abort_handle = Some(abort_handle));
+    }
+
+    /// Aborts the wait for the experiment to start, unless it has set its own abort handle.
+    pub fn set_waiting_abort_handle(&self, abort_handle: AbortHandle) {
+        self.update(|status| {
+            status.abort_handle.get_or_insert(abort_handle);
+        });
+    }
+
+    /// Whether the experiment has left the waiting stage.
+    pub fn started(&self) -> bool {
+        let experiments = self.dashboard.experiments.lock().unwrap();
+        experiments[self.idx].stage != ExperimentStage::Uninitialized
+    }
+
+    pub fn set_stage(&self, stage: ExperimentStage) {
+        self.update(|status| status.stage = stage);
+    }
+
+    pub fn measured(&self, avg_temperature: f32, notified: bool) {
+        self.update(|status| {
+            if status.history.len() == HISTORY_LEN {
+                status.history.pop_front();
+            }
+            status.history.push_back(avg_temperature);
+            status.sent_samples += 1;
+            status.notifications += u64::from(notified);
+        });
+    }
+}
+
+/// One bar per temperature, scaled to the temperature range widened by its own width on
+/// both sides, so the range is the middle third of the bars.
+fn sparkline(history: &VecDeque<f32>, temp_range: TempRange) -> String {
+    let width = temp_range.upper_threshold - temp_range.lower_threshold;
+    let low = temp_range.lower_threshold - width;
+    let high = temp_range.upper_threshold + width;
+    history
+        .iter()
+        .map(|temperature| {
+            let level = (temperature - low) / (high - low) * SPARKS.len() as f32;
+            SPARKS[(level.max(0.0) as usize).min(SPARKS.len() - 1)]
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use event_hash::Keyring;
+    use std::future;
+
+    #[tokio::test]
+    async fn abort_stops_the_running_experiment_not_its_wait() {
+        let config = ExperimentConfiguration::new(
+            "researcher".into(),
+            vec![],
+            100,
+            TempRange::new(25.0, 26.0).unwrap(),
+            2,
+            2,
+            Keyring::new(b"QJUHsPhnA0eiqHuJqsPgzhDozYO4f1zh").unwrap(),
+            "experiment".into(),
+            None,
+        );
+        let dashboard = Dashboard::new();
+        let status = dashboard.register(&config);
+        let waiting = tokio::spawn(future::pending::<()>());
+        let running = tokio::spawn(future::pending::<()>());
+        status.set_abort_handle(running.abort_handle());
+        status.set_waiting_abort_handle(waiting.abort_handle());
+        assert!(!status.started());
+
+        status.set_stage(ExperimentStage::Configuration);
+        assert!(status.started());
+        dashboard.abort(0);
+        assert!(running.await.unwrap_err().is_cancelled());
+        assert!(!waiting.is_finished());
+    }
+
+    #[test]
+    fn sparkline_scales_to_range() {
+        let temp_range = TempRange::new(25.0, 26.0).unwrap();
+        let history = VecDeque::from(vec![0.0, 24.0, 25.5, 27.0, 100.0]);
+        assert_eq!(sparkline(&history, temp_range), "▁▁▅██");
+    }
+}
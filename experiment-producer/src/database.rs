@@ -4,18 +4,20 @@ pub async fn insert_ground_truth(
     pool: &Pool<Postgres>,
     experiment_id: &str,
     measurement_id: &str,
+    notification_type: &str,
 ) -> Result<(), sqlx::Error> {
     sqlx::query!(
         "
             INSERT INTO 
-                demo.notification_ground_truth (experiment_id, measurement_id) 
+                demo.notification_ground_truth (experiment_id, measurement_id, notification_type) 
             VALUES 
-                ($1, $2)
+                ($1, $2, $3)
             ON CONFLICT
                 DO NOTHING;
             ",
         experiment_id,
         measurement_id,
+        notification_type,
     )
     .execute(pool)
     .await?;
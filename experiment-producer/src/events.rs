@@ -1,5 +1,6 @@
 use apache_avro::types::{Record, Value};
 use apache_avro::{Reader, Schema, Writer};
+use prometheus_client::metrics::gauge::Gauge;
 use rdkafka::{
     config::ClientConfig,
     error::KafkaError,
@@ -7,15 +8,30 @@ use rdkafka::{
     producer::{FutureProducer, FutureRecord},
 };
 use std::{fs, time::Duration};
-use tracing::{debug, info, span, Level, Span};
+use tokio::{sync::mpsc, task::JoinHandle};
+use tracing::{debug, info, span, warn, Instrument, Level, Span};
 use uuid::Uuid;
 
-use event_hash::{HashData, NotificationType};
+use event_hash::{HashData, Keyring, NotificationType};
 
-use crate::metric::{EventCountLabels, Metrics};
-use crate::simulator::{self, ExperimentStage, IterMut, Measurement, TempRange, TemperatureSample};
+use crate::budget::EventBudget;
+use crate::chaos::{Chaos, Fault, FaultyRecord};
+use crate::dashboard::Dashboard;
+use crate::metric::{EventCountLabels, FaultCountLabels, Metrics};
+use crate::simulator::{self, ExperimentStage, Measurement, Samples, TempRange, TemperatureSample};
 use crate::time;
 
+/// Directory of the Avro schemas. The producer runs from the workspace root, while the tests
+/// run from wherever cargo puts them.
+#[cfg(not(test))]
+const SCHEMA_DIR: &str = "experiment-producer/schemas";
+#[cfg(test)]
+const SCHEMA_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/schemas");
+
+fn read_schema(file_name: &str) -> String {
+    fs::read_to_string(format!("{}/{}", SCHEMA_DIR, file_name)).unwrap()
+}
+
 /// `Vec<u8>` wrapper
 ///
 /// FutureRecord::payload requires a type that implements the trait `ToBytes` as an argument. This is our
@@ -34,8 +50,7 @@ pub fn experiment_configured_event(
     sensors: &Vec<String>,
     temp_range: TempRange,
 ) -> EventWrapper {
-    let raw_schema =
-        fs::read_to_string("experiment-producer/schemas/experiment_configured.avsc").unwrap();
+    let raw_schema = read_schema("experiment_configured.avsc");
     let schema = Schema::parse_str(&raw_schema).unwrap();
     let mut writer = Writer::new(&schema, Vec::new());
 
@@ -59,8 +74,7 @@ pub fn experiment_configured_event(
 }
 
 pub fn stabilization_started_event(experiment_id: &str) -> EventWrapper {
-    let raw_schema =
-        fs::read_to_string("experiment-producer/schemas/stabilization_started.avsc").unwrap();
+    let raw_schema = read_schema("stabilization_started.avsc");
     let schema = Schema::parse_str(&raw_schema).unwrap();
     let mut writer = Writer::new(&schema, Vec::new());
 
@@ -75,8 +89,7 @@ pub fn stabilization_started_event(experiment_id: &str) -> EventWrapper {
 }
 
 pub fn experiment_started_event(experiment_id: &str) -> EventWrapper {
-    let raw_schema =
-        fs::read_to_string("experiment-producer/schemas/experiment_started.avsc").unwrap();
+    let raw_schema = read_schema("experiment_started.avsc");
     let schema = Schema::parse_str(&raw_schema).unwrap();
     let mut writer = Writer::new(&schema, Vec::new());
 
@@ -91,13 +104,37 @@ pub fn experiment_started_event(experiment_id: &str) -> EventWrapper {
 }
 
 pub fn experiment_terminated_event(experiment_id: &str) -> EventWrapper {
-    let raw_schema =
-        fs::read_to_string("experiment-producer/schemas/experiment_terminated.avsc").unwrap();
+    let raw_schema = read_schema("experiment_terminated.avsc");
+    let schema = Schema::parse_str(&raw_schema).unwrap();
+    let mut writer = Writer::new(&schema, Vec::new());
+
+    let mut record = Record::new(writer.schema()).unwrap();
+    record.put("experiment", experiment_id);
+
+    let current_time = time::current_epoch();
+    record.put("timestamp", Value::Double(current_time));
+
+    writer.append(record).unwrap();
+    EventWrapper(writer.into_inner().unwrap())
+}
+
+pub fn experiment_heartbeat_event(
+    experiment_id: &str,
+    stage: &ExperimentStage,
+    sequence: Option<u64>,
+) -> EventWrapper {
+    let raw_schema = read_schema("experiment_heartbeat.avsc");
     let schema = Schema::parse_str(&raw_schema).unwrap();
     let mut writer = Writer::new(&schema, Vec::new());
 
     let mut record = Record::new(writer.schema()).unwrap();
     record.put("experiment", experiment_id);
+    record.put("stage", stage.name());
+    let sequence = match sequence {
+        Some(sequence) => Value::Union(1, Box::new(Value::Long(sequence as i64))),
+        None => Value::Union(0, Box::new(Value::Null)),
+    };
+    record.put("sequence", sequence);
 
     let current_time = time::current_epoch();
     record.put("timestamp", Value::Double(current_time));
@@ -111,8 +148,7 @@ pub fn experiment_document_event(
     measurements: &Vec<Measurement>,
     temp_range: TempRange,
 ) -> EventWrapper {
-    let raw_schema =
-        fs::read_to_string("experiment-producer/schemas/experiment_document.avsc").unwrap();
+    let raw_schema = read_schema("experiment_document.avsc");
     let schema = Schema::parse_str(&raw_schema).unwrap();
     let mut writer = Writer::new(&schema, Vec::new());
 
@@ -148,6 +184,12 @@ pub fn experiment_document_event(
     EventWrapper(writer.into_inner().unwrap())
 }
 
+/// Position of a sensor event among the events of its experiment and of its sensor.
+pub struct EventSequence {
+    pub experiment: u64,
+    pub sensor: u64,
+}
+
 pub fn temperature_measured_event(
     experiment: &str,
     measurement_id: &str,
@@ -155,9 +197,9 @@ pub fn temperature_measured_event(
     temperature: f32,
     timestamp: f64,
     measurement_hash: &str,
+    sequence: EventSequence,
 ) -> EventWrapper {
-    let raw_schema =
-        fs::read_to_string("experiment-producer/schemas/sensor_temperature_measured.avsc").unwrap();
+    let raw_schema = read_schema("sensor_temperature_measured.avsc");
     let schema = Schema::parse_str(&raw_schema).unwrap();
     let mut writer = Writer::new(&schema, Vec::new());
 
@@ -168,6 +210,8 @@ pub fn temperature_measured_event(
     record.put("temperature", temperature);
     record.put("measurement_hash", measurement_hash);
     record.put("timestamp", Value::Double(timestamp));
+    record.put("sequence", Value::Long(sequence.experiment as i64));
+    record.put("sensor_sequence", Value::Long(sequence.sensor as i64));
 
     writer.append(record).unwrap();
     let encoded = writer.into_inner().unwrap();
@@ -216,14 +260,20 @@ fn compute_notification_type(
     }
 }
 
+/// Builds the sensor events of every sample in `sample_iter`.
+///
+/// `num_measurements` counts the measurements already produced for the experiment. The
+/// `sequence` of an event numbers all sensor events of the experiment, whereas its
+/// `sensor_sequence` numbers the events of that sensor (i.e. the measurement).
 pub fn temperature_events<'b>(
-    sample_iter: IterMut<'b>,
+    sample_iter: Samples<'b>,
+    num_measurements: &'b mut u64,
     experiment_id: &'b str,
     researcher: &'b str,
     sensors: &'b Vec<String>,
     stage: &'b ExperimentStage,
-    secret_key: &'b str,
-) -> Box<dyn Iterator<Item = (Vec<EventWrapper>, Span, Measurement)> + 'b + Send> {
+    keyring: &'b Keyring,
+) -> Box<dyn ExactSizeIterator<Item = (Vec<EventWrapper>, Span, Measurement)> + 'b + Send> {
     let mut prev_sample = None;
 
     Box::new(sample_iter.map(move |sample| {
@@ -239,6 +289,8 @@ pub fn temperature_events<'b>(
             experiment_id: experiment_id.into(),
             measurement_id: measurement_id.clone(),
             researcher: researcher.into(),
+            issued_at: Some(current_time),
+            expires_at: keyring.expires_at(current_time),
         };
         let measurement = Measurement {
             measurement_id: measurement_id.clone(),
@@ -246,12 +298,16 @@ pub fn temperature_events<'b>(
             timestamp: current_time,
             notification_type,
         };
-        let measurement_hash = hash_data.encrypt(secret_key.as_bytes());
+        let measurement_hash = hash_data.encrypt_with(keyring);
         prev_sample = Some(sample);
 
+        let sensor_sequence = *num_measurements;
+        *num_measurements += 1;
+
         let sensor_events = simulator::compute_sensor_temperatures(&sensors, sample.cur())
             .into_iter()
-            .map(|(sensor_id, sensor_temperature)| {
+            .enumerate()
+            .map(|(i, (sensor_id, sensor_temperature))| {
                 temperature_measured_event(
                     experiment_id,
                     measurement_id.as_str(),
@@ -259,6 +315,10 @@ pub fn temperature_events<'b>(
                     sensor_temperature,
                     current_time,
                     &measurement_hash,
+                    EventSequence {
+                        experiment: sensor_sequence * sensors.len() as u64 + i as u64,
+                        sensor: sensor_sequence,
+                    },
                 )
             })
             .collect();
@@ -267,6 +327,21 @@ pub fn temperature_events<'b>(
     }))
 }
 
+/// Connection settings shared by every client talking to the brokers.
+pub fn client_config(brokers: &str) -> ClientConfig {
+    let mut config = ClientConfig::new();
+    config
+        .set("bootstrap.servers", brokers)
+        .set("security.protocol", "SSL")
+        .set("ssl.ca.location", "experiment-producer/auth/ca.crt")
+        .set(
+            "ssl.keystore.location",
+            "experiment-producer/auth/kafka.keystore.pkcs12",
+        )
+        .set("ssl.keystore.password", "cc2023");
+    config
+}
+
 pub struct RecordData<K: ToBytes, T: ToBytes> {
     pub payload: T,
     pub key: Option<K>,
@@ -277,20 +352,22 @@ pub struct RecordData<K: ToBytes, T: ToBytes> {
 pub struct KafkaTopicProducer {
     producer: FutureProducer, // partition: Option<usize>
     metrics: Metrics,
+    budget: EventBudget,
+    chaos: Chaos,
+    /// Counts the send errors of each experiment when running with `--tui`.
+    dashboard: Option<Dashboard>,
 }
 
 impl KafkaTopicProducer {
-    pub fn new(brokers: &str, metrics: Metrics) -> Self {
-        let producer: FutureProducer = ClientConfig::new()
-            .set("bootstrap.servers", brokers)
+    pub fn new(
+        brokers: &str,
+        metrics: Metrics,
+        budget: EventBudget,
+        chaos: Chaos,
+        dashboard: Option<Dashboard>,
+    ) -> Self {
+        let producer: FutureProducer = client_config(brokers)
             .set("message.timeout.ms", "5000")
-            .set("security.protocol", "SSL")
-            .set("ssl.ca.location", "experiment-producer/auth/ca.crt")
-            .set(
-                "ssl.keystore.location",
-                "experiment-producer/auth/kafka.keystore.pkcs12",
-            )
-            .set("ssl.keystore.password", "cc2023")
             .create()
             .expect("Producer creation error");
 
@@ -299,7 +376,22 @@ impl KafkaTopicProducer {
         // call to ClientConfig::new()
         span!(Level::INFO, "");
 
-        KafkaTopicProducer { producer, metrics }
+        KafkaTopicProducer {
+            producer,
+            metrics,
+            budget,
+            chaos,
+            dashboard,
+        }
+    }
+
+    /// Events-per-second budget shared by everything sent through this producer.
+    pub fn budget(&self) -> &EventBudget {
+        &self.budget
+    }
+
+    pub fn dashboard(&self) -> Option<&Dashboard> {
+        self.dashboard.as_ref()
     }
 
     fn update_count<K>(&self, topic: &str, key: Option<&K>)
@@ -326,6 +418,12 @@ impl KafkaTopicProducer {
         T: ToBytes,
         K: ToBytes + std::fmt::Debug,
     {
+        let fault = self.chaos.pick().map(|fault| {
+            (
+                fault,
+                FaultyRecord::new(fault, record.payload.to_bytes(), &record.headers),
+            )
+        });
         let mut future_record: FutureRecord<'_, K, T> = FutureRecord::to(topic)
             .payload(&record.payload)
             .headers(record.headers);
@@ -344,8 +442,148 @@ impl KafkaTopicProducer {
         }
         self.update_count(topic, record.key.as_ref());
 
-        self.producer
+        let result = self
+            .producer
+            .send(future_record, Duration::from_secs(0))
+            .await;
+        if let (Err(_), Some(dashboard), Some(key)) = (&result, &self.dashboard, &record.key) {
+            dashboard.send_error(&String::from_utf8_lossy(key.to_bytes()));
+        }
+        if let Some((fault, faulty_record)) = fault {
+            self.send_fault(fault, faulty_record, record.key.as_ref(), topic)
+                .await;
+        }
+        result
+    }
+
+    /// Sends the faulty copy of an event. Failing to do so is not an error of the
+    /// experiment, so it is only logged.
+    async fn send_fault<K>(
+        &self,
+        fault: Fault,
+        faulty_record: FaultyRecord,
+        key: Option<&K>,
+        topic: &str,
+    ) where
+        K: ToBytes + ?Sized,
+    {
+        let mut future_record: FutureRecord<'_, K, Vec<u8>> =
+            FutureRecord::to(topic).payload(&faulty_record.payload);
+        if let Some(headers) = faulty_record.headers {
+            future_record = future_record.headers(headers);
+        }
+        if let (Some(key), true) = (key, faulty_record.keyed) {
+            future_record = future_record.key(key);
+        }
+        info!(topic, fault = fault.name(), "Injecting fault");
+        self.metrics
+            .fault_count
+            .get_or_create(&FaultCountLabels {
+                fault: fault.name(),
+                topic: topic.to_string(),
+            })
+            .inc();
+
+        if let Err((err, _)) = self
+            .producer
             .send(future_record, Duration::from_secs(0))
             .await
+        {
+            warn!(
+                topic,
+                fault = fault.name(),
+                "Failed to inject fault: {}",
+                err
+            );
+        }
+    }
+}
+
+/// Sensor events of an experiment waiting for the event budget under the `Queue` policy.
+/// A single task sends them one after the other, so they keep the order of their
+/// sequence numbers.
+pub struct EventQueue {
+    sender: mpsc::Sender<(RecordData<String, EventWrapper>, Span)>,
+    worker: JoinHandle<()>,
+    backlog: Gauge,
+}
+
+impl EventQueue {
+    /// Events an experiment can have queued. Beyond it, pushing waits as well, so the
+    /// measurements slip as under the `Delay` policy rather than the queue growing unbounded.
+    pub const CAPACITY: usize = 10_000;
+
+    pub fn new(producer: KafkaTopicProducer, topic: String) -> Self {
+        let (sender, mut receiver) =
+            mpsc::channel::<(RecordData<String, EventWrapper>, Span)>(Self::CAPACITY);
+        let backlog = producer.budget().backlog().clone();
+        let worker = tokio::spawn(async move {
+            while let Some((record, span)) = receiver.recv().await {
+                async {
+                    producer.budget().queue_event().await;
+                    let sent = producer.send_event(record, &topic).await;
+                    producer.budget().backlog().dec();
+                    sent.expect("Failed to produce message");
+                }
+                .instrument(span)
+                .await;
+            }
+        });
+        Self {
+            sender,
+            worker,
+            backlog,
+        }
+    }
+
+    pub async fn push(&self, record: RecordData<String, EventWrapper>) {
+        self.backlog.inc();
+        self.sender
+            .send((record, Span::current()))
+            .await
+            .unwrap_or_else(|_| panic!("Event queue should be running"));
+    }
+
+    /// Waits until every queued event is sent.
+    pub async fn flush(self) {
+        drop(self.sender);
+        self.worker.await.expect("Event queue should not panic");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::budget::BudgetPolicy;
+
+    #[tokio::test]
+    async fn queued_events_count_as_backlog() {
+        let metrics = Metrics::new();
+        let budget = EventBudget::new(Some(1), BudgetPolicy::Queue, metrics.event_backlog.clone());
+        // Uses up the initial budget and the credit after it
+        budget.queue_event().await;
+        budget.queue_event().await;
+        let producer = KafkaTopicProducer {
+            producer: ClientConfig::new()
+                .set("test.mock.num.brokers", "1")
+                .create()
+                .unwrap(),
+            metrics: metrics.clone(),
+            budget,
+            chaos: Chaos::new(0.0, vec![]),
+            dashboard: None,
+        };
+
+        let queue = EventQueue::new(producer, "sensor".into());
+        for _ in 0..5 {
+            queue
+                .push(RecordData {
+                    payload: experiment_started_event("experiment"),
+                    key: Some("experiment".into()),
+                    headers: OwnedHeaders::new(),
+                })
+                .await;
+        }
+        assert_eq!(metrics.event_backlog.get(), 5);
     }
 }
@@ -0,0 +1,172 @@
+use clap::ValueEnum;
+use rdkafka::message::OwnedHeaders;
+use serde::Deserialize;
+use std::future;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::{task::JoinHandle, time};
+use tracing::{info, warn, Instrument, Span};
+
+use crate::events::{self, KafkaTopicProducer, RecordData};
+use crate::simulator::ExperimentStage;
+
+/// What the heartbeats report.
+struct Pulse {
+    stage: ExperimentStage,
+    sequence: Option<u64>,
+    paused: bool,
+}
+
+/// Periodically sends an `experiment_heartbeat` event, so consumers can tell a slow
+/// experiment from a stalled one. The heartbeats stop when it is dropped.
+pub struct Heartbeat {
+    pulse: Arc<Mutex<Pulse>>,
+    handle: JoinHandle<()>,
+}
+
+impl Heartbeat {
+    pub fn start(
+        producer: KafkaTopicProducer,
+        topic: String,
+        experiment_id: String,
+        interval: Duration,
+    ) -> Self {
+        let pulse = Arc::new(Mutex::new(Pulse {
+            stage: ExperimentStage::Uninitialized,
+            sequence: None,
+            paused: false,
+        }));
+        let handle = tokio::spawn({
+            let pulse = pulse.clone();
+            async move {
+                let mut interval = time::interval(interval);
+                interval.set_missed_tick_behavior(time::MissedTickBehavior::Delay);
+                loop {
+                    interval.tick().await;
+                    let payload = {
+                        let pulse = pulse.lock().unwrap();
+                        if pulse.paused {
+                            continue;
+                        }
+                        events::experiment_heartbeat_event(
+                            &experiment_id,
+                            &pulse.stage,
+                            pulse.sequence,
+                        )
+                    };
+                    let record = RecordData {
+                        payload,
+                        key: Some(&experiment_id),
+                        headers: OwnedHeaders::new().add("record_name", "experiment_heartbeat"),
+                    };
+                    if let Err((err, _)) = producer.send_event(record, &topic).await {
+                        warn!("Failed to send heartbeat: {}", err);
+                    }
+                }
+            }
+            .instrument(Span::current())
+        });
+        Self { pulse, handle }
+    }
+
+    pub fn set_stage(&self, stage: ExperimentStage) {
+        self.pulse.lock().unwrap().stage = stage;
+    }
+
+    /// Experiment-wide sequence number of the last sensor event.
+    pub fn set_sequence(&self, sequence: u64) {
+        self.pulse.lock().unwrap().sequence = Some(sequence);
+    }
+
+    fn set_paused(&self, paused: bool) {
+        self.pulse.lock().unwrap().paused = paused;
+    }
+}
+
+impl Drop for Heartbeat {
+    fn drop(&mut self) {
+        self.handle.abort();
+    }
+}
+
+/// What stops during a stall.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum StallMode {
+    /// Measurements stop, heartbeats continue: the producer is alive but stuck.
+    #[default]
+    Measurements,
+    /// Measurements and heartbeats stop: the producer crashed.
+    All,
+}
+
+/// Simulated stall of an experiment.
+#[derive(Clone, Debug, Deserialize)]
+pub struct Stall {
+    /// Number of measurements sent before the stall.
+    pub after_measurements: u64,
+
+    /// The experiment never resumes when not set.
+    #[serde(default)]
+    pub duration_ms: Option<u64>,
+
+    #[serde(default)]
+    pub mode: StallMode,
+}
+
+impl Stall {
+    /// Stalls when `measurement` (counted from zero) is the first one after the stall.
+    pub async fn wait(&self, measurement: u64, heartbeat: Option<&Heartbeat>) {
+        if measurement != self.after_measurements {
+            return;
+        }
+        info!(
+            measurement,
+            duration_ms = self.duration_ms,
+            mode = format!("{:?}", self.mode),
+            "Stalling experiment"
+        );
+        let heartbeat = heartbeat.filter(|_| self.mode == StallMode::All);
+        if let Some(heartbeat) = heartbeat {
+            heartbeat.set_paused(true);
+        }
+        match self.duration_ms {
+            Some(duration_ms) => time::sleep(Duration::from_millis(duration_ms)).await,
+            None => future::pending().await,
+        }
+        if let Some(heartbeat) = heartbeat {
+            heartbeat.set_paused(false);
+        }
+        info!("Resuming experiment");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn stall_waits_only_at_its_measurement() {
+        let stall = Stall {
+            after_measurements: 3,
+            duration_ms: Some(5000),
+            mode: StallMode::Measurements,
+        };
+        let start = time::Instant::now();
+        stall.wait(2, None).await;
+        stall.wait(4, None).await;
+        assert_eq!(start.elapsed(), Duration::ZERO);
+        stall.wait(3, None).await;
+        assert_eq!(start.elapsed(), Duration::from_millis(5000));
+    }
+
+    #[test]
+    fn stall_mode_defaults_to_measurements() {
+        let stall: Stall = serde_json::from_str(r#"{"after_measurements": 10}"#).unwrap();
+        assert_eq!(stall.mode, StallMode::Measurements);
+        assert_eq!(stall.duration_ms, None);
+        let stall: Stall =
+            serde_json::from_str(r#"{"after_measurements": 10, "mode": "all"}"#).unwrap();
+        assert_eq!(stall.mode, StallMode::All);
+    }
+}
@@ -0,0 +1,236 @@
+use std::time::Duration;
+
+use crate::config::{ConfigEntry, RoomEntry};
+use crate::simulator::ExperimentConfiguration;
+
+/// A lab room with a fixed set of sensors.
+///
+/// Every experiment carried out in the room reports its measurements through the
+/// room's sensors, so the same sensor ids show up across consecutive experiments.
+#[derive(Clone, Debug)]
+pub struct Room {
+    pub name: String,
+    pub sensors: Vec<String>,
+}
+
+impl Room {
+    pub fn new(name: String, sensors: Vec<String>) -> Self {
+        Self { name, sensors }
+    }
+}
+
+impl From<&RoomEntry> for Room {
+    fn from(room_entry: &RoomEntry) -> Self {
+        Self::new(
+            room_entry.name.clone(),
+            ExperimentConfiguration::random_sensors(room_entry.num_sensors),
+        )
+    }
+}
+
+/// Room an experiment has been assigned to, and when it may start (relative to the
+/// producer's start).
+#[derive(Clone, Debug, PartialEq)]
+pub struct Placement {
+    pub room: usize,
+    pub start_offset: Duration,
+}
+
+pub struct Lab {
+    pub rooms: Vec<Room>,
+}
+
+impl Lab {
+    pub fn new(rooms: Vec<Room>) -> Self {
+        Self { rooms }
+    }
+
+    /// Places each experiment in a room that is free at the experiment's start time.
+    ///
+    /// Experiments are handled in order of their `start_time`. When every room with
+    /// enough sensors is still occupied, the experiment is postponed until the first of
+    /// those rooms becomes free. The returned placements follow the order of `entries`.
+    ///
+    /// The room is the one that frees up earliest (the first such room on a tie), not the
+    /// one whose sensors fit the experiment best: a small experiment may take a large room,
+    /// and a later experiment needing that room waits even if a smaller room is free.
+    pub fn schedule(&self, entries: &[ConfigEntry]) -> Result<Vec<Placement>, String> {
+        let mut order: Vec<usize> = (0..entries.len()).collect();
+        order.sort_by_key(|&i| entries[i].start_time);
+
+        let mut free_at = vec![Duration::ZERO; self.rooms.len()];
+        let mut placements = vec![None; entries.len()];
+        for i in order {
+            let entry = &entries[i];
+            let requested = Duration::from_secs(entry.start_time);
+            let room = (0..self.rooms.len())
+                .filter(|&room| self.rooms[room].sensors.len() >= entry.num_sensors)
+                .min_by_key(|&room| (free_at[room].max(requested), room))
+                .ok_or(format!(
+                    "No room has the {} sensors required by the experiment of `{}` starting at {}s",
+                    entry.num_sensors, entry.researcher, entry.start_time
+                ))?;
+
+            let start_offset = free_at[room].max(requested);
+            free_at[room] = start_offset + entry.expected_duration();
+            placements[i] = Some(Placement { room, start_offset });
+        }
+        Ok(placements.into_iter().flatten().collect())
+    }
+
+    /// Sensors of `room` used by an experiment that requires `num_sensors` of them.
+    pub fn sensors(&self, room: usize, num_sensors: usize) -> Vec<String> {
+        self.rooms[room].sensors[..num_sensors].to_vec()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use apache_avro::{types::Value, Reader};
+    use event_hash::{HashData, Keyring};
+    use rdkafka::message::ToBytes;
+    use std::collections::HashMap;
+
+    use crate::events::temperature_events;
+    use crate::simulator::{ExperimentStage, TemperatureSample};
+
+    fn entries(raw: &str) -> Vec<ConfigEntry> {
+        serde_json::from_str(raw).unwrap()
+    }
+
+    fn lab(sensors_per_room: &[usize]) -> Lab {
+        Lab::new(
+            sensors_per_room
+                .iter()
+                .enumerate()
+                .map(|(i, &num_sensors)| {
+                    Room::new(
+                        format!("room-{}", i),
+                        ExperimentConfiguration::random_sensors(num_sensors),
+                    )
+                })
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn consecutive_experiments_share_room() {
+        let entries = entries(
+            r#"[
+                {"start_time": 0, "researcher": "a", "sample_rate": 1000, "stabilization_samples": 2, "carry_out_samples": 3},
+                {"start_time": 1, "researcher": "b"}
+            ]"#,
+        );
+        let placements = lab(&[2]).schedule(&entries).unwrap();
+        assert_eq!(
+            placements,
+            vec![
+                Placement {
+                    room: 0,
+                    start_offset: Duration::ZERO,
+                },
+                Placement {
+                    room: 0,
+                    start_offset: Duration::from_millis(7000),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn consecutive_experiments_keep_readings_apart() {
+        let entries = entries(
+            r#"[
+                {"start_time": 0, "researcher": "a", "carry_out_samples": 3},
+                {"start_time": 0, "researcher": "b", "carry_out_samples": 3}
+            ]"#,
+        );
+        let lab = lab(&[2]);
+        let placements = lab.schedule(&entries).unwrap();
+        assert_eq!(placements[0].room, placements[1].room);
+        assert_eq!(placements[1].start_offset, entries[0].expected_duration());
+
+        let keyring = Keyring::new(b"QJUHsPhnA0eiqHuJqsPgzhDozYO4f1zh").unwrap();
+        let sensors = lab.sensors(placements[0].room, 2);
+        let configs: Vec<_> = entries
+            .into_iter()
+            .zip(&placements)
+            .map(|(mut entry, placement)| {
+                entry.set_keyring(keyring.clone());
+                entry.set_sensors(lab.sensors(placement.room, entry.num_sensors));
+                (entry.temp_range, ExperimentConfiguration::from(entry))
+            })
+            .collect();
+        assert_ne!(configs[0].1.experiment_id, configs[1].1.experiment_id);
+        for (temp_range, config) in &configs {
+            let experiment_id = config.experiment_id.as_str();
+            let mut sample = TemperatureSample::new(26.0, *temp_range);
+            let mut num_measurements = 0;
+            let readings: Vec<_> = temperature_events(
+                Box::new(sample.carry_out_samples(3)),
+                &mut num_measurements,
+                experiment_id,
+                "a",
+                &sensors,
+                &ExperimentStage::CarryOut,
+                &keyring,
+            )
+            .flat_map(|(events, _, _)| events)
+            .flat_map(|event| Reader::new(event.to_bytes()).unwrap().collect::<Vec<_>>())
+            .map(|record| match record.unwrap() {
+                Value::Record(fields) => fields.into_iter().collect::<HashMap<_, _>>(),
+                value => panic!("Not a record: {:?}", value),
+            })
+            .collect();
+
+            // The room's sensors report for both experiments, but every reading names its
+            // own experiment, and the sequences start over
+            assert_eq!(readings.len(), 3 * sensors.len());
+            for (i, reading) in readings.iter().enumerate() {
+                assert_eq!(reading["experiment"], Value::String(experiment_id.into()));
+                assert_eq!(
+                    reading["sensor"],
+                    Value::String(sensors[i % sensors.len()].clone())
+                );
+                assert_eq!(reading["sequence"], Value::Long(i as i64));
+                let Value::String(measurement_hash) = &reading["measurement_hash"] else {
+                    panic!("Not a string: {:?}", reading["measurement_hash"]);
+                };
+                let Value::String(measurement_id) = &reading["measurement_id"] else {
+                    panic!("Not a string: {:?}", reading["measurement_id"]);
+                };
+                let hash_data = HashData::decrypt_with(
+                    &keyring,
+                    measurement_hash,
+                    experiment_id,
+                    measurement_id,
+                )
+                .unwrap();
+                assert_eq!(hash_data.experiment_id, experiment_id);
+            }
+        }
+    }
+
+    #[test]
+    fn overlapping_experiments_use_free_rooms() {
+        let entries = entries(
+            r#"[
+                {"start_time": 5, "researcher": "a"},
+                {"start_time": 0, "researcher": "b", "num_sensors": 3},
+                {"start_time": 0, "researcher": "c"}
+            ]"#,
+        );
+        let placements = lab(&[2, 3]).schedule(&entries).unwrap();
+        assert_eq!(placements[1].room, 1);
+        assert_eq!(placements[2].room, 0);
+        assert_eq!(placements[0].room, 0);
+        assert_eq!(placements[0].start_offset, Duration::from_secs(5));
+    }
+
+    #[test]
+    fn experiment_larger_than_every_room() {
+        let entries = entries(r#"[{"start_time": 0, "researcher": "a", "num_sensors": 4}]"#);
+        assert!(lab(&[2, 3]).schedule(&entries).is_err());
+    }
+}
@@ -2,46 +2,73 @@ use ::time::{format_description, UtcOffset};
 use clap::{command, value_parser, Arg, ArgAction, ArgMatches};
 use dotenv;
 use futures::future;
+use rdkafka::{admin::AdminClient, client::DefaultClientContext};
 use sqlx::{
     postgres::{PgPoolOptions, Postgres},
     Pool,
 };
-use std::env;
-use tokio::time::{self as tktime, Duration};
+use std::{env, str::FromStr, sync::Arc};
+use tokio::{
+    sync::Mutex,
+    time::{self as tktime, Duration},
+};
 use tracing::{info, span, Instrument, Level};
 use tracing_appender::non_blocking::WorkerGuard;
 use tracing_subscriber::{filter::LevelFilter, fmt::time::OffsetTime, prelude::*};
 
+use event_hash::{Algorithm, Envelope, Keyring, SecretKey};
+
+mod budget;
+mod chaos;
 mod config;
+mod dashboard;
 mod database;
 mod events;
+mod heartbeat;
+mod lab;
 mod metric;
+mod provision;
 mod simulator;
 mod time;
+mod trace;
 
+use budget::{BudgetPolicy, EventBudget};
+use chaos::{Chaos, Fault};
 use config::ConfigFile;
+use dashboard::Dashboard;
 use events::KafkaTopicProducer;
+use heartbeat::{Stall, StallMode};
+use lab::{Lab, Room};
 use metric::{MetricServer, Metrics};
+use provision::TopicSettings;
 use simulator::{Experiment, ExperimentConfiguration, TempRange};
 
 async fn run_single_experiment(
     mut matches: ArgMatches,
     pool: Option<Pool<Postgres>>,
     metrics: Metrics,
+    budget: EventBudget,
+    chaos: Chaos,
+    dashboard: Option<Dashboard>,
 ) {
     let topic_producer = KafkaTopicProducer::new(
         &matches
             .remove_one::<String>("broker-list")
             .expect("required"),
         metrics,
+        budget,
+        chaos,
+        dashboard,
     );
 
     let experiment_config = ExperimentConfiguration::new(
         "d.landau@uu.nl".into(),
-        matches
-            .remove_one::<u8>("num-sensors")
-            .expect("required")
-            .into(),
+        ExperimentConfiguration::random_sensors(
+            matches
+                .remove_one::<u8>("num-sensors")
+                .expect("required")
+                .into(),
+        ),
         matches.remove_one::<u64>("sample-rate").expect("required"),
         TempRange::new(
             matches
@@ -58,9 +85,7 @@ async fn run_single_experiment(
         matches
             .remove_one::<u16>("carry-out-samples")
             .expect("required"),
-        matches
-            .remove_one::<String>("secret-key")
-            .expect("required"),
+        load_keyring(&matches),
         matches.remove_one::<String>("topic").expect("required"),
         matches.remove_one::<String>("topic-document"),
     );
@@ -74,9 +99,32 @@ async fn run_single_experiment(
         "experiment",
         experiment_id = experiment_config.experiment_id
     );
+    let status = topic_producer
+        .dashboard()
+        .map(|dashboard| dashboard.register(&experiment_config));
     let mut experiment =
         Experiment::new(start_temperature, experiment_config, topic_producer, pool);
-    experiment.run().instrument(span).await;
+    if let Some(trace_file) = matches.remove_one::<String>("trace-file") {
+        experiment
+            .load_trace(&trace_file, matches.get_flag("shift-trace"))
+            .unwrap_or_else(|err| panic!("{}", err));
+    }
+    if let Some(interval) = matches.remove_one::<u64>("heartbeat-interval") {
+        experiment.enable_heartbeat(Duration::from_millis(interval));
+    }
+    if let Some(after_measurements) = matches.remove_one::<u64>("stall-after") {
+        experiment.set_stall(Stall {
+            after_measurements,
+            duration_ms: matches.remove_one::<u64>("stall-duration"),
+            mode: matches
+                .remove_one::<StallMode>("stall-mode")
+                .expect("required"),
+        });
+    }
+    match status {
+        Some(status) => experiment.run_abortable(status).instrument(span).await,
+        None => experiment.run().instrument(span).await,
+    }
 }
 
 async fn run_multiple_experiments(
@@ -84,50 +132,176 @@ async fn run_multiple_experiments(
     config_file: &str,
     pool: Option<Pool<Postgres>>,
     metrics: Metrics,
+    budget: EventBudget,
+    chaos: Chaos,
+    dashboard: Option<Dashboard>,
 ) {
     let topic_producer = KafkaTopicProducer::new(
         &matches
             .remove_one::<String>("broker-list")
             .expect("required"),
         metrics,
+        budget,
+        chaos,
+        dashboard,
     );
 
     let config = ConfigFile::from_file(config_file);
+    let lab = Lab::new(config.rooms.iter().map(Room::from).collect());
+    let placements = if lab.rooms.is_empty() {
+        vec![None; config.experiments.len()]
+    } else {
+        lab.schedule(&config.experiments)
+            .expect("Experiments should fit in the lab's rooms")
+            .into_iter()
+            .map(Some)
+            .collect()
+    };
+    // A room only hosts one experiment at a time, even if an earlier experiment
+    // overruns its expected duration.
+    let room_locks: Vec<_> = lab.rooms.iter().map(|_| Arc::new(Mutex::new(()))).collect();
+
+    let keyring = load_keyring(&matches);
     let mut handles = vec![];
-    for mut entry in config.0 {
+    for (mut entry, placement) in config.experiments.into_iter().zip(placements) {
         let start_temperature = entry.start_temperature;
-        let start_offset = entry.start_time;
-        entry.set_secret_key(&matches.get_one::<String>("secret-key").expect("required"));
+        let trace = entry
+            .trace_file
+            .take()
+            .map(|trace_file| (trace_file, entry.shift_trace));
+        let heartbeat_interval = entry.heartbeat_interval_ms.map(|interval| {
+            assert!(interval > 0, "heartbeat_interval_ms should be positive");
+            Duration::from_millis(interval)
+        });
+        let stall = entry.stall.take();
+        let start_offset = match &placement {
+            Some(placement) => placement.start_offset,
+            None => Duration::from_secs(entry.start_time),
+        };
+        entry.set_keyring(keyring.clone());
         entry.set_topic(&matches.get_one::<String>("topic").expect("required"));
         entry.set_topic_document(
             matches
                 .get_one::<String>("topic-document")
                 .map(|topic| topic.as_str()),
         );
+        let room = placement.map(|placement| {
+            entry.set_sensors(lab.sensors(placement.room, entry.num_sensors));
+            (
+                lab.rooms[placement.room].name.clone(),
+                room_locks[placement.room].clone(),
+            )
+        });
         let experiment_config = ExperimentConfiguration::from(entry);
+        let status = topic_producer
+            .dashboard()
+            .map(|dashboard| dashboard.register(&experiment_config));
+        let abort_status = status.clone();
         let topic_producer = topic_producer.clone();
 
         let span = span!(
             Level::INFO,
             "experiment",
-            experiment_id = experiment_config.experiment_id
+            experiment_id = experiment_config.experiment_id,
+            room = room.as_ref().map(|(name, _)| name.as_str())
         );
         let pool = pool.clone();
         handles.push(tokio::spawn(
             async move {
-                tktime::sleep(Duration::from_millis(start_offset * 1000)).await;
+                tktime::sleep(start_offset).await;
+                let _room_guard = match &room {
+                    Some((_, lock)) => Some(lock.lock().await),
+                    None => None,
+                };
 
                 let mut experiment =
                     Experiment::new(start_temperature, experiment_config, topic_producer, pool);
-                experiment.run().await;
+                if let Some((trace_file, shift)) = trace {
+                    experiment
+                        .load_trace(&trace_file, shift)
+                        .unwrap_or_else(|err| panic!("{}", err));
+                }
+                if let Some(interval) = heartbeat_interval {
+                    experiment.enable_heartbeat(interval);
+                }
+                if let Some(stall) = stall {
+                    experiment.set_stall(stall);
+                }
+                match status {
+                    Some(status) => experiment.run_abortable(status).await,
+                    None => experiment.run().await,
+                }
             }
             .instrument(span),
         ));
+        if let Some(status) = abort_status {
+            status.set_waiting_abort_handle(handles[handles.len() - 1].abort_handle());
+        }
     }
     future::join_all(handles).await;
 }
 
-fn configure_tracing() -> WorkerGuard {
+/// The keyring of `--keyring-file`, or else the single `--secret-key`, encrypting in the
+/// `--envelope` format with the `--algorithm`, and expiring after `--hash-ttl`.
+fn load_keyring(matches: &ArgMatches) -> Keyring {
+    let mut keyring = match matches.get_one::<String>("keyring-file") {
+        Some(keyring_file) => Keyring::from_file(keyring_file),
+        None => Ok(Keyring::from(
+            matches
+                .get_one::<SecretKey>("secret-key")
+                .expect("required")
+                .clone(),
+        )),
+    }
+    .unwrap_or_else(|err| panic!("{}", err));
+    match matches.get_one::<String>("envelope").expect("required").as_str() {
+        "v2" => keyring.set_envelope(Envelope::V2),
+        _ => keyring.set_envelope(Envelope::V1),
+    }
+    keyring.set_algorithm(*matches.get_one::<Algorithm>("algorithm").expect("required"));
+    keyring.set_ttl(
+        matches
+            .get_one::<u64>("hash-ttl")
+            .map(|ttl| Duration::from_secs(*ttl)),
+    );
+    keyring
+}
+
+async fn provision_topics(matches: &ArgMatches) {
+    let admin: AdminClient<DefaultClientContext> =
+        events::client_config(matches.get_one::<String>("broker-list").expect("required"))
+            .create()
+            .expect("Admin client creation error");
+
+    let mut topics = vec![matches
+        .get_one::<String>("topic")
+        .expect("required")
+        .as_str()];
+    if let Some(topic_document) = matches.get_one::<String>("topic-document") {
+        topics.push(topic_document);
+    }
+    let settings = TopicSettings {
+        partitions: *matches
+            .get_one::<i32>("topic-partitions")
+            .expect("required"),
+        replication_factor: *matches
+            .get_one::<i32>("topic-replication-factor")
+            .expect("required"),
+        retention_ms: *matches
+            .get_one::<i64>("topic-retention-ms")
+            .expect("required"),
+        parallelism: *matches
+            .get_one::<i32>("experiment-parallelism")
+            .expect("required"),
+    };
+    provision::provision_topics(&admin, &topics, &settings)
+        .await
+        .unwrap_or_else(|err| panic!("Topic provisioning failed: {}", err));
+    info!("Provisioned topics {:?}", topics);
+}
+
+/// With `tui`, the logs are only written to the log file, so they do not garble the dashboard.
+fn configure_tracing(tui: bool) -> WorkerGuard {
     let mut layers = vec![];
 
     let offset = UtcOffset::from_hms(2, 0, 0).expect("Should get CET offset");
@@ -150,13 +324,15 @@ fn configure_tracing() -> WorkerGuard {
             .boxed(),
     );
 
-    layers.push(
-        tracing_subscriber::fmt::layer()
-            .with_target(true)
-            .with_timer(timer)
-            .with_filter(LevelFilter::INFO)
-            .boxed(),
-    );
+    if !tui {
+        layers.push(
+            tracing_subscriber::fmt::layer()
+                .with_target(true)
+                .with_timer(timer)
+                .with_filter(LevelFilter::INFO)
+                .boxed(),
+        );
+    }
 
     tracing_subscriber::registry().with(layers).init();
     _guard
@@ -170,7 +346,37 @@ fn configure_cli() -> ArgMatches {
             .long("secret-key")
             .action(ArgAction::Set)
             .default_value("QJUHsPhnA0eiqHuJqsPgzhDozYO4f1zh")
-            .help("<key> is a 32 character string that must match the key being passed to the notifications-service")
+            .value_parser(SecretKey::from_str)
+            .help("<key> is a 32 character string, or `hex:<key>`, `base64:<key>`, `file:<path>` or `passphrase:<passphrase>`, that must match the key being passed to the notifications-service")
+        )
+        .arg(Arg::new("keyring-file")
+            .required(false)
+            .long("keyring-file")
+            .action(ArgAction::Set)
+            .help("JSON keyring used instead of --secret-key. Its active key encrypts the measurement hashes, prefixed with the key ID")
+        )
+        .arg(Arg::new("envelope")
+            .required(false)
+            .long("envelope")
+            .default_value("v1")
+            .action(ArgAction::Set)
+            .value_parser(["v1", "v2"])
+            .help("Format of the measurement hashes. `v2` is compact and only decrypts with the experiment and measurement IDs of its event")
+        )
+        .arg(Arg::new("algorithm")
+            .required(false)
+            .long("algorithm")
+            .default_value("aes-256-gcm")
+            .action(ArgAction::Set)
+            .value_parser(value_parser!(Algorithm))
+            .help("AEAD of the measurement hashes. The ChaCha20 algorithms are faster without AES hardware acceleration, and imply `--envelope v2`")
+        )
+        .arg(Arg::new("hash-ttl")
+            .required(false)
+            .long("hash-ttl")
+            .action(ArgAction::Set)
+            .value_parser(value_parser!(u64))
+            .help("Seconds after which the notifications-service rejects the notifications of a measurement as expired. Never when not set")
         )
         .arg(Arg::new("config-file")
             .required(false)
@@ -244,14 +450,130 @@ fn configure_cli() -> ArgMatches {
             .action(ArgAction::Set)
             .long("topic-document")
         )
+        .arg(Arg::new("trace-file")
+            .required(false)
+            .long("trace-file")
+            .action(ArgAction::Set)
+            .help("CSV file (timestamp,sensor,value[,phase]) of recorded temperatures to replay instead of simulated ones")
+        )
+        .arg(Arg::new("shift-trace")
+            .required(false)
+            .long("shift-trace")
+            .action(ArgAction::SetTrue)
+            .help("Shift the recorded temperatures so that they settle in the middle of the temperature range")
+        )
+        .arg(Arg::new("heartbeat-interval")
+            .required(false)
+            .long("heartbeat-interval")
+            .action(ArgAction::Set)
+            .value_parser(value_parser!(u64).range(1..))
+            .help("Milliseconds between experiment_heartbeat events. No heartbeats when not set")
+        )
+        .arg(Arg::new("stall-after")
+            .required(false)
+            .long("stall-after")
+            .action(ArgAction::Set)
+            .value_parser(value_parser!(u64))
+            .help("Simulate a stall after this many measurements")
+        )
+        .arg(Arg::new("stall-duration")
+            .required(false)
+            .long("stall-duration")
+            .action(ArgAction::Set)
+            .value_parser(value_parser!(u64))
+            .help("Milliseconds the stall of --stall-after lasts. The experiment never resumes when not set")
+        )
+        .arg(Arg::new("stall-mode")
+            .required(false)
+            .long("stall-mode")
+            .default_value("measurements")
+            .action(ArgAction::Set)
+            .value_parser(value_parser!(StallMode))
+            .help("`measurements` stops the measurements while heartbeats continue, `all` stops both")
+        )
+        .arg(Arg::new("tui")
+            .required(false)
+            .long("tui")
+            .action(ArgAction::SetTrue)
+            .help("Show a live dashboard of the experiments instead of the logs. Select with ↑/↓, abort the selected experiment with `a`, quit with `q`")
+        )
+        .arg(Arg::new("max-events-per-second")
+            .required(false)
+            .long("max-events-per-second")
+            .action(ArgAction::Set)
+            .value_parser(value_parser!(u64))
+            .help("Budget of sensor events per second shared by all experiments. Unlimited when not set")
+        )
+        .arg(Arg::new("budget-policy")
+            .required(false)
+            .long("budget-policy")
+            .default_value("delay")
+            .action(ArgAction::Set)
+            .value_parser(value_parser!(BudgetPolicy))
+            .help("`delay` postpones measurements (their timestamps slip), `queue` keeps the timestamps and queues the events")
+        )
+        .arg(Arg::new("chaos-rate")
+            .required(false)
+            .long("chaos-rate")
+            .default_value("0")
+            .action(ArgAction::Set)
+            .value_parser(value_parser!(f64))
+            .help("Fraction of the events followed by a faulty copy, to test how consumers cope with a dirty topic")
+        )
+        .arg(Arg::new("chaos-faults")
+            .required(false)
+            .long("chaos-faults")
+            .action(ArgAction::Set)
+            .value_delimiter(',')
+            .value_parser(value_parser!(Fault))
+            .help("Comma-separated faults injected by --chaos-rate. All of them when not set")
+        )
+        .arg(Arg::new("provision-topics")
+            .required(false)
+            .long("provision-topics")
+            .action(ArgAction::SetTrue)
+            .help("Create the missing topics and check the existing ones before producing")
+        )
+        .arg(Arg::new("topic-partitions")
+            .required(false)
+            .long("topic-partitions")
+            .default_value("3")
+            .action(ArgAction::Set)
+            .value_parser(value_parser!(i32))
+            .help("Partitions of the topics created by --provision-topics")
+        )
+        .arg(Arg::new("topic-replication-factor")
+            .required(false)
+            .long("topic-replication-factor")
+            .default_value("3")
+            .action(ArgAction::Set)
+            .value_parser(value_parser!(i32))
+            .help("Replication factor of the topics created by --provision-topics")
+        )
+        .arg(Arg::new("topic-retention-ms")
+            .required(false)
+            .long("topic-retention-ms")
+            .default_value("604800000")
+            .action(ArgAction::Set)
+            .value_parser(value_parser!(i64))
+            .help("retention.ms of the topics created by --provision-topics")
+        )
+        .arg(Arg::new("experiment-parallelism")
+            .required(false)
+            .long("experiment-parallelism")
+            .default_value("1")
+            .action(ArgAction::Set)
+            .value_parser(value_parser!(i32))
+            .help("Minimum number of partitions every topic must have, so experiments can be consumed in parallel")
+        )
         .get_matches()
 }
 
 #[tokio::main]
 async fn main() {
     dotenv::from_filename("experiment-producer/.env").expect(".env file should exist");
-    let _guard = configure_tracing();
     let mut matches = configure_cli();
+    let _guard = configure_tracing(matches.get_flag("tui"));
 
     let pool = match env::var("DATABASE_URL") {
         Ok(database_url) => {
@@ -268,13 +590,66 @@ async fn main() {
         _ => None,
     };
 
+    if matches.get_flag("provision-topics") {
+        provision_topics(&matches).await;
+    }
+
     let metrics = Metrics::new();
     let metric_server = MetricServer::new(metrics.clone());
     metric_server.start();
 
-    if let Some(config_file) = matches.remove_one::<String>("config-file") {
-        run_multiple_experiments(matches, &config_file, pool, metrics).await
-    } else {
-        run_single_experiment(matches, pool, metrics).await
-    }
+    let budget = EventBudget::new(
+        matches.remove_one::<u64>("max-events-per-second"),
+        matches
+            .remove_one::<BudgetPolicy>("budget-policy")
+            .expect("required"),
+        metrics.event_backlog.clone(),
+    );
+
+    let chaos = Chaos::new(
+        matches.remove_one::<f64>("chaos-rate").expect("required"),
+        matches
+            .remove_many::<Fault>("chaos-faults")
+            .map(|faults| faults.collect())
+            .unwrap_or_default(),
+    );
+
+    let dashboard = matches.get_flag("tui").then(Dashboard::new);
+    let experiments = {
+        let dashboard = dashboard.clone();
+        async move {
+            if let Some(config_file) = matches.remove_one::<String>("config-file") {
+                run_multiple_experiments(
+                    matches,
+                    &config_file,
+                    pool,
+                    metrics,
+                    budget,
+                    chaos,
+                    dashboard,
+                )
+                .await
+            } else {
+                run_single_experiment(matches, pool, metrics, budget, chaos, dashboard).await
+            }
+        }
+    };
+    let Some(dashboard) = dashboard else {
+        experiments.await;
+        return;
+    };
+
+    // The dashboard stays up once the experiments are done, and quitting it early stops them.
+    let mut ui = tokio::task::spawn_blocking(move || dashboard.show());
+    let closed = tokio::select! {
+        result = &mut ui => Some(result),
+        _ = experiments => None,
+    };
+    let result = match closed {
+        Some(result) => result,
+        None => ui.await,
+    };
+    result
+        .expect("Dashboard should not panic")
+        .expect("Dashboard should render");
 }
@@ -1,7 +1,7 @@
 use actix_web::{get, web::Data, App, HttpServer, Responder};
 use prometheus_client::{
     encoding::{text, EncodeLabelSet},
-    metrics::{counter::Counter, family::Family},
+    metrics::{counter::Counter, family::Family, gauge::Gauge},
     registry::Registry,
 };
 use std::sync::Mutex;
@@ -13,15 +13,25 @@ pub struct EventCountLabels {
     pub topic: String,
 }
 
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+pub struct FaultCountLabels {
+    pub fault: String,
+    pub topic: String,
+}
+
 #[derive(Clone)]
 pub struct Metrics {
     pub event_count: Family<EventCountLabels, Counter>,
+    pub event_backlog: Gauge,
+    pub fault_count: Family<FaultCountLabels, Counter>,
 }
 
 impl Metrics {
     pub fn new() -> Self {
         Self {
             event_count: Family::<EventCountLabels, Counter>::default(),
+            event_backlog: Gauge::default(),
+            fault_count: Family::<FaultCountLabels, Counter>::default(),
         }
     }
 }
@@ -38,6 +48,16 @@ impl MetricServer {
             "Count of events produced",
             metrics.event_count.clone(),
         );
+        registry.register(
+            "experiment_producer_event_backlog",
+            "Events waiting for the global event budget",
+            metrics.event_backlog.clone(),
+        );
+        registry.register(
+            "experiment_producer_fault_count",
+            "Count of faulty events injected",
+            metrics.fault_count.clone(),
+        );
         Self { registry }
     }
 
@@ -0,0 +1,251 @@
+use rdkafka::{
+    admin::{AdminClient, AdminOptions, NewTopic, ResourceSpecifier, TopicReplication},
+    client::DefaultClientContext,
+    error::RDKafkaErrorCode,
+};
+use std::time::Duration;
+use tracing::{info, warn};
+
+const ADMIN_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Settings the topics used by the producer are expected to have.
+#[derive(Clone, Debug)]
+pub struct TopicSettings {
+    pub partitions: i32,
+    pub replication_factor: i32,
+    pub retention_ms: i64,
+    /// Number of experiments that should be consumable in parallel, i.e. the minimum
+    /// number of partitions of every topic.
+    pub parallelism: i32,
+}
+
+/// Creates the missing `topics` and checks the ones that already exist.
+///
+/// Topics with fewer partitions than `settings.parallelism` are an error, both the ones
+/// to create and the existing ones. A differing replication factor or retention of an
+/// existing topic is only reported.
+pub async fn provision_topics(
+    admin: &AdminClient<DefaultClientContext>,
+    topics: &[&str],
+    settings: &TopicSettings,
+) -> Result<(), String> {
+    // Metadata for all topics, so that requesting it does not auto-create the ones
+    // we are looking for.
+    let metadata = admin
+        .inner()
+        .fetch_metadata(None, ADMIN_TIMEOUT)
+        .map_err(|err| format!("Could not fetch cluster metadata: {}", err))?;
+
+    let mut missing = vec![];
+    let mut existing = vec![];
+    for &topic in topics {
+        match metadata.topics().iter().find(|t| t.name() == topic) {
+            Some(metadata_topic) => {
+                let partitions = metadata_topic.partitions().len() as i32;
+                if partitions < settings.parallelism {
+                    return Err(format!(
+                        "Topic `{}` has {} partitions, fewer than the requested parallelism of {}",
+                        topic, partitions, settings.parallelism
+                    ));
+                }
+                let replication_factor = metadata_topic
+                    .partitions()
+                    .iter()
+                    .map(|partition| partition.replicas().len() as i32)
+                    .min()
+                    .unwrap_or_default();
+                if replication_factor != settings.replication_factor {
+                    warn!(
+                        topic,
+                        "Topic has replication factor {}, expected {}",
+                        replication_factor,
+                        settings.replication_factor
+                    );
+                }
+                existing.push(topic);
+            }
+            None => missing.push(topic),
+        }
+    }
+    if !missing.is_empty() && settings.partitions < settings.parallelism {
+        return Err(format!(
+            "Topics {:?} would be created with {} partitions, fewer than the requested parallelism of {}",
+            missing, settings.partitions, settings.parallelism
+        ));
+    }
+
+    let options = AdminOptions::new().operation_timeout(Some(ADMIN_TIMEOUT));
+    create_topics(admin, &options, &missing, settings).await?;
+    check_retention(admin, &options, &existing, settings).await;
+    Ok(())
+}
+
+async fn create_topics(
+    admin: &AdminClient<DefaultClientContext>,
+    options: &AdminOptions,
+    topics: &[&str],
+    settings: &TopicSettings,
+) -> Result<(), String> {
+    if topics.is_empty() {
+        return Ok(());
+    }
+    let retention_ms = settings.retention_ms.to_string();
+    let new_topics = new_topics(topics, settings, &retention_ms);
+    let results = admin
+        .create_topics(&new_topics, options)
+        .await
+        .map_err(|err| format!("Could not create topics {:?}: {}", topics, err))?;
+    for result in results {
+        match result {
+            Ok(topic) => info!(topic, "Created topic"),
+            Err((topic, RDKafkaErrorCode::TopicAlreadyExists)) => {
+                info!(topic, "Topic was created concurrently")
+            }
+            Err((topic, code)) => {
+                return Err(format!("Could not create topic `{}`: {}", topic, code))
+            }
+        }
+    }
+    Ok(())
+}
+
+fn new_topics<'a>(
+    topics: &[&'a str],
+    settings: &TopicSettings,
+    retention_ms: &'a str,
+) -> Vec<NewTopic<'a>> {
+    topics
+        .iter()
+        .map(|topic| {
+            NewTopic::new(
+                topic,
+                settings.partitions,
+                TopicReplication::Fixed(settings.replication_factor),
+            )
+            .set("retention.ms", retention_ms)
+        })
+        .collect()
+}
+
+async fn check_retention(
+    admin: &AdminClient<DefaultClientContext>,
+    options: &AdminOptions,
+    topics: &[&str],
+    settings: &TopicSettings,
+) {
+    if topics.is_empty() {
+        return;
+    }
+    let resources: Vec<_> = topics
+        .iter()
+        .map(|topic| ResourceSpecifier::Topic(topic))
+        .collect();
+    let results = match admin.describe_configs(&resources, options).await {
+        Ok(results) => results,
+        Err(err) => {
+            warn!("Could not describe topics {:?}: {}", topics, err);
+            return;
+        }
+    };
+
+    let expected = settings.retention_ms.to_string();
+    for (topic, result) in topics.iter().zip(results) {
+        let retention_ms = match result {
+            Ok(resource) => resource
+                .get("retention.ms")
+                .and_then(|entry| entry.value.clone()),
+            Err(code) => {
+                warn!(topic, "Could not describe topic: {}", code);
+                continue;
+            }
+        };
+        if retention_ms.as_deref() != Some(expected.as_str()) {
+            warn!(
+                topic,
+                "Topic has retention.ms {:?}, expected {}", retention_ms, expected
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rdkafka::config::ClientConfig;
+
+    /// Admin client connected to a mock cluster owned by the client itself. The mock
+    /// cluster does not answer DescribeConfigs, so requests time out early.
+    fn mock_admin() -> AdminClient<DefaultClientContext> {
+        ClientConfig::new()
+            .set("test.mock.num.brokers", "3")
+            .set("socket.timeout.ms", "100")
+            .create()
+            .unwrap()
+    }
+
+    /// Requesting metadata for a single topic makes the mock cluster auto-create it
+    /// with 4 partitions.
+    fn create_mock_topic(admin: &AdminClient<DefaultClientContext>, topic: &str) {
+        admin
+            .inner()
+            .fetch_metadata(Some(topic), ADMIN_TIMEOUT)
+            .unwrap();
+    }
+
+    fn settings(partitions: i32, parallelism: i32) -> TopicSettings {
+        TopicSettings {
+            partitions,
+            replication_factor: 3,
+            retention_ms: 604_800_000,
+            parallelism,
+        }
+    }
+
+    #[tokio::test]
+    async fn existing_topic_with_enough_partitions() {
+        let admin = mock_admin();
+        create_mock_topic(&admin, "experiment");
+        provision_topics(&admin, &["experiment"], &settings(4, 4))
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn existing_topic_with_too_few_partitions() {
+        let admin = mock_admin();
+        create_mock_topic(&admin, "experiment");
+        let err = provision_topics(&admin, &["experiment"], &settings(8, 8))
+            .await
+            .unwrap_err();
+        assert!(err.contains("`experiment` has 4 partitions"));
+    }
+
+    /// The mock cluster does not implement CreateTopics, so only the request is checked.
+    #[test]
+    fn missing_topic_is_created_with_settings() {
+        let new_topics = new_topics(&["experiment"], &settings(6, 4), "604800000");
+        assert_eq!(new_topics.len(), 1);
+        assert_eq!(new_topics[0].name, "experiment");
+        assert_eq!(new_topics[0].num_partitions, 6);
+        assert!(matches!(
+            new_topics[0].replication,
+            TopicReplication::Fixed(3)
+        ));
+        assert_eq!(new_topics[0].config, [("retention.ms", "604800000")]);
+    }
+
+    #[tokio::test]
+    async fn partitions_below_parallelism() {
+        let admin = mock_admin();
+        let err = provision_topics(&admin, &["experiment"], &settings(2, 4))
+            .await
+            .unwrap_err();
+        assert!(err.contains("fewer than the requested parallelism"));
+
+        // Only the partitions of topics to create are checked against the parallelism
+        create_mock_topic(&admin, "experiment");
+        provision_topics(&admin, &["experiment"], &settings(2, 4))
+            .await
+            .unwrap();
+    }
+}
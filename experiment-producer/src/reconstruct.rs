@@ -0,0 +1,269 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::BufRead;
+
+/// A line of `producer.json.log`, as written by the JSON layer of `configure_tracing`.
+#[derive(Debug, Deserialize)]
+struct LogLine {
+    timestamp: String,
+    #[serde(default)]
+    fields: Fields,
+    #[serde(default)]
+    spans: Vec<SpanFields>,
+}
+
+/// The fields of the events the simulator logs.
+#[derive(Debug, Default, Deserialize)]
+struct Fields {
+    stage: Option<String>,
+    avg_temperature: Option<f32>,
+    sensor: Option<String>,
+    temperature: Option<f32>,
+    range_event: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct SpanFields {
+    name: String,
+    experiment_id: Option<String>,
+    room: Option<String>,
+    measurement_id: Option<String>,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+pub struct StageChange {
+    pub timestamp: String,
+    pub stage: String,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+pub struct SensorReading {
+    pub sensor: String,
+    pub temperature: f32,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+pub struct Measurement {
+    pub measurement_id: String,
+    /// Timestamp of the first line logged for the measurement.
+    pub timestamp: String,
+    pub stage: Option<String>,
+    pub avg_temperature: Option<f32>,
+    pub sensors: Vec<SensorReading>,
+    /// Notification the measurement is expected to trigger (`Stabilized` or `OutOfRange`).
+    pub notification: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Experiment {
+    pub experiment_id: String,
+    pub room: Option<String>,
+    pub timeline: Vec<StageChange>,
+    pub measurements: Vec<Measurement>,
+    /// The average temperature is logged before the measurement's span is entered.
+    #[serde(skip)]
+    pending_avg_temperature: Option<f32>,
+    #[serde(skip)]
+    measurement_index: HashMap<String, usize>,
+}
+
+impl Experiment {
+    fn new(experiment_id: String, room: Option<String>) -> Self {
+        Self {
+            experiment_id,
+            room,
+            timeline: Vec::new(),
+            measurements: Vec::new(),
+            pending_avg_temperature: None,
+            measurement_index: HashMap::new(),
+        }
+    }
+
+    fn measurement(&mut self, measurement_id: &str, timestamp: &str) -> &mut Measurement {
+        let idx = match self.measurement_index.get(measurement_id) {
+            Some(idx) => *idx,
+            None => {
+                self.measurements.push(Measurement {
+                    measurement_id: measurement_id.into(),
+                    timestamp: timestamp.into(),
+                    stage: self.timeline.last().map(|change| change.stage.clone()),
+                    avg_temperature: self.pending_avg_temperature.take(),
+                    sensors: Vec::new(),
+                    notification: None,
+                });
+                self.measurement_index
+                    .insert(measurement_id.into(), self.measurements.len() - 1);
+                self.measurements.len() - 1
+            }
+        };
+        &mut self.measurements[idx]
+    }
+
+    /// Measurements that trigger a notification, i.e. the run's ground truth.
+    pub fn expected_notifications(&self) -> impl Iterator<Item = &Measurement> {
+        self.measurements
+            .iter()
+            .filter(|measurement| measurement.notification.is_some())
+    }
+}
+
+/// Rebuilds the experiments of a run from its JSON logs.
+#[derive(Default)]
+pub struct Reconstruction {
+    experiments: Vec<Experiment>,
+    index: HashMap<String, usize>,
+    skipped_lines: usize,
+}
+
+impl Reconstruction {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Reads the lines of a log file. Lines that are not JSON log lines are skipped.
+    pub fn read(&mut self, reader: impl BufRead) -> Result<(), String> {
+        for line in reader.lines() {
+            let line = line.map_err(|err| format!("Could not read log: {}", err))?;
+            match serde_json::from_str::<LogLine>(&line) {
+                Ok(line) => self.apply(line),
+                Err(_) => self.skipped_lines += 1,
+            }
+        }
+        Ok(())
+    }
+
+    pub fn skipped_lines(&self) -> usize {
+        self.skipped_lines
+    }
+
+    fn apply(&mut self, line: LogLine) {
+        let Some(experiment_span) = line
+            .spans
+            .iter()
+            .find(|span| span.name == "experiment" && span.experiment_id.is_some())
+        else {
+            return;
+        };
+        let experiment_id = experiment_span.experiment_id.clone().expect("checked");
+        let idx = *self.index.entry(experiment_id.clone()).or_insert_with(|| {
+            self.experiments
+                .push(Experiment::new(experiment_id, experiment_span.room.clone()));
+            self.experiments.len() - 1
+        });
+        let experiment = &mut self.experiments[idx];
+
+        if let Some(stage) = line.fields.stage {
+            experiment.timeline.push(StageChange {
+                timestamp: line.timestamp.clone(),
+                stage,
+            });
+        }
+        let measurement_id = line
+            .spans
+            .iter()
+            .find(|span| span.name == "measurement")
+            .and_then(|span| span.measurement_id.as_deref());
+        let Some(measurement_id) = measurement_id else {
+            if line.fields.avg_temperature.is_some() {
+                experiment.pending_avg_temperature = line.fields.avg_temperature;
+            }
+            return;
+        };
+
+        let measurement = experiment.measurement(measurement_id, &line.timestamp);
+        if let (Some(sensor), Some(temperature)) = (line.fields.sensor, line.fields.temperature) {
+            measurement.sensors.push(SensorReading {
+                sensor,
+                temperature,
+            });
+        }
+        if line.fields.range_event.is_some() {
+            measurement.notification = line.fields.range_event;
+        }
+    }
+
+    pub fn experiments(&self) -> &[Experiment] {
+        &self.experiments
+    }
+
+    /// Keeps only the given experiment.
+    pub fn retain_experiment(&mut self, experiment_id: &str) {
+        self.experiments
+            .retain(|experiment| experiment.experiment_id == experiment_id);
+    }
+}
+
+/// Flat view of a measurement for the CSV export.
+#[derive(Serialize)]
+pub struct MeasurementRow<'a> {
+    pub experiment_id: &'a str,
+    pub measurement_id: &'a str,
+    pub timestamp: &'a str,
+    pub stage: Option<&'a str>,
+    pub avg_temperature: Option<f32>,
+    pub notification: Option<&'a str>,
+}
+
+impl<'a> MeasurementRow<'a> {
+    pub fn new(experiment: &'a Experiment, measurement: &'a Measurement) -> Self {
+        Self {
+            experiment_id: &experiment.experiment_id,
+            measurement_id: &measurement.measurement_id,
+            timestamp: &measurement.timestamp,
+            stage: measurement.stage.as_deref(),
+            avg_temperature: measurement.avg_temperature,
+            notification: measurement.notification.as_deref(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LOG: &str = r#"{"timestamp":"2023-10-07T15:53:18.000001+02","level":"INFO","fields":{"stage":"configuration"},"target":"experiment_producer::simulator","span":{"experiment_id":"e1","name":"experiment"},"spans":[{"experiment_id":"e1","name":"experiment"}]}
+{"timestamp":"2023-10-07T15:53:20.000001+02","level":"INFO","fields":{"stage":"stabilization"},"target":"experiment_producer::simulator","span":{"experiment_id":"e1","name":"experiment"},"spans":[{"experiment_id":"e1","name":"experiment"}]}
+{"timestamp":"2023-10-07T15:53:20.000002+02","level":"INFO","fields":{"avg_temperature":25.9},"target":"experiment_producer::simulator","span":{"experiment_id":"e1","name":"experiment"},"spans":[{"experiment_id":"e1","name":"experiment"}]}
+{"timestamp":"2023-10-07T15:53:20.000003+02","level":"INFO","fields":{"range_event":"Stabilized"},"target":"experiment_producer::events","span":{"measurement_id":"m1","name":"measurement"},"spans":[{"experiment_id":"e1","name":"experiment"},{"measurement_id":"m1","name":"measurement"}]}
+{"timestamp":"2023-10-07T15:53:20.000004+02","level":"INFO","fields":{"sensor":"s1","temperature":25.5},"target":"experiment_producer::simulator","span":{"measurement_id":"m1","name":"measurement"},"spans":[{"experiment_id":"e1","name":"experiment"},{"measurement_id":"m1","name":"measurement"}]}
+not a log line
+{"timestamp":"2023-10-07T15:53:20.000005+02","level":"INFO","fields":{"sensor":"s2","temperature":26.3},"target":"experiment_producer::simulator","span":{"measurement_id":"m1","name":"measurement"},"spans":[{"experiment_id":"e1","name":"experiment"},{"measurement_id":"m1","name":"measurement"}]}
+{"timestamp":"2023-10-07T15:53:20.100000+02","level":"INFO","fields":{"avg_temperature":26.0},"target":"experiment_producer::simulator","span":{"experiment_id":"e1","name":"experiment"},"spans":[{"experiment_id":"e1","name":"experiment"}]}
+{"timestamp":"2023-10-07T15:53:20.100001+02","level":"INFO","fields":{"sensor":"s1","temperature":26.0},"target":"experiment_producer::simulator","span":{"measurement_id":"m2","name":"measurement"},"spans":[{"experiment_id":"e1","name":"experiment"},{"measurement_id":"m2","name":"measurement"}]}
+{"timestamp":"2023-10-07T15:53:20.100002+02","level":"INFO","fields":{"message":"Created connection pool to database"},"target":"experiment_producer"}
+"#;
+
+    #[test]
+    fn rebuilds_experiment() {
+        let mut reconstruction = Reconstruction::new();
+        reconstruction.read(LOG.as_bytes()).unwrap();
+        assert_eq!(reconstruction.skipped_lines(), 1);
+
+        let experiments = reconstruction.experiments();
+        assert_eq!(experiments.len(), 1);
+        let experiment = &experiments[0];
+        assert_eq!(experiment.experiment_id, "e1");
+        let stages: Vec<_> = experiment
+            .timeline
+            .iter()
+            .map(|change| change.stage.as_str())
+            .collect();
+        assert_eq!(stages, vec!["configuration", "stabilization"]);
+
+        assert_eq!(experiment.measurements.len(), 2);
+        let m1 = &experiment.measurements[0];
+        assert_eq!(m1.avg_temperature, Some(25.9));
+        assert_eq!(m1.stage.as_deref(), Some("stabilization"));
+        assert_eq!(m1.sensors.len(), 2);
+        assert_eq!(m1.notification.as_deref(), Some("Stabilized"));
+        let m2 = &experiment.measurements[1];
+        assert_eq!(m2.avg_temperature, Some(26.0));
+        assert_eq!(m2.notification, None);
+
+        let expected: Vec<_> = experiment
+            .expected_notifications()
+            .map(|measurement| measurement.measurement_id.as_str())
+            .collect();
+        assert_eq!(expected, vec!["m1"]);
+    }
+}
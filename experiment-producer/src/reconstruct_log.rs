@@ -0,0 +1,122 @@
+use clap::{command, value_parser, Arg, ArgAction, ValueEnum};
+use sqlx::postgres::PgPoolOptions;
+use std::{env, fs::File, io, io::BufReader, io::Write};
+
+mod database;
+mod reconstruct;
+
+use reconstruct::{MeasurementRow, Reconstruction};
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum Format {
+    /// Experiments with their timeline, measurements and sensor readings.
+    Json,
+    /// One row per measurement.
+    Csv,
+}
+
+#[tokio::main]
+async fn main() {
+    let mut matches = command!() // requires `cargo` feature
+        .next_line_help(true)
+        .about("Rebuilds the experiments of a past run from producer.json.log files")
+        .arg(
+            Arg::new("log-file")
+                .required(true)
+                .action(ArgAction::Append)
+                .help("JSON log files written by the experiment-producer, e.g. producer.json.log.2023-10-07"),
+        )
+        .arg(
+            Arg::new("experiment")
+                .required(false)
+                .long("experiment")
+                .action(ArgAction::Set)
+                .help("Only reconstruct this experiment"),
+        )
+        .arg(
+            Arg::new("format")
+                .required(false)
+                .long("format")
+                .default_value("json")
+                .action(ArgAction::Set)
+                .value_parser(value_parser!(Format)),
+        )
+        .arg(
+            Arg::new("output")
+                .required(false)
+                .long("output")
+                .action(ArgAction::Set)
+                .help("File the reconstruction is written to. Standard output when not set"),
+        )
+        .arg(
+            Arg::new("load-ground-truth")
+                .required(false)
+                .long("load-ground-truth")
+                .action(ArgAction::SetTrue)
+                .help("Insert the expected notifications into demo.notification_ground_truth (DATABASE_URL)"),
+        )
+        .get_matches();
+
+    let mut reconstruction = Reconstruction::new();
+    for log_file in matches.remove_many::<String>("log-file").expect("required") {
+        let file = File::open(&log_file)
+            .unwrap_or_else(|err| panic!("Could not read file `{}`: {}", log_file, err));
+        reconstruction
+            .read(BufReader::new(file))
+            .unwrap_or_else(|err| panic!("{}", err));
+    }
+    if reconstruction.skipped_lines() > 0 {
+        eprintln!("Skipped {} lines", reconstruction.skipped_lines());
+    }
+    if let Some(experiment_id) = matches.remove_one::<String>("experiment") {
+        reconstruction.retain_experiment(&experiment_id);
+    }
+
+    let output: Box<dyn Write> = match matches.remove_one::<String>("output") {
+        Some(output) => Box::new(File::create(&output).expect("Could not create output file")),
+        None => Box::new(io::stdout()),
+    };
+    match matches.remove_one::<Format>("format").expect("required") {
+        Format::Json => serde_json::to_writer_pretty(output, reconstruction.experiments())
+            .expect("Could not write reconstruction"),
+        Format::Csv => {
+            let mut writer = csv::Writer::from_writer(output);
+            for experiment in reconstruction.experiments() {
+                for measurement in &experiment.measurements {
+                    writer
+                        .serialize(MeasurementRow::new(experiment, measurement))
+                        .expect("Could not write reconstruction");
+                }
+            }
+            writer.flush().expect("Could not write reconstruction");
+        }
+    }
+
+    if matches.get_flag("load-ground-truth") {
+        dotenv::from_filename("experiment-producer/.env").ok();
+        let database_url = env::var("DATABASE_URL").expect("DATABASE_URL should be set");
+        let pool = PgPoolOptions::new()
+            .max_connections(5)
+            .connect(&database_url)
+            .await
+            .expect("Unable to connect to database provided in DATABASE_URL");
+        let mut inserted = 0;
+        for experiment in reconstruction.experiments() {
+            for measurement in experiment.expected_notifications() {
+                database::insert_ground_truth(
+                    &pool,
+                    &experiment.experiment_id,
+                    &measurement.measurement_id,
+                    measurement
+                        .notification
+                        .as_deref()
+                        .expect("expected notifications have a type"),
+                )
+                .await
+                .expect("Insert should not fail");
+                inserted += 1;
+            }
+        }
+        eprintln!("Loaded {} expected notifications", inserted);
+    }
+}
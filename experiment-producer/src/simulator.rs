@@ -8,13 +8,19 @@ use tokio::{task::JoinHandle, time};
 use tracing::{info, Instrument, Span};
 use uuid::Uuid;
 
-use event_hash::NotificationType;
+use event_hash::{Keyring, NotificationType};
 
 use crate::config::{ConfigEntry, UncheckedTempRange};
+use crate::dashboard::StatusHandle;
 use crate::database;
-use crate::events::{self, EventWrapper, KafkaTopicProducer, RecordData};
+use crate::events::{self, EventQueue, EventWrapper, KafkaTopicProducer, RecordData};
+use crate::heartbeat::{Heartbeat, Stall};
+use crate::trace::Trace;
 
-#[derive(Clone, Copy)]
+/// Pause between the configuration and stabilization stages.
+pub const CONFIGURATION_WAIT: Duration = Duration::from_millis(2000);
+
+#[derive(Clone, Copy, PartialEq)]
 pub enum ExperimentStage {
     Uninitialized,
     Configuration,
@@ -23,6 +29,18 @@ pub enum ExperimentStage {
     Terminated,
 }
 
+impl ExperimentStage {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ExperimentStage::Uninitialized => "uninitialized",
+            ExperimentStage::Configuration => "configuration",
+            ExperimentStage::Stabilization => "stabilization",
+            ExperimentStage::CarryOut => "carry_out",
+            ExperimentStage::Terminated => "terminated",
+        }
+    }
+}
+
 #[derive(Clone, Copy, Debug, Deserialize)]
 #[serde(try_from = "UncheckedTempRange")]
 pub struct TempRange {
@@ -57,6 +75,9 @@ impl TempRange {
     }
 }
 
+/// Average temperatures of an experiment stage.
+pub type Samples<'a> = Box<dyn ExactSizeIterator<Item = TemperatureSample> + Send + 'a>;
+
 #[derive(Clone, Copy, Debug)]
 pub struct TemperatureSample {
     cur: f32,
@@ -64,6 +85,21 @@ pub struct TemperatureSample {
 }
 
 impl TemperatureSample {
+    pub fn new(cur: f32, temp_range: TempRange) -> Self {
+        Self { cur, temp_range }
+    }
+
+    /// Moves a sample lying on a threshold slightly into the range, so that it is not
+    /// ambiguous whether it is out of range.
+    pub fn away_from_thresholds(mut self) -> Self {
+        if (self.cur - self.temp_range.upper_threshold).abs() <= 0.01 {
+            self.cur = self.temp_range.upper_threshold - 0.011;
+        } else if (self.cur - self.temp_range.lower_threshold).abs() <= 0.01 {
+            self.cur = self.temp_range.lower_threshold + 0.011;
+        }
+        self
+    }
+
     pub fn is_out_of_range(&self) -> bool {
         self.cur > self.temp_range.upper_threshold || self.cur < self.temp_range.lower_threshold
     }
@@ -110,7 +146,7 @@ pub struct ExperimentConfiguration {
     temp_range: TempRange,
     stabilization_samples: u16,
     carry_out_samples: u16,
-    secret_key: String,
+    keyring: Keyring,
     topic: String,
     topic_document: Option<String>,
 }
@@ -118,18 +154,15 @@ pub struct ExperimentConfiguration {
 impl ExperimentConfiguration {
     pub fn new(
         researcher: String,
-        num_sensors: usize,
+        sensors: Vec<String>,
         sample_rate: u64,
         temp_range: TempRange,
         stabilization_samples: u16,
         carry_out_samples: u16,
-        secret_key: String,
+        keyring: Keyring,
         topic: String,
         topic_document: Option<String>,
     ) -> Self {
-        let sensors: Vec<_> = (0..num_sensors)
-            .map(|_| format!("{}", Uuid::new_v4()))
-            .collect();
         Self {
             experiment_id: format!("{}", Uuid::new_v4()),
             researcher,
@@ -138,11 +171,26 @@ impl ExperimentConfiguration {
             temp_range,
             stabilization_samples,
             carry_out_samples,
-            secret_key,
+            keyring,
             topic,
             topic_document,
         }
     }
+
+    pub fn random_sensors(num_sensors: usize) -> Vec<String> {
+        (0..num_sensors)
+            .map(|_| format!("{}", Uuid::new_v4()))
+            .collect()
+    }
+
+    pub fn temp_range(&self) -> TempRange {
+        self.temp_range
+    }
+
+    /// Number of measurements over the stabilization and carry out stages.
+    pub fn num_samples(&self) -> u64 {
+        u64::from(self.stabilization_samples) + u64::from(self.carry_out_samples)
+    }
 }
 
 impl From<ConfigEntry> for ExperimentConfiguration {
@@ -155,19 +203,24 @@ impl From<ConfigEntry> for ExperimentConfiguration {
             stabilization_samples,
             carry_out_samples,
             start_time: _,
-            secret_key,
+            keyring,
             start_temperature: _,
+            trace_file: _,
+            shift_trace: _,
+            heartbeat_interval_ms: _,
+            stall: _,
             topic,
             topic_document,
+            sensors,
         } = config_entry;
         Self::new(
             researcher,
-            num_sensors,
+            sensors.unwrap_or_else(|| Self::random_sensors(num_sensors)),
             sample_rate,
             temp_range,
             stabilization_samples,
             carry_out_samples,
-            secret_key,
+            keyring.expect("Keyring should be set"),
             topic,
             topic_document,
         )
@@ -176,11 +229,22 @@ impl From<ConfigEntry> for ExperimentConfiguration {
 
 pub struct Experiment {
     sample: TemperatureSample,
+    /// Recorded temperatures replacing the synthetic `sample` when set.
+    trace: Option<Trace>,
+    num_measurements: u64,
     measurements: Vec<Measurement>,
     stage: ExperimentStage,
     config: ExperimentConfiguration,
     producer: KafkaTopicProducer,
     pool: Option<Pool<Postgres>>,
+    /// Sensor events still queued on the event budget.
+    queued_events: Option<EventQueue>,
+    heartbeat_interval: Option<Duration>,
+    heartbeat: Option<Heartbeat>,
+    /// Simulated stall of the measurements (and heartbeats).
+    stall: Option<Stall>,
+    /// Row of the experiment on the `--tui` dashboard.
+    status: Option<StatusHandle>,
 }
 
 impl Experiment {
@@ -190,22 +254,77 @@ impl Experiment {
         producer: KafkaTopicProducer,
         pool: Option<Pool<Postgres>>,
     ) -> Self {
-        let sample = TemperatureSample {
-            cur: start,
-            temp_range: config.temp_range,
-        };
+        let sample = TemperatureSample::new(start, config.temp_range);
         Experiment {
             stage: ExperimentStage::Uninitialized,
+            num_measurements: 0,
             measurements: Vec::new(),
             sample,
+            trace: None,
             producer,
             config,
             pool,
+            queued_events: None,
+            heartbeat_interval: None,
+            heartbeat: None,
+            stall: None,
+            status: None,
+        }
+    }
+
+    /// Sends an `experiment_heartbeat` event every `interval` until the experiment terminates.
+    pub fn enable_heartbeat(&mut self, interval: Duration) {
+        self.heartbeat_interval = Some(interval);
+    }
+
+    pub fn set_stall(&mut self, stall: Stall) {
+        self.stall = Some(stall);
+    }
+
+    pub fn set_status(&mut self, status: StatusHandle) {
+        self.status = Some(status);
+    }
+
+    fn set_stage(&mut self, stage: ExperimentStage) {
+        self.stage = stage;
+        if let Some(heartbeat) = &self.heartbeat {
+            heartbeat.set_stage(stage);
+        }
+        if let Some(status) = &self.status {
+            status.set_stage(stage);
+        }
+    }
+
+    /// Replays the recorded temperatures of a CSV trace instead of simulating them.
+    pub fn load_trace(&mut self, file_path: &str, shift: bool) -> Result<(), String> {
+        self.trace = Some(Trace::from_file(
+            file_path,
+            self.config.sample_rate,
+            self.config.temp_range,
+            shift,
+        )?);
+        Ok(())
+    }
+
+    /// Starts the queue of the sensor events when the budget queues them.
+    fn start_queue(&mut self) {
+        if self.producer.budget().queues() && self.queued_events.is_none() {
+            self.queued_events = Some(EventQueue::new(
+                self.producer.clone(),
+                self.config.topic.clone(),
+            ));
+        }
+    }
+
+    /// Waits until the queued sensor events are sent, so they precede the next stage's event.
+    async fn flush_queued_events(&mut self) {
+        if let Some(queue) = self.queued_events.take() {
+            queue.flush().await;
         }
     }
 
     async fn stage_configuration(&mut self) {
-        self.stage = ExperimentStage::Configuration;
+        self.set_stage(ExperimentStage::Configuration);
         let record = RecordData {
             payload: events::experiment_configured_event(
                 &self.config.experiment_id,
@@ -223,7 +342,7 @@ impl Experiment {
     }
 
     async fn stage_stabilization(&mut self) {
-        self.stage = ExperimentStage::Stabilization;
+        self.set_stage(ExperimentStage::Stabilization);
         let record = RecordData {
             payload: events::stabilization_started_event(&self.config.experiment_id),
             key: Some(&self.config.experiment_id),
@@ -235,22 +354,40 @@ impl Experiment {
             .expect("Failed to produce message");
 
         // Stabilization Temperature Samples
-        let stabilization_samples = self
-            .sample
-            .stabilization_samples(self.config.stabilization_samples.into());
-        let stabilization_events = events::temperature_events(
+        self.start_queue();
+        let len = self.config.stabilization_samples.into();
+        let stabilization_samples: Samples = match &self.trace {
+            Some(trace) => Box::new(trace.stabilization_samples(len, self.config.temp_range)),
+            None => Box::new(self.sample.stabilization_samples(len)),
+        };
+        let first_measurement = self.num_measurements;
+        let mut stabilization_events = events::temperature_events(
             stabilization_samples,
+            &mut self.num_measurements,
             &self.config.experiment_id,
             &self.config.researcher,
             &self.config.sensors,
             &self.stage,
-            &self.config.secret_key,
+            &self.config.keyring,
         );
 
-        for (sensor_events, _span, measurement) in stabilization_events {
+        for i in 0..stabilization_events.len() as u64 {
+            if let Some(stall) = &self.stall {
+                stall
+                    .wait(first_measurement + i, self.heartbeat.as_ref())
+                    .await;
+            }
+            self.producer
+                .budget()
+                .delay_measurement(self.config.sensors.len() as u64)
+                .await;
+            let (sensor_events, _span, measurement) = stabilization_events
+                .next()
+                .expect("One measurement per sample");
             measurement
                 .persist_sensor_events(
                     &self.producer,
+                    self.queued_events.as_ref(),
                     self.pool.clone(),
                     &self.config.topic,
                     &self.config.experiment_id,
@@ -258,11 +395,19 @@ impl Experiment {
                     self.config.sample_rate,
                 )
                 .await;
+            report_measurement(
+                self.heartbeat.as_ref(),
+                self.status.as_ref(),
+                self.config.sensors.len(),
+                first_measurement + i + 1,
+                &measurement,
+            );
         }
     }
 
     async fn stage_carry_out(&mut self) {
-        self.stage = ExperimentStage::CarryOut;
+        self.flush_queued_events().await;
+        self.set_stage(ExperimentStage::CarryOut);
         let record = RecordData {
             payload: events::experiment_started_event(&self.config.experiment_id),
             key: Some(&self.config.experiment_id),
@@ -273,21 +418,38 @@ impl Experiment {
             .await
             .expect("Failed to produce message");
 
-        let carry_out_samples = self
-            .sample
-            .carry_out_samples(self.config.carry_out_samples.into());
-        let carry_out_events = events::temperature_events(
+        self.start_queue();
+        let len = self.config.carry_out_samples.into();
+        let carry_out_samples: Samples = match &self.trace {
+            Some(trace) => Box::new(trace.carry_out_samples(len, self.config.temp_range)),
+            None => Box::new(self.sample.carry_out_samples(len)),
+        };
+        let first_measurement = self.num_measurements;
+        let mut carry_out_events = events::temperature_events(
             carry_out_samples,
+            &mut self.num_measurements,
             &self.config.experiment_id,
             &self.config.researcher,
             &self.config.sensors,
             &self.stage,
-            &self.config.secret_key,
+            &self.config.keyring,
         );
-        for (sensor_events, _span, measurement) in carry_out_events {
+        for i in 0..carry_out_events.len() as u64 {
+            if let Some(stall) = &self.stall {
+                stall
+                    .wait(first_measurement + i, self.heartbeat.as_ref())
+                    .await;
+            }
+            self.producer
+                .budget()
+                .delay_measurement(self.config.sensors.len() as u64)
+                .await;
+            let (sensor_events, _span, measurement) =
+                carry_out_events.next().expect("One measurement per sample");
             measurement
                 .persist_sensor_events(
                     &self.producer,
+                    self.queued_events.as_ref(),
                     self.pool.clone(),
                     &self.config.topic,
                     &self.config.experiment_id,
@@ -295,19 +457,27 @@ impl Experiment {
                     self.config.sample_rate,
                 )
                 .await;
+            report_measurement(
+                self.heartbeat.as_ref(),
+                self.status.as_ref(),
+                self.config.sensors.len(),
+                first_measurement + i + 1,
+                &measurement,
+            );
             self.measurements.push(measurement);
         }
-
-        self.stage = ExperimentStage::Terminated;
-        let record = RecordData {
-            payload: events::experiment_terminated_event(&self.config.experiment_id),
-            key: Some(&self.config.experiment_id),
-            headers: OwnedHeaders::new().add("record_name", "experiment_terminated"),
-        };
-        self.producer
-            .send_event(record, &self.config.topic)
-            .await
-            .expect("Failed to produce message");
+        drop(carry_out_events);
+        self.flush_queued_events().await;
+
+        // A terminated experiment has no heartbeat.
+        self.heartbeat = None;
+        self.set_stage(ExperimentStage::Terminated);
+        send_experiment_terminated(
+            &self.producer,
+            &self.config.topic,
+            &self.config.experiment_id,
+        )
+        .await;
 
         if let Some(topic_document) = &self.config.topic_document {
             let record = RecordData {
@@ -327,14 +497,57 @@ impl Experiment {
     }
 
     pub async fn run(&mut self) {
+        if let Some(interval) = self.heartbeat_interval {
+            self.heartbeat = Some(Heartbeat::start(
+                self.producer.clone(),
+                self.config.topic.clone(),
+                self.config.experiment_id.clone(),
+                interval,
+            ));
+        }
         info!(stage = "configuration");
         self.stage_configuration().await;
-        time::sleep(Duration::from_millis(2000)).await;
+        time::sleep(CONFIGURATION_WAIT).await;
         info!(stage = "stabilization");
         self.stage_stabilization().await;
         info!(stage = "carry out");
         self.stage_carry_out().await;
     }
+
+    /// Runs the experiment in its own task, so the dashboard row `status` can abort it. An
+    /// experiment aborted once started is terminated, so consumers do not wait for it.
+    pub async fn run_abortable(mut self, status: StatusHandle) {
+        self.set_status(status.clone());
+        let producer = self.producer.clone();
+        let topic = self.config.topic.clone();
+        let experiment_id = self.config.experiment_id.clone();
+        let handle = tokio::spawn(async move { self.run().await }.in_current_span());
+        status.set_abort_handle(handle.abort_handle());
+        match handle.await {
+            Err(err) if err.is_panic() => std::panic::resume_unwind(err.into_panic()),
+            Err(_) if status.started() => {
+                info!(stage = "terminated", "Experiment aborted");
+                send_experiment_terminated(&producer, &topic, &experiment_id).await;
+            }
+            _ => {}
+        }
+    }
+}
+
+async fn send_experiment_terminated(
+    producer: &KafkaTopicProducer,
+    topic: &str,
+    experiment_id: &str,
+) {
+    let record = RecordData {
+        payload: events::experiment_terminated_event(experiment_id),
+        key: Some(experiment_id.to_string()),
+        headers: OwnedHeaders::new().add("record_name", "experiment_terminated"),
+    };
+    producer
+        .send_event(record, topic)
+        .await
+        .expect("Failed to produce message");
 }
 
 #[derive(Debug)]
@@ -345,10 +558,37 @@ pub struct Measurement {
     pub notification_type: Option<NotificationType>,
 }
 
+/// Reports the `num_measurements`-th measurement to the heartbeat and the status line.
+fn report_measurement(
+    heartbeat: Option<&Heartbeat>,
+    status: Option<&StatusHandle>,
+    num_sensors: usize,
+    num_measurements: u64,
+    measurement: &Measurement,
+) {
+    if let Some(heartbeat) = heartbeat {
+        // Without sensors there is no sensor event to point at yet
+        if let Some(sequence) = (num_measurements * num_sensors as u64).checked_sub(1) {
+            heartbeat.set_sequence(sequence);
+        }
+    }
+    if let Some(status) = status {
+        status.measured(
+            measurement.temperature,
+            measurement.notification_type.is_some(),
+        );
+    }
+}
+
 impl Measurement {
+    /// Sends the sensor events of the measurement and waits for the sample period.
+    ///
+    /// With a `queue`, the events are only pushed onto it, and are sent once the producer's
+    /// budget allows it.
     pub async fn persist_sensor_events(
         &self,
         producer: &KafkaTopicProducer,
+        queue: Option<&EventQueue>,
         pool: Option<Pool<Postgres>>,
         topic: &str,
         experiment_id: &str,
@@ -358,29 +598,40 @@ impl Measurement {
         let sleep_handle = tokio::spawn(async move {
             time::sleep(Duration::from_millis(period_millis)).await;
         });
-        if let (Some(pool), Some(_)) = (pool, &self.notification_type) {
+        if let (Some(pool), Some(notification_type)) = (pool, &self.notification_type) {
             let experiment_id = experiment_id.to_string();
             let measurement_id = self.measurement_id.clone();
+            let notification_type = match notification_type {
+                NotificationType::OutOfRange => "OutOfRange",
+                NotificationType::Stabilized => "Stabilized",
+            };
 
             tokio::spawn(async move {
                 database::insert_ground_truth(
                     &pool,
                     experiment_id.as_str(),
                     measurement_id.as_str(),
+                    notification_type,
                 )
                 .await
                 .expect("Insert should not fail");
             });
         }
+        let records = sensor_events.into_iter().map(|event| RecordData {
+            payload: event,
+            key: Some(experiment_id.to_string()),
+            headers: OwnedHeaders::new().add("record_name", "sensor_temperature_measured"),
+        });
+        if let Some(queue) = queue {
+            for record in records {
+                queue.push(record).await;
+            }
+            sleep_handle.await.expect("Sleep should not fail");
+            return;
+        }
         let span = Span::current();
-        let mut handles: Vec<JoinHandle<_>> = sensor_events
-            .into_iter()
-            .map(|event| {
-                let record = RecordData {
-                    payload: event,
-                    key: Some(experiment_id.to_string()),
-                    headers: OwnedHeaders::new().add("record_name", "sensor_temperature_measured"),
-                };
+        let mut handles: Vec<JoinHandle<_>> = records
+            .map(|record| {
                 let producer = producer.clone();
                 let topic = topic.to_string().clone();
                 tokio::spawn(
@@ -422,22 +673,19 @@ impl<'a> Iterator for IterMut<'a> {
             self.sample.cur += absolute_val;
         }
         self.iteration += 1;
-        let ret = if (self.sample.cur - self.sample.temp_range.upper_threshold).abs() <= 0.01 {
-            let mut sample = self.sample.clone();
-            sample.cur = self.sample.temp_range.upper_threshold - 0.011;
-            sample
-        } else if (self.sample.cur - self.sample.temp_range.lower_threshold).abs() <= 0.01 {
-            let mut sample = self.sample.clone();
-            sample.cur = self.sample.temp_range.lower_threshold + 0.011;
-            sample
-        } else {
-            self.sample.clone()
-        };
+        let ret = self.sample.away_from_thresholds();
         info!(avg_temperature = ret.cur);
         Some(ret)
     }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = self.len - self.iteration;
+        (remaining, Some(remaining))
+    }
 }
 
+impl<'a> ExactSizeIterator for IterMut<'a> {}
+
 pub fn compute_sensor_temperatures(
     sensors: &Vec<String>,
     average_temperature: f32,
@@ -0,0 +1,329 @@
+use serde::Deserialize;
+use std::collections::HashMap;
+use tracing::info;
+
+use crate::simulator::{TempRange, TemperatureSample};
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Phase {
+    Stabilization,
+    CarryOut,
+}
+
+/// A row of a recorded trace. `phase` is optional, and when no row carries it the phases
+/// are detected from the temperatures.
+#[derive(Debug, Deserialize)]
+struct TraceRecord {
+    timestamp: f64,
+    sensor: String,
+    value: f32,
+    #[serde(default)]
+    phase: Option<Phase>,
+}
+
+/// Most samples a trace is resampled to, so that a long trace or a short sample rate cannot
+/// exhaust the memory.
+const MAX_SAMPLES: f64 = 10_000_000.0;
+
+/// Average temperature of a recorded trace, resampled to the experiment's sample rate and
+/// split into the stabilization and carry out phases.
+#[derive(Debug)]
+pub struct Trace {
+    stabilization: Vec<f32>,
+    carry_out: Vec<f32>,
+}
+
+impl Trace {
+    /// Reads a CSV file with the columns `timestamp` (seconds), `sensor`, `value` and,
+    /// optionally, `phase` (`stabilization` or `carry_out`).
+    pub fn from_file(
+        file_path: &str,
+        sample_rate: u64,
+        temp_range: TempRange,
+        shift: bool,
+    ) -> Result<Self, String> {
+        let mut reader = csv::Reader::from_path(file_path)
+            .map_err(|err| format!("Could not read trace `{}`: {}", file_path, err))?;
+        let records = reader
+            .deserialize()
+            .collect::<Result<Vec<TraceRecord>, _>>()
+            .map_err(|err| format!("Invalid trace `{}`: {}", file_path, err))?;
+        Self::from_records(records, sample_rate, temp_range, shift)
+    }
+
+    fn from_records(
+        mut records: Vec<TraceRecord>,
+        sample_rate: u64,
+        temp_range: TempRange,
+        shift: bool,
+    ) -> Result<Self, String> {
+        if records.is_empty() {
+            return Err("Trace has no records".into());
+        }
+        if sample_rate == 0 {
+            return Err("Trace cannot be resampled with a sample rate of 0".into());
+        }
+        if let Some(record) = records
+            .iter()
+            .find(|record| !record.timestamp.is_finite() || !record.value.is_finite())
+        {
+            return Err(format!(
+                "Trace has a non-finite reading of sensor `{}`: {} at {}",
+                record.sensor, record.value, record.timestamp
+            ));
+        }
+        records.sort_by(|a, b| a.timestamp.total_cmp(&b.timestamp));
+        let period = sample_rate as f64 / 1000.0;
+        let span = records[records.len() - 1].timestamp - records[0].timestamp;
+        if span / period >= MAX_SAMPLES {
+            return Err(format!(
+                "Trace of {} s has more than {} samples at a sample rate of {} ms",
+                span, MAX_SAMPLES, sample_rate
+            ));
+        }
+        let (samples, phases) = resample(&records, period);
+
+        let annotated = records.iter().any(|record| record.phase.is_some());
+        let (stabilization, carry_out, plateau) = if annotated {
+            let (stabilization, carry_out): (Vec<_>, Vec<_>) = samples
+                .iter()
+                .zip(phases)
+                .partition(|(_, phase)| *phase == Phase::Stabilization);
+            let stabilization: Vec<f32> = stabilization.into_iter().map(|(v, _)| *v).collect();
+            let carry_out: Vec<f32> = carry_out.into_iter().map(|(v, _)| *v).collect();
+            let plateau = median(&carry_out)
+                .or_else(|| stabilization.last().copied())
+                .ok_or("Trace has no samples")?;
+            (stabilization, carry_out, plateau)
+        } else {
+            detect_phases(&samples, temp_range)
+        };
+
+        let offset = if shift {
+            (temp_range.lower_threshold + temp_range.upper_threshold) / 2.0 - plateau
+        } else {
+            0.0
+        };
+        info!(
+            stabilization_samples = stabilization.len(),
+            carry_out_samples = carry_out.len(),
+            offset,
+            "Loaded trace"
+        );
+        Ok(Self {
+            stabilization: stabilization.into_iter().map(|v| v + offset).collect(),
+            carry_out: carry_out.into_iter().map(|v| v + offset).collect(),
+        })
+    }
+
+    /// The last (at most) `len` samples of the stabilization phase, i.e. those leading into
+    /// the temperature range.
+    pub fn stabilization_samples(
+        &self,
+        len: usize,
+        temp_range: TempRange,
+    ) -> impl ExactSizeIterator<Item = TemperatureSample> + Send + '_ {
+        let start = self.stabilization.len().saturating_sub(len);
+        Self::samples(&self.stabilization[start..], temp_range)
+    }
+
+    /// The first (at most) `len` samples of the carry out phase.
+    pub fn carry_out_samples(
+        &self,
+        len: usize,
+        temp_range: TempRange,
+    ) -> impl ExactSizeIterator<Item = TemperatureSample> + Send + '_ {
+        let end = self.carry_out.len().min(len);
+        Self::samples(&self.carry_out[..end], temp_range)
+    }
+
+    fn samples(
+        values: &[f32],
+        temp_range: TempRange,
+    ) -> impl ExactSizeIterator<Item = TemperatureSample> + Send + '_ {
+        values.iter().map(move |&cur| {
+            let sample = TemperatureSample::new(cur, temp_range).away_from_thresholds();
+            info!(avg_temperature = sample.cur());
+            sample
+        })
+    }
+}
+
+/// Averages the sensors' values every `period` seconds, interpolating each sensor's
+/// readings linearly. The phase of a sample is the phase of the latest preceding record.
+fn resample(records: &[TraceRecord], period: f64) -> (Vec<f32>, Vec<Phase>) {
+    let mut sensors: HashMap<&str, Vec<(f64, f32)>> = HashMap::new();
+    for record in records {
+        sensors
+            .entry(&record.sensor)
+            .or_default()
+            .push((record.timestamp, record.value));
+    }
+
+    let start = records[0].timestamp;
+    let end = records[records.len() - 1].timestamp;
+    let mut samples = vec![];
+    let mut phases = vec![];
+    let mut phase = Phase::Stabilization;
+    let mut next_record = 0;
+    let mut tick = 0;
+    loop {
+        let time = start + tick as f64 * period;
+        if time > end {
+            break;
+        }
+        while next_record < records.len() && records[next_record].timestamp <= time {
+            phase = records[next_record].phase.unwrap_or(phase);
+            next_record += 1;
+        }
+        let total: f32 = sensors
+            .values()
+            .map(|readings| interpolate(readings, time))
+            .sum();
+        samples.push(total / sensors.len() as f32);
+        phases.push(phase);
+        tick += 1;
+    }
+    (samples, phases)
+}
+
+fn interpolate(readings: &[(f64, f32)], time: f64) -> f32 {
+    let after = readings.partition_point(|(timestamp, _)| *timestamp < time);
+    if after == 0 {
+        return readings[0].1;
+    }
+    if after == readings.len() {
+        return readings[readings.len() - 1].1;
+    }
+    let (t0, v0) = readings[after - 1];
+    let (t1, v1) = readings[after];
+    if t1 == t0 {
+        return v1;
+    }
+    v0 + (v1 - v0) * ((time - t0) / (t1 - t0)) as f32
+}
+
+fn median(values: &[f32]) -> Option<f32> {
+    if values.is_empty() {
+        return None;
+    }
+    let mut values = values.to_vec();
+    values.sort_by(|a, b| a.total_cmp(b));
+    Some(values[values.len() / 2])
+}
+
+/// Splits the samples where the trace first settles around its plateau, the median of the
+/// second half of the trace. Settling means getting as close to the plateau as the
+/// temperature range's half width, so once shifted the plateau is the middle of the range
+/// and stabilization ends when the range is entered.
+fn detect_phases(samples: &[f32], temp_range: TempRange) -> (Vec<f32>, Vec<f32>, f32) {
+    let plateau = median(&samples[samples.len() / 2..]).expect("Trace has samples");
+    let band = (temp_range.upper_threshold - temp_range.lower_threshold) / 2.0;
+    let settled = samples
+        .iter()
+        .position(|v| (v - plateau).abs() <= band)
+        .unwrap_or(samples.len() - 1);
+    (
+        samples[..=settled].to_vec(),
+        samples[settled + 1..].to_vec(),
+        plateau,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn records(raw: &str) -> Vec<TraceRecord> {
+        csv::Reader::from_reader(raw.as_bytes())
+            .deserialize()
+            .collect::<Result<_, _>>()
+            .unwrap()
+    }
+
+    #[test]
+    fn resamples_sensor_average() {
+        let records = records(
+            "timestamp,sensor,value\n\
+             0.0,a,10.0\n\
+             0.0,b,12.0\n\
+             1.0,a,20.0\n\
+             2.0,b,12.0\n",
+        );
+        let (samples, _) = resample(&records, 0.5);
+        assert_eq!(samples, vec![11.0, 13.5, 16.0, 16.0, 16.0]);
+    }
+
+    #[test]
+    fn annotated_phases_and_shift() {
+        let records = records(
+            "timestamp,sensor,value,phase\n\
+             0,a,0.0,stabilization\n\
+             1,a,10.0,stabilization\n\
+             2,a,20.0,carry_out\n\
+             3,a,20.0,carry_out\n",
+        );
+        let temp_range = TempRange::new(25.0, 27.0).unwrap();
+        let trace = Trace::from_records(records, 1000, temp_range, true).unwrap();
+        assert_eq!(trace.stabilization, vec![6.0, 16.0]);
+        assert_eq!(trace.carry_out, vec![26.0, 26.0]);
+    }
+
+    #[test]
+    fn detected_phases() {
+        let records = records(
+            "timestamp,sensor,value\n\
+             0,a,0.0\n\
+             1,a,10.0\n\
+             2,a,19.5\n\
+             3,a,20.5\n\
+             4,a,20.0\n\
+             5,a,20.0\n",
+        );
+        let temp_range = TempRange::new(19.0, 21.0).unwrap();
+        let trace = Trace::from_records(records, 1000, temp_range, false).unwrap();
+        assert_eq!(trace.stabilization, vec![0.0, 10.0, 19.5]);
+        assert_eq!(trace.carry_out, vec![20.5, 20.0, 20.0]);
+
+        let samples: Vec<_> = trace
+            .stabilization_samples(2, temp_range)
+            .map(|sample| sample.cur())
+            .collect();
+        assert_eq!(samples, vec![10.0, 19.5]);
+    }
+
+    #[test]
+    fn zero_sample_rate() {
+        let records = records(
+            "timestamp,sensor,value\n\
+             0,a,0.0\n\
+             1,a,10.0\n",
+        );
+        let temp_range = TempRange::new(19.0, 21.0).unwrap();
+        assert!(Trace::from_records(records, 0, temp_range, false).is_err());
+    }
+
+    #[test]
+    fn non_finite_readings() {
+        let temp_range = TempRange::new(19.0, 21.0).unwrap();
+        for raw in [
+            "timestamp,sensor,value\n0,a,0.0\nNaN,a,10.0\n",
+            "timestamp,sensor,value\n0,a,0.0\ninf,a,10.0\n",
+            "timestamp,sensor,value\n0,a,0.0\n1,a,NaN\n",
+        ] {
+            assert!(Trace::from_records(records(raw), 1000, temp_range, false).is_err());
+        }
+    }
+
+    #[test]
+    fn too_many_samples() {
+        let records = records(
+            "timestamp,sensor,value\n\
+             0,a,0.0\n\
+             100000000,a,10.0\n",
+        );
+        let temp_range = TempRange::new(19.0, 21.0).unwrap();
+        assert!(Trace::from_records(records, 1, temp_range, false).is_err());
+    }
+}
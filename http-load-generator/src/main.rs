@@ -1,6 +1,7 @@
 use clap::{command, value_parser, Arg, ArgAction};
+use event_hash::SecretKey;
 use futures::future;
-use std::process;
+use std::{process, str::FromStr};
 use tokio::sync::mpsc;
 
 mod consume;
@@ -28,7 +29,8 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             .long("secret-key")
             .action(ArgAction::Set)
             .default_value("QJUHsPhnA0eiqHuJqsPgzhDozYO4f1zh")
-            .help("<key> is a 32 character string that must match the key being passed to the notifications-service")
+            .value_parser(SecretKey::from_str)
+            .help("<key> is a 32 character string, or `hex:<key>`, `base64:<key>`, `file:<path>` or `passphrase:<passphrase>`, that must match the key being passed to the notifications-service")
         )
         .arg(Arg::new("broker-list")
             .required(true)
@@ -1,25 +1,29 @@
-use event_hash::{DecryptError, HashData};
+use event_hash::{DecryptError, HashData, Keyring, NotificationType, ValidationError};
 use poem::web::Data;
 use poem_openapi::{
-    param::Query,
+    param::{Path, Query},
     payload::{Json, PlainText},
     ApiResponse, Enum, Object, OpenApi,
 };
 use prometheus_client::{encoding::text, registry::Registry};
 use serde::{Deserialize, Serialize};
-use sqlx::{Pool, Postgres};
 use std::{
+    collections::HashMap,
     sync::{Arc, Mutex},
-    time::{SystemTime, UNIX_EPOCH},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
-use tracing::info;
+use tracing::{info, warn};
 
-use crate::metric::{Metrics, ResponseCountLabels};
-use crate::store;
-use crate::{jwt, metric::ResponseType};
-
-#[derive(Debug, Clone)]
-pub struct SecretKey(pub String);
+use crate::auth::{AuthError, PublicMetrics, RequireAuth, Token};
+use crate::delivery::{Delivery, DeliveryStatus, Notification};
+use crate::metric::{GroupLabels, LatencyLabels, Metrics, Reason, ResponseCountLabels};
+use crate::ratelimit::RateLimiter;
+use crate::score::{self, Score};
+use crate::store::{self, NotificationStore};
+use crate::{
+    jwt::{Claims, JwtError, Verifier},
+    metric::ResponseType,
+};
 
 #[derive(Debug, PartialEq, Enum, Serialize, Deserialize)]
 enum BodyNotificationType {
@@ -27,6 +31,15 @@ enum BodyNotificationType {
     Stabilized,
 }
 
+impl BodyNotificationType {
+    fn name(&self) -> &'static str {
+        match self {
+            BodyNotificationType::OutOfRange => "OutOfRange",
+            BodyNotificationType::Stabilized => "Stabilized",
+        }
+    }
+}
+
 #[derive(Object)]
 struct NotifyBody {
     notification_type: BodyNotificationType,
@@ -37,45 +50,17 @@ struct NotifyBody {
 }
 
 impl NotifyBody {
-    fn validate_body(&self, hash_data: &HashData) -> Result<(), NotifyErrorResponse> {
-        if hash_data.measurement_id != self.measurement_id {
-            return Err(NotifyErrorResponse::BadRequest(PlainText(format!(
-                "Unexpected measurement_id `{}`. Expected `{}`",
-                self.measurement_id, hash_data.measurement_id
-            ))));
-        } else if hash_data.experiment_id != self.experiment_id {
-            return Err(NotifyErrorResponse::BadRequest(PlainText(format!(
-                "Unexpected experiment_id `{}`. Expected `{}`",
-                self.experiment_id, hash_data.experiment_id
-            ))));
-        } else if hash_data.researcher != self.researcher {
-            return Err(NotifyErrorResponse::BadRequest(PlainText(format!(
-                "Unexpected researcher `{}`. Expected `{}`",
-                self.researcher, hash_data.researcher
-            ))));
-        }
-        if let None = hash_data.notification_type {
-            return Err(NotifyErrorResponse::BadRequest(PlainText(format!(
-                "Unexpected notification. Measurement `{}` should not have been notified",
-                hash_data.measurement_id
-            ))));
-        }
-
-        let hash_notification = hash_data
-            .notification_type
-            .as_ref()
-            .expect("verify whether hash_data is None beforehand");
-        let hash_notification_string = serde_json::to_string(hash_notification)
-            .expect("Serializable HashData NotificationType");
-        let body_notification_string = serde_json::to_string(&self.notification_type)
-            .expect("Serializable Body NotificationType");
-        if hash_notification_string != body_notification_string {
-            return Err(NotifyErrorResponse::BadRequest(PlainText(format!(
-                "Unexpected notification_type `{:?}`. Expected `{:?}`",
-                self.notification_type, hash_notification
-            ))));
-        }
-        Ok(())
+    fn validate_body(&self, hash_data: &HashData) -> Result<(), ValidationError> {
+        let notification_type = match self.notification_type {
+            BodyNotificationType::OutOfRange => NotificationType::OutOfRange,
+            BodyNotificationType::Stabilized => NotificationType::Stabilized,
+        };
+        hash_data.validate(
+            &self.researcher,
+            &self.experiment_id,
+            &self.measurement_id,
+            &notification_type,
+        )
     }
 }
 
@@ -92,11 +77,57 @@ pub enum NotifyErrorResponse {
     #[oai(status = 400)]
     BadRequest(PlainText<String>),
 
+    /// The token is missing, expired or revoked
+    #[oai(status = 401)]
+    Unauthorized(PlainText<String>),
+
+    /// The group already sent a notification of the measurement
+    #[oai(status = 409)]
+    Conflict(PlainText<String>),
+
+    /// The notification arrived after the measurement hash expired
+    #[oai(status = 410)]
+    Expired(PlainText<String>),
+
+    /// The group sent more notifications than its rate limit allows. `Retry-After` is in
+    /// seconds
+    #[oai(status = 429)]
+    TooManyRequests(PlainText<String>, #[oai(header = "Retry-After")] u64),
+
     /// The server has encountered an error
     #[oai(status = 500)]
     InternalServerError(PlainText<String>),
 }
 
+/// Outcome of one notification of a batch, as `/notify` would answer it
+#[derive(Object, Debug)]
+struct NotifyBatchItem {
+    status: u16,
+    /// Latency in seconds if accepted, the error otherwise
+    message: String,
+}
+
+impl From<NotifyErrorResponse> for NotifyBatchItem {
+    fn from(e: NotifyErrorResponse) -> Self {
+        let (status, PlainText(message)) = match e {
+            NotifyErrorResponse::BadRequest(message) => (400, message),
+            NotifyErrorResponse::Unauthorized(message) => (401, message),
+            NotifyErrorResponse::Conflict(message) => (409, message),
+            NotifyErrorResponse::Expired(message) => (410, message),
+            NotifyErrorResponse::TooManyRequests(message, _) => (429, message),
+            NotifyErrorResponse::InternalServerError(message) => (500, message),
+        };
+        Self { status, message }
+    }
+}
+
+#[derive(ApiResponse)]
+enum NotifyBatchResponse {
+    /// Outcome of every notification, in the order of the batch
+    #[oai(status = 200)]
+    Ok(Json<Vec<NotifyBatchItem>>),
+}
+
 impl From<DecryptError> for NotifyErrorResponse {
     fn from(e: DecryptError) -> Self {
         match e {
@@ -118,6 +149,15 @@ impl From<DecryptError> for NotifyErrorResponse {
             DecryptError::JsonDeserializationError => NotifyErrorResponse::InternalServerError(
                 PlainText("Could not deserialize json string into HashData.".into()),
             ),
+            DecryptError::UnknownKeyId => {
+                NotifyErrorResponse::BadRequest(PlainText("Unknown key ID".into()))
+            }
+            DecryptError::UnknownAlgorithm => {
+                NotifyErrorResponse::BadRequest(PlainText("Unknown cipher algorithm".into()))
+            }
+            DecryptError::MalformedBinaryPayload => NotifyErrorResponse::InternalServerError(
+                PlainText("Could not decode binary payload into HashData.".into()),
+            ),
         }
     }
 }
@@ -125,115 +165,711 @@ impl From<DecryptError> for NotifyErrorResponse {
 impl From<sqlx::Error> for NotifyErrorResponse {
     fn from(e: sqlx::Error) -> Self {
         info!("sqlx error: {:?}", e);
-        NotifyErrorResponse::InternalServerError(PlainText(format!("Failed to insert values")))
+        insert_failed()
     }
 }
 
-impl From<jsonwebtoken::errors::Error> for NotifyErrorResponse {
-    fn from(e: jsonwebtoken::errors::Error) -> Self {
+fn insert_failed() -> NotifyErrorResponse {
+    NotifyErrorResponse::InternalServerError(PlainText("Failed to insert values".into()))
+}
+
+impl From<JwtError> for NotifyErrorResponse {
+    fn from(e: JwtError) -> Self {
         info!("jsonwebtoken error: {:?}", e);
-        NotifyErrorResponse::InternalServerError(PlainText(format!(
-            "Token must be encoded by ECC private key"
-        )))
+        match e {
+            e if e.is_expired() => {
+                NotifyErrorResponse::Unauthorized(PlainText("Token expired".into()))
+            }
+            JwtError::Revoked => NotifyErrorResponse::Unauthorized(PlainText(e.to_string())),
+            _ => NotifyErrorResponse::InternalServerError(PlainText(
+                "Token must be encoded by ECC private key".into(),
+            )),
+        }
+    }
+}
+
+impl From<AuthError> for NotifyErrorResponse {
+    fn from(e: AuthError) -> Self {
+        match e {
+            AuthError::Missing => NotifyErrorResponse::Unauthorized(PlainText(e.to_string())),
+            AuthError::Token(e) => NotifyErrorResponse::from(e),
+        }
+    }
+}
+
+#[derive(ApiResponse, Debug)]
+pub enum AuthErrorResponse {
+    /// The token is missing, invalid, expired or revoked
+    #[oai(status = 401)]
+    Unauthorized(PlainText<String>),
+
+    /// The token does not grant access to the data
+    #[oai(status = 403)]
+    Forbidden(PlainText<String>),
+}
+
+impl From<AuthError> for AuthErrorResponse {
+    fn from(e: AuthError) -> Self {
+        info!("authentication error: {:?}", e);
+        AuthErrorResponse::Unauthorized(PlainText(e.to_string()))
+    }
+}
+
+/// A notification received by `/notify`. `received_at` is seconds since the epoch.
+#[derive(Object)]
+struct NotificationEntry {
+    id: i64,
+    group: Option<String>,
+    notification_type: String,
+    researcher: String,
+    experiment_id: String,
+    measurement_id: String,
+    received_at: f64,
+    latency: f64,
+}
+
+impl From<store::NotificationRecord> for NotificationEntry {
+    fn from(record: store::NotificationRecord) -> Self {
+        Self {
+            id: record.id,
+            group: record.group_id,
+            notification_type: record.notification_type,
+            researcher: record.researcher,
+            experiment_id: record.experiment_id,
+            measurement_id: record.measurement_id,
+            received_at: record.received_at,
+            latency: record.latency,
+        }
+    }
+}
+
+#[derive(Object)]
+struct NotificationPage {
+    notifications: Vec<NotificationEntry>,
+    /// `cursor` of the next page, absent on the last page
+    next_cursor: Option<i64>,
+}
+
+#[derive(ApiResponse)]
+enum NotificationsResponse {
+    #[oai(status = 200)]
+    Ok(Json<NotificationPage>),
+}
+
+#[derive(ApiResponse, Debug)]
+pub enum NotificationsErrorResponse {
+    /// Request could not be processed
+    #[oai(status = 400)]
+    BadRequest(PlainText<String>),
+
+    /// The token is missing, invalid, expired or revoked
+    #[oai(status = 401)]
+    Unauthorized(PlainText<String>),
+
+    /// The token does not grant access to the group
+    #[oai(status = 403)]
+    Forbidden(PlainText<String>),
+
+    /// The server has encountered an error
+    #[oai(status = 500)]
+    InternalServerError(PlainText<String>),
+}
+
+impl From<sqlx::Error> for NotificationsErrorResponse {
+    fn from(e: sqlx::Error) -> Self {
+        info!("sqlx error: {:?}", e);
+        NotificationsErrorResponse::InternalServerError(PlainText("Failed to select values".into()))
     }
 }
 
-fn compute_latency(ts: f64) -> f64 {
+impl From<AuthErrorResponse> for NotificationsErrorResponse {
+    fn from(e: AuthErrorResponse) -> Self {
+        match e {
+            AuthErrorResponse::Unauthorized(message) => {
+                NotificationsErrorResponse::Unauthorized(message)
+            }
+            AuthErrorResponse::Forbidden(message) => NotificationsErrorResponse::Forbidden(message),
+        }
+    }
+}
+
+#[derive(ApiResponse)]
+pub enum ScoreResponse {
+    #[oai(status = 200)]
+    Ok(Json<Score>),
+}
+
+const DEFAULT_PAGE_SIZE: i64 = 100;
+const MAX_PAGE_SIZE: i64 = 1000;
+const MAX_BATCH_SIZE: usize = 1000;
+
+fn current_epoch() -> f64 {
     let current_time = SystemTime::now();
     let current_time = current_time
         .duration_since(UNIX_EPOCH)
         .expect("Time went backwards");
-    let current_time: f64 =
-        current_time.as_secs() as f64 + current_time.subsec_nanos() as f64 / 1_000_000_000_f64;
-    current_time - ts
+    current_time.as_secs() as f64 + current_time.subsec_nanos() as f64 / 1_000_000_000_f64
+}
+
+/// Longest time after it was issued that a measurement hash is accepted, set with
+/// `--max-hash-age`. Unlimited when `None`, leaving only the `expires_at` of the hash.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct MaxHashAge(pub Option<Duration>);
+
+/// Rejects the notifications of an expired measurement hash, so that late notifications
+/// are told apart from invalid ones. A hash expires at its `expires_at`, or `max_age` after
+/// its `issued_at`, whichever is first. Hashes without `issued_at` (v1 hashes of earlier
+/// producers) age from the timestamp of their measurement, so they cannot be replayed
+/// forever either.
+fn check_expiry(
+    hash_data: &HashData,
+    max_age: MaxHashAge,
+    now: f64,
+) -> Result<(), NotifyErrorResponse> {
+    let issued_at = hash_data.issued_at.unwrap_or(hash_data.timestamp);
+    let aged_at = max_age.0.map(|max_age| issued_at + max_age.as_secs_f64());
+    let expires_at = match (hash_data.expires_at, aged_at) {
+        (Some(expires_at), Some(aged_at)) => Some(expires_at.min(aged_at)),
+        (expires_at, aged_at) => expires_at.or(aged_at),
+    };
+    match expires_at {
+        Some(expires_at) if now > expires_at => {
+            Err(NotifyErrorResponse::Expired(PlainText(format!(
+                "Measurement hash of `{}` expired at {}",
+                hash_data.measurement_id, expires_at
+            ))))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Delivers in the background, so that slow channels do not add to the latency of
+/// `/notify`, and records the status per channel.
+async fn deliver(
+    delivery: Arc<Delivery>,
+    store: Arc<dyn NotificationStore>,
+    notification: Notification,
+) {
+    for record in delivery.deliver(&notification).await {
+        let error = match &record.status {
+            DeliveryStatus::Delivered => {
+                info!(
+                    "measurement_id: {}\tdelivered over {}",
+                    notification.measurement_id, record.channel
+                );
+                None
+            }
+            DeliveryStatus::Failed(err) => {
+                warn!(
+                    "measurement_id: {}\tdelivery over {} failed: {}",
+                    notification.measurement_id, record.channel, err
+                );
+                Some(err.as_str())
+            }
+        };
+        if let Err(e) = store
+            .insert_delivery(
+                notification.group.as_deref(),
+                &notification.experiment_id,
+                &notification.measurement_id,
+                record.channel,
+                error,
+            )
+            .await
+        {
+            warn!("sqlx error: {:?}", e);
+        }
+    }
+}
+
+/// Admins may read the data of every group and groups only their own, where `group == None`
+/// stands for every group. Anonymous callers, which only get here without `--require-auth`,
+/// may read the data of no group, or they could drop their token to read another group's.
+fn authorize(caller: Option<&Claims>, group: Option<&str>) -> Result<(), AuthErrorResponse> {
+    match caller {
+        Some(claims) if claims.is_admin() || group == Some(claims.sub.as_str()) => Ok(()),
+        Some(claims) => Err(AuthErrorResponse::Forbidden(PlainText(match group {
+            Some(group) => format!("{} may not read the data of {}", claims.sub, group),
+            None => format!("{} may only read its own data", claims.sub),
+        }))),
+        None => Err(AuthErrorResponse::Unauthorized(PlainText(
+            "A token is required to read the data of a group".into(),
+        ))),
+    }
+}
+
+fn throttled(retry_after: Duration) -> NotifyErrorResponse {
+    let retry_after = retry_after.as_secs_f64().ceil() as u64;
+    NotifyErrorResponse::TooManyRequests(
+        PlainText(format!(
+            "Rate limit exceeded. Retry after {} seconds",
+            retry_after
+        )),
+        retry_after,
+    )
+}
+
+fn duplicate(measurement_id: &str, first_seen: f64) -> NotifyErrorResponse {
+    NotifyErrorResponse::Conflict(PlainText(format!(
+        "Duplicate notification of `{}`. First seen at {}",
+        measurement_id, first_seen
+    )))
 }
 
 pub struct Api;
 
 #[OpenApi]
 impl Api {
+    #[allow(clippy::too_many_arguments)]
     #[oai(path = "/notify", method = "post")]
     async fn notify_post(
         &self,
-        data_secret_key: Data<&SecretKey>,
-        pool: Data<&Option<Pool<Postgres>>>,
+        keyring: Data<&Keyring>,
+        store: Data<&Arc<dyn NotificationStore>>,
+        verifier: Data<&Arc<Verifier>>,
+        require_auth: Data<&RequireAuth>,
+        rate_limiter: Data<&Arc<RateLimiter>>,
+        max_hash_age: Data<&MaxHashAge>,
         body: Json<NotifyBody>,
         metrics: Data<&Metrics>,
-        token: Query<Option<String>>,
+        delivery: Data<&Arc<Delivery>>,
+        token: Token,
     ) -> Result<NotifyResponse, NotifyErrorResponse> {
-        let pool = pool.0;
-        let secret_key = data_secret_key.0;
-        let key: &[u8] = secret_key.0.as_bytes();
-        let body = body.0;
+        let store = store.0;
+        let metrics = metrics.0;
+        let _in_flight = metrics.in_flight("/notify");
+        let subject = self.admit(metrics, &verifier, *require_auth.0, &rate_limiter, token, 1)?;
+        let subject = subject.as_deref();
+
+        let (notification, received_at) =
+            self.accept(&keyring, *max_hash_age.0, metrics, subject, body.0)?;
+        let started = Instant::now();
+        let inserted = store.insert_notification(&notification, received_at).await;
+        metrics
+            .insert_duration
+            .observe(started.elapsed().as_secs_f64());
+        let first_seen = inserted.map_err(|e| {
+            self.update_counters(
+                metrics,
+                subject,
+                ResponseType::from(&e),
+                Some(Reason::from(&e)),
+            );
+            e
+        })?;
+        if let Some(first_seen) = first_seen {
+            let e = duplicate(&notification.measurement_id, first_seen);
+            return Err(self.reject(metrics, subject, e));
+        }
+
+        let latency = notification.latency;
+        self.complete(metrics, store, &delivery, notification);
+        Ok(NotifyResponse::Ok(PlainText(format!("{}", latency))))
+    }
+
+    /// Notifies up to 1000 measurements at once. Every notification is answered with the
+    /// status and message `/notify` would answer it with, and the accepted ones are inserted
+    /// together. Every notification takes a request from the rate limit, and the batch is
+    /// throttled as a whole when the bucket cannot cover all of them. A batch larger than the
+    /// burst is rejected with 400.
+    #[allow(clippy::too_many_arguments)]
+    #[oai(path = "/notify/batch", method = "post")]
+    async fn notify_batch_post(
+        &self,
+        keyring: Data<&Keyring>,
+        store: Data<&Arc<dyn NotificationStore>>,
+        verifier: Data<&Arc<Verifier>>,
+        require_auth: Data<&RequireAuth>,
+        rate_limiter: Data<&Arc<RateLimiter>>,
+        max_hash_age: Data<&MaxHashAge>,
+        body: Json<Vec<NotifyBody>>,
+        metrics: Data<&Metrics>,
+        delivery: Data<&Arc<Delivery>>,
+        token: Token,
+    ) -> Result<NotifyBatchResponse, NotifyErrorResponse> {
+        let store = store.0;
         let metrics = metrics.0;
-        let mut subject: Option<String> = None;
+        let _in_flight = metrics.in_flight("/notify/batch");
+        if !(1..=MAX_BATCH_SIZE).contains(&body.len()) {
+            return Err(NotifyErrorResponse::BadRequest(PlainText(format!(
+                "A batch holds between 1 and {} notifications",
+                MAX_BATCH_SIZE
+            ))));
+        }
+        let subject = self.admit(
+            metrics,
+            &verifier,
+            *require_auth.0,
+            &rate_limiter,
+            token,
+            body.len(),
+        )?;
+        let subject = subject.as_deref();
 
-        if let Some(token) = token.0 {
-            let claims = jwt::decode(&token)
-                .map_err(|e| {
+        let mut outcomes: Vec<_> = body
+            .0
+            .into_iter()
+            .map(|item| self.accept(&keyring, *max_hash_age.0, metrics, subject, item))
+            .collect();
+        // A measurement repeated within the batch is a duplicate of its first notification
+        let mut first_seen_in_batch = HashMap::new();
+        for outcome in outcomes.iter_mut() {
+            let Ok((notification, received_at)) = outcome else {
+                continue;
+            };
+            let key = (
+                notification.experiment_id.clone(),
+                notification.measurement_id.clone(),
+            );
+            match first_seen_in_batch.get(&key) {
+                Some(&first_seen) => {
+                    let e = duplicate(&notification.measurement_id, first_seen);
+                    *outcome = Err(self.reject(metrics, subject, e));
+                }
+                None => {
+                    first_seen_in_batch.insert(key, *received_at);
+                }
+            }
+        }
+
+        let accepted: Vec<(&Notification, f64)> = outcomes
+            .iter()
+            .filter_map(|outcome| outcome.as_ref().ok())
+            .map(|(notification, received_at)| (notification, *received_at))
+            .collect();
+        let inserted = if accepted.is_empty() {
+            Ok(vec![])
+        } else {
+            let started = Instant::now();
+            let inserted = store.insert_notifications(&accepted).await;
+            metrics
+                .insert_duration
+                .observe(started.elapsed().as_secs_f64());
+            inserted
+        };
+
+        // When the insert fails, every accepted notification is answered with 500 and the
+        // rejected ones keep their own status
+        let (mut first_seen, insert_error) = match inserted {
+            Ok(first_seen) => (first_seen.into_iter(), None),
+            Err(e) => {
+                info!("sqlx error: {:?}", e);
+                (vec![].into_iter(), Some(e))
+            }
+        };
+        let items = outcomes
+            .into_iter()
+            .map(|outcome| match (outcome, &insert_error) {
+                (Ok(_), Some(e)) => {
                     self.update_counters(
                         metrics,
-                        subject.as_ref().map(|x| x.as_str()),
-                        ResponseType::from(&e),
+                        subject,
+                        ResponseType::from(e),
+                        Some(Reason::from(e)),
                     );
-                    e
-                })?
-                .claims;
-            subject = Some(claims.sub);
+                    NotifyBatchItem::from(insert_failed())
+                }
+                (Ok((notification, _)), None) => match first_seen.next().flatten() {
+                    Some(first_seen) => {
+                        let e = duplicate(&notification.measurement_id, first_seen);
+                        NotifyBatchItem::from(self.reject(metrics, subject, e))
+                    }
+                    None => {
+                        let latency = notification.latency;
+                        self.complete(metrics, store, &delivery, notification);
+                        NotifyBatchItem {
+                            status: 200,
+                            message: format!("{}", latency),
+                        }
+                    }
+                },
+                (Err(e), _) => NotifyBatchItem::from(e),
+            })
+            .collect();
+        Ok(NotifyBatchResponse::Ok(Json(items)))
+    }
+
+    /// Notifications received, oldest first. The time window is `[received_after,
+    /// received_before)` in seconds since the epoch.
+    #[allow(clippy::too_many_arguments)]
+    #[oai(path = "/notifications", method = "get")]
+    async fn notifications_get(
+        &self,
+        store: Data<&Arc<dyn NotificationStore>>,
+        metrics: Data<&Metrics>,
+        verifier: Data<&Arc<Verifier>>,
+        require_auth: Data<&RequireAuth>,
+        token: Token,
+        group: Query<Option<String>>,
+        experiment_id: Query<Option<String>>,
+        researcher: Query<Option<String>>,
+        notification_type: Query<Option<BodyNotificationType>>,
+        received_after: Query<Option<f64>>,
+        received_before: Query<Option<f64>>,
+        cursor: Query<Option<i64>>,
+        limit: Query<Option<i64>>,
+    ) -> Result<NotificationsResponse, NotificationsErrorResponse> {
+        let _in_flight = metrics.in_flight("/notifications");
+        let caller = token
+            .verify(&verifier, *require_auth.0)
+            .map_err(AuthErrorResponse::from)?;
+        // Groups read their own notifications unless they ask for another group's
+        let group = match (&caller, group.0) {
+            (Some(claims), None) if !claims.is_admin() => Some(claims.sub.clone()),
+            (_, group) => group,
+        };
+        authorize(caller.as_ref(), group.as_deref())?;
+        let limit = limit.0.unwrap_or(DEFAULT_PAGE_SIZE);
+        if !(1..=MAX_PAGE_SIZE).contains(&limit) {
+            return Err(NotificationsErrorResponse::BadRequest(PlainText(format!(
+                "limit must be between 1 and {}",
+                MAX_PAGE_SIZE
+            ))));
+        }
+
+        let filter = store::NotificationFilter {
+            group_id: group.as_deref(),
+            experiment_id: experiment_id.0.as_deref(),
+            researcher: researcher.0.as_deref(),
+            notification_type: notification_type.0.as_ref().map(BodyNotificationType::name),
+            received_after: received_after.0,
+            received_before: received_before.0,
+        };
+        let notifications: Vec<NotificationEntry> = store
+            .select_notifications(&filter, cursor.0.unwrap_or(0), limit)
+            .await?
+            .into_iter()
+            .map(NotificationEntry::from)
+            .collect();
+        let next_cursor = match notifications.last() {
+            Some(last) if notifications.len() as i64 == limit => Some(last.id),
+            _ => None,
+        };
+        Ok(NotificationsResponse::Ok(Json(NotificationPage {
+            notifications,
+            next_cursor,
+        })))
+    }
+
+    /// Compares the notifications of `group` received in `[from, to)` to the notifications
+    /// expected in that window, in seconds since the epoch.
+    #[allow(clippy::too_many_arguments)]
+    #[oai(path = "/groups/:group/score", method = "get")]
+    async fn group_score_get(
+        &self,
+        store: Data<&Arc<dyn NotificationStore>>,
+        metrics: Data<&Metrics>,
+        verifier: Data<&Arc<Verifier>>,
+        require_auth: Data<&RequireAuth>,
+        token: Token,
+        group: Path<String>,
+        from: Query<Option<f64>>,
+        to: Query<Option<f64>>,
+    ) -> Result<ScoreResponse, NotificationsErrorResponse> {
+        let _in_flight = metrics.in_flight("/groups/:group/score");
+        let caller = token
+            .verify(&verifier, *require_auth.0)
+            .map_err(AuthErrorResponse::from)?;
+        authorize(caller.as_ref(), Some(&group.0))?;
+        let expected = store.select_ground_truth(from.0, to.0).await?;
+        let filter = store::NotificationFilter {
+            group_id: Some(&group.0),
+            received_after: from.0,
+            received_before: to.0,
+            ..Default::default()
+        };
+        let mut received = vec![];
+        let mut cursor = 0;
+        loop {
+            let page = store
+                .select_notifications(&filter, cursor, MAX_PAGE_SIZE)
+                .await?;
+            let last_page = (page.len() as i64) < MAX_PAGE_SIZE;
+            if let Some(last) = page.last() {
+                cursor = last.id;
+            }
+            received.extend(page);
+            if last_page {
+                break;
+            }
+        }
+
+        let score = score::grade(&expected, &received);
+        metrics
+            .0
+            .group_score
+            .get_or_create(&GroupLabels {
+                group: group.0.clone(),
+            })
+            .set(score.score);
+        Ok(ScoreResponse::Ok(Json(score)))
+    }
+
+    /// Public keys that verify the tokens of `/notify`.
+    #[oai(path = "/.well-known/jwks.json", method = "get")]
+    async fn jwks_get(&self, verifier: Data<&Arc<Verifier>>) -> Json<serde_json::Value> {
+        Json(serde_json::to_value(verifier.jwks()).expect("JWKS serialize to JSON"))
+    }
+
+    /// Metrics of every group, for admins
+    #[oai(path = "/metrics", method = "get")]
+    async fn get_metrics(
+        &self,
+        state: Data<&Arc<Mutex<Registry>>>,
+        verifier: Data<&Arc<Verifier>>,
+        public_metrics: Data<&PublicMetrics>,
+        token: Token,
+    ) -> Result<PlainText<String>, AuthErrorResponse> {
+        // With --public-metrics, Prometheus scrapes the metrics anonymously
+        if token.0.is_some() || !public_metrics.0 .0 {
+            let claims = token.verify(&verifier, RequireAuth(true))?;
+            authorize(claims.as_ref(), None)?;
+        }
+        let state = state.0.lock().unwrap();
+        let mut body = String::new();
+        text::encode(&mut body, &state).unwrap();
+        Ok(PlainText(body))
+    }
+
+    /// Authenticates a notification request and takes it from the rate limit of its token
+    /// subject, which it returns. A batch larger than the burst of the subject is rejected,
+    /// since it would never fit.
+    fn admit(
+        &self,
+        metrics: &Metrics,
+        verifier: &Verifier,
+        require_auth: RequireAuth,
+        rate_limiter: &RateLimiter,
+        token: Token,
+        requests: usize,
+    ) -> Result<Option<String>, NotifyErrorResponse> {
+        let subject = token
+            .verify(verifier, require_auth)
+            .map_err(|e| {
+                self.update_counters(
+                    metrics,
+                    None,
+                    ResponseType::from(&e),
+                    Some(Reason::from(&e)),
+                );
+                e
+            })?
+            .map(|claims| claims.sub);
+        if let Some(limit) = rate_limiter.limit(subject.as_deref()) {
+            if requests as f64 > limit.burst {
+                let e = NotifyErrorResponse::BadRequest(PlainText(format!(
+                    "A batch of {} notifications exceeds the rate limit burst of {}",
+                    requests, limit.burst
+                )));
+                return Err(self.reject(metrics, subject.as_deref(), e));
+            }
         }
+        if let Err(retry_after) = rate_limiter.acquire(subject.as_deref(), requests) {
+            return Err(self.reject(metrics, subject.as_deref(), throttled(retry_after)));
+        }
+        Ok(subject)
+    }
 
-        let hash_data = HashData::decrypt(key, &body.cipher_data).map_err(|e| {
+    /// Decrypts and validates a notification. Returns it with the time it was received, from
+    /// which its latency is counted against the timestamp of its own measurement.
+    fn accept(
+        &self,
+        keyring: &Keyring,
+        max_hash_age: MaxHashAge,
+        metrics: &Metrics,
+        subject: Option<&str>,
+        body: NotifyBody,
+    ) -> Result<(Notification, f64), NotifyErrorResponse> {
+        let hash_data = HashData::decrypt_with(
+            keyring,
+            &body.cipher_data,
+            &body.experiment_id,
+            &body.measurement_id,
+        )
+        .map_err(|e| {
             self.update_counters(
                 metrics,
-                subject.as_ref().map(|subject| subject.as_str()),
+                subject,
                 ResponseType::from(&e),
+                Some(Reason::from(&e)),
             );
             e
         })?;
         body.validate_body(&hash_data).map_err(|e| {
             self.update_counters(
                 metrics,
-                subject.as_ref().map(|subject| subject.as_str()),
+                subject,
                 ResponseType::from(&e),
+                Some(Reason::from(&e)),
             );
-            e
+            NotifyErrorResponse::BadRequest(PlainText(e.to_string()))
         })?;
-        let latency = compute_latency(hash_data.timestamp);
-
-        if let (Some(subject), Some(pool)) = (subject.as_ref(), pool.as_ref()) {
-            store::insert_latency(
-                pool,
-                subject,
-                &body.experiment_id,
-                &body.measurement_id,
-                &latency,
-            )
-            .await
-            .map_err(|e| {
-                self.update_counters(metrics, Some(subject.as_str()), ResponseType::from(&e));
-                e
-            })?;
-        }
+        let received_at = current_epoch();
+        check_expiry(&hash_data, max_hash_age, received_at)
+            .map_err(|e| self.reject(metrics, subject, e))?;
+        let notification = Notification {
+            group: subject.map(String::from),
+            researcher: hash_data.researcher,
+            experiment_id: body.experiment_id,
+            measurement_id: body.measurement_id,
+            notification_type: hash_data
+                .notification_type
+                .expect("validated notifications have a type"),
+            latency: received_at - hash_data.timestamp,
+        };
+        Ok((notification, received_at))
+    }
 
+    /// Counts, logs and delivers a stored notification.
+    fn complete(
+        &self,
+        metrics: &Metrics,
+        store: &Arc<dyn NotificationStore>,
+        delivery: &Arc<Delivery>,
+        notification: Notification,
+    ) {
         self.update_counters(
             metrics,
-            subject.as_ref().map(|x| x.as_str()),
+            notification.group.as_deref(),
             ResponseType::Ok,
+            None,
         );
+        let labels = LatencyLabels {
+            group: notification.group.clone(),
+            notification_type: notification.type_name().into(),
+        };
+        metrics
+            .latency
+            .get_or_create(&labels)
+            .observe(notification.latency);
+        if notification.latency > score::LATENCY_REQUIREMENT {
+            metrics.late_count.get_or_create(&labels).inc();
+        }
 
         info!(
             "group: {:?}\tmeasurement_id: {}\tlatency: {}s",
-            subject, body.measurement_id, latency
+            notification.group, notification.measurement_id, notification.latency
         );
-        Ok(NotifyResponse::Ok(PlainText(format!("{}", latency))))
+
+        if !delivery.is_empty() {
+            tokio::spawn(deliver(delivery.clone(), store.clone(), notification));
+        }
     }
 
-    #[oai(path = "/metrics", method = "get")]
-    async fn get_metrics(&self, state: Data<&Arc<Mutex<Registry>>>) -> PlainText<String> {
-        let state = state.0.lock().unwrap();
-        let mut body = String::new();
-        text::encode(&mut body, &state).unwrap();
-        PlainText(body)
+    /// Counts the rejection of a notification.
+    fn reject(
+        &self,
+        metrics: &Metrics,
+        subject: Option<&str>,
+        e: NotifyErrorResponse,
+    ) -> NotifyErrorResponse {
+        self.update_counters(
+            metrics,
+            subject,
+            ResponseType::from(&e),
+            Some(Reason::from(&e)),
+        );
+        e
     }
 
     fn update_counters(
@@ -241,12 +877,14 @@ impl Api {
         metrics: &Metrics,
         subject: Option<&str>,
         response_type: ResponseType,
+        reason: Option<Reason>,
     ) {
         metrics
             .response_count
             .get_or_create(&ResponseCountLabels {
                 group: subject.map(|subject| subject.to_string()),
                 response_type,
+                reason,
             })
             .inc();
     }
@@ -255,6 +893,10 @@ impl Api {
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::jwt::{Role, Signer};
+    use crate::memory_store::MemoryStore;
+    use crate::ratelimit::Limit;
+    use crate::sqlite_store::SqliteStore;
     use aes_gcm::aead::{AeadCore, OsRng};
     use aes_gcm::{
         aead::{Aead, KeyInit},
@@ -262,9 +904,11 @@ mod test {
         Key, // Or `Aes128Gcm`
     };
     use base64::{engine::general_purpose, Engine as _};
-    use poem::{middleware::AddDataEndpoint, test::TestClient, EndpointExt, Route};
+    use event_hash::Envelope;
+    use poem::{test::TestClient, Endpoint, EndpointExt, Route};
     use poem_openapi::{types::ToJSON, OpenApiService};
     use serde_json::json;
+    use std::time::Duration;
 
     const SECRET_KEY: &str = "QJUHsPhnA0eiqHuJqsPgzhDozYO4f1zh";
 
@@ -286,14 +930,14 @@ mod test {
             experiment_id: "5678".into(),
             measurement_id: "1234".into(),
             timestamp: 1692029115.4314,
+            issued_at: None,
+            expires_at: None,
         }
     }
 
     fn create_cipher_data(message: String) -> String {
-        let secret_key = SecretKey(SECRET_KEY.into());
-        let key: &[u8] = secret_key.0.as_bytes();
-        let key = Key::<Aes256Gcm>::from_slice(key);
-        let cipher = Aes256Gcm::new(&key);
+        let key = Key::<Aes256Gcm>::from_slice(SECRET_KEY.as_bytes());
+        let cipher = Aes256Gcm::new(key);
         let nonce = Aes256Gcm::generate_nonce(&mut OsRng); // 96-bits; unique per message
         let ciphertext = cipher.encrypt(&nonce, message.as_bytes().as_ref()).unwrap();
 
@@ -302,16 +946,38 @@ mod test {
         b64_nonce + "." + &b64_cipher
     }
 
-    fn get_client(
-    ) -> TestClient<AddDataEndpoint<AddDataEndpoint<Route, SecretKey>, Option<Pool<Postgres>>>>
-    {
-        let secret_key = SecretKey(SECRET_KEY.into());
+    fn get_client() -> TestClient<impl Endpoint> {
+        get_client_with(
+            Metrics::new(),
+            RequireAuth(false),
+            PublicMetrics(false),
+            RateLimiter::default(),
+            Arc::new(MemoryStore::default()),
+        )
+    }
+
+    fn get_client_with(
+        metrics: Metrics,
+        require_auth: RequireAuth,
+        public_metrics: PublicMetrics,
+        rate_limiter: RateLimiter,
+        store: Arc<dyn NotificationStore>,
+    ) -> TestClient<impl Endpoint> {
+        let keyring = Keyring::new(SECRET_KEY.as_bytes()).unwrap();
         let api_service =
             OpenApiService::new(Api, "Hello World", "1.0").server("http://localhost:3000/api");
         let app = Route::new()
             .nest("/api", api_service)
-            .data(secret_key)
-            .data(None);
+            .data(keyring)
+            .data(store)
+            .data(Arc::new(Verifier::new("testdata/jwt.pub", None).unwrap()))
+            .data(require_auth)
+            .data(public_metrics)
+            .data(Arc::new(rate_limiter))
+            .data(MaxHashAge::default())
+            .data(Arc::new(Mutex::new(Registry::default())))
+            .data(metrics)
+            .data(Arc::new(Delivery::default()));
         TestClient::new(app)
     }
 
@@ -319,9 +985,7 @@ mod test {
     async fn post_notify_valid_request() {
         let client = get_client();
         let hash_data = create_hash_data();
-        let secret_key = SecretKey(SECRET_KEY.into());
-        let key: &[u8] = secret_key.0.as_bytes();
-        let cipher_data = hash_data.encrypt(key);
+        let cipher_data = hash_data.encrypt(&SECRET_KEY.parse().unwrap());
         let body = json!({
             "notification_type": "OutOfRange",
             "researcher": "d.landau@uu.nl",
@@ -334,6 +998,656 @@ mod test {
         assert_eq!(res.0.status(), 200);
     }
 
+    #[tokio::test]
+    async fn post_notify_valid_request_with_key_id() {
+        let client = get_client();
+        let keyring = Keyring::with_id("2023-10", SECRET_KEY.as_bytes()).unwrap();
+        let cipher_data = create_hash_data().encrypt_with(&keyring);
+        let body = json!({
+            "notification_type": "OutOfRange",
+            "researcher": "d.landau@uu.nl",
+            "measurement_id": "1234",
+            "experiment_id": "5678",
+            "cipher_data": cipher_data
+        });
+        let res = client.post("/api/notify").body_json(&body).send().await;
+        assert_eq!(res.0.status(), 200);
+    }
+
+    #[tokio::test]
+    async fn post_notify_v2_envelope() {
+        let client = get_client();
+        let mut keyring = Keyring::new(SECRET_KEY.as_bytes()).unwrap();
+        keyring.set_envelope(Envelope::V2);
+        let cipher_data = create_hash_data().encrypt_with(&keyring);
+        let body = |measurement_id: &str| {
+            json!({
+                "notification_type": "OutOfRange",
+                "researcher": "d.landau@uu.nl",
+                "measurement_id": measurement_id,
+                "experiment_id": "5678",
+                "cipher_data": cipher_data
+            })
+        };
+        let res = client
+            .post("/api/notify")
+            .body_json(&body("1234"))
+            .send()
+            .await;
+        assert_eq!(res.0.status(), 200);
+        let res = client
+            .post("/api/notify")
+            .body_json(&body("4321"))
+            .send()
+            .await;
+        assert_eq!(res.0.status(), 400);
+    }
+
+    #[tokio::test]
+    async fn post_notify_duplicate() {
+        let client = get_client();
+        let body = json!({
+            "notification_type": "OutOfRange",
+            "researcher": "d.landau@uu.nl",
+            "measurement_id": "1234",
+            "experiment_id": "5678",
+            "cipher_data": create_hash_data().encrypt(&SECRET_KEY.parse().unwrap())
+        });
+        let res = client.post("/api/notify").body_json(&body).send().await;
+        assert_eq!(res.0.status(), 200);
+        let mut res = client.post("/api/notify").body_json(&body).send().await;
+        assert_eq!(res.0.status(), 409);
+        assert!(res
+            .0
+            .take_body()
+            .into_string()
+            .await
+            .unwrap()
+            .starts_with("Duplicate notification of `1234`. First seen at "));
+    }
+
+    #[tokio::test]
+    async fn post_notify_metrics() {
+        let metrics = Metrics::new();
+        let mut registry = <Registry>::default();
+        registry.register("response_count", "", metrics.response_count.clone());
+        registry.register("latency_seconds", "", metrics.latency.clone());
+        let client = get_client_with(
+            metrics,
+            RequireAuth(false),
+            PublicMetrics(false),
+            RateLimiter::default(),
+            Arc::new(MemoryStore::default()),
+        );
+        let body = |cipher_data: String| {
+            json!({
+                "notification_type": "OutOfRange",
+                "researcher": "d.landau@uu.nl",
+                "measurement_id": "1234",
+                "experiment_id": "5678",
+                "cipher_data": cipher_data
+            })
+        };
+        client
+            .post("/api/notify")
+            .body_json(&body("~8n76xYE4v/AUk1X.5hM".into()))
+            .send()
+            .await;
+        client
+            .post("/api/notify")
+            .body_json(&body(
+                create_hash_data().encrypt(&SECRET_KEY.parse().unwrap()),
+            ))
+            .send()
+            .await;
+
+        let mut encoded = String::new();
+        text::encode(&mut encoded, &registry).unwrap();
+        assert!(encoded.contains(
+            r#"response_count_total{group="",response_type="HashError",reason="MalformedB64Nonce"} 1"#
+        ));
+        assert!(
+            encoded.contains(r#"response_count_total{group="",response_type="Ok",reason=""} 1"#)
+        );
+        assert!(
+            encoded.contains(r#"latency_seconds_count{group="",notification_type="OutOfRange"} 1"#)
+        );
+    }
+
+    #[tokio::test]
+    async fn get_jwks() {
+        let client = get_client();
+        let mut res = client.get("/api/.well-known/jwks.json").send().await;
+        assert_eq!(res.0.status(), 200);
+        let jwks: serde_json::Value =
+            serde_json::from_str(&res.0.take_body().into_string().await.unwrap()).unwrap();
+        assert_eq!(jwks["keys"][0]["kty"], "RSA");
+    }
+
+    fn create_token(subject: &str, role: Role) -> String {
+        Signer::new("testdata/jwt.key", None)
+            .unwrap()
+            .encode(&Claims::new(subject.into(), role, Duration::from_secs(60)))
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn post_notify_requires_token() {
+        let client = get_client_with(
+            Metrics::new(),
+            RequireAuth(true),
+            PublicMetrics(false),
+            RateLimiter::default(),
+            Arc::new(MemoryStore::default()),
+        );
+        let body = json!({
+            "notification_type": "OutOfRange",
+            "researcher": "d.landau@uu.nl",
+            "measurement_id": "1234",
+            "experiment_id": "5678",
+            "cipher_data": create_hash_data().encrypt(&SECRET_KEY.parse().unwrap())
+        });
+        let res = client.post("/api/notify").body_json(&body).send().await;
+        assert_eq!(res.0.status(), 401);
+        let res = client
+            .post("/api/notify")
+            .header("Authorization", "Bearer not-a-token")
+            .body_json(&body)
+            .send()
+            .await;
+        assert_ne!(res.0.status(), 200);
+
+        let res = client
+            .post("/api/notify")
+            .header(
+                "Authorization",
+                format!("Bearer {}", create_token("group0", Role::Group)),
+            )
+            .body_json(&body)
+            .send()
+            .await;
+        assert_eq!(res.0.status(), 200);
+        let res = client
+            .post("/api/notify")
+            .query("token", &create_token("group1", Role::Group))
+            .body_json(&body)
+            .send()
+            .await;
+        assert_eq!(res.0.status(), 200);
+    }
+
+    #[tokio::test]
+    async fn admin_endpoints_require_admin_role() {
+        let client = get_client_with(
+            Metrics::new(),
+            RequireAuth(true),
+            PublicMetrics(false),
+            RateLimiter::default(),
+            Arc::new(MemoryStore::default()),
+        );
+        let group0 = format!("Bearer {}", create_token("group0", Role::Group));
+        let admin = format!("Bearer {}", create_token("landau", Role::Admin));
+
+        let res = client.get("/api/metrics").send().await;
+        assert_eq!(res.0.status(), 401);
+        let res = client
+            .get("/api/metrics")
+            .header("Authorization", &group0)
+            .send()
+            .await;
+        assert_eq!(res.0.status(), 403);
+        let res = client
+            .get("/api/metrics")
+            .header("Authorization", &admin)
+            .send()
+            .await;
+        assert_eq!(res.0.status(), 200);
+
+        for (path, authorization, status) in [
+            ("/api/groups/group1/score", &group0, 403),
+            ("/api/groups/group0/score", &group0, 200),
+            ("/api/groups/group1/score", &admin, 200),
+            ("/api/notifications?group=group1", &group0, 403),
+            ("/api/notifications", &group0, 200),
+            ("/api/notifications", &admin, 200),
+        ] {
+            let res = client
+                .get(path)
+                .header("Authorization", authorization)
+                .send()
+                .await;
+            assert_eq!(res.0.status(), status, "{}", path);
+        }
+    }
+
+    #[tokio::test]
+    async fn anonymous_callers_read_no_group() {
+        let client = get_client();
+        for (path, status) in [
+            ("/api/notifications", 401),
+            ("/api/notifications?group=group0", 401),
+            ("/api/groups/group0/score", 401),
+            ("/api/metrics", 401),
+        ] {
+            let res = client.get(path).send().await;
+            assert_eq!(res.0.status(), status, "{}", path);
+        }
+    }
+
+    #[tokio::test]
+    async fn public_metrics_are_scraped_anonymously() {
+        let client = get_client_with(
+            Metrics::new(),
+            RequireAuth(false),
+            PublicMetrics(true),
+            RateLimiter::default(),
+            Arc::new(MemoryStore::default()),
+        );
+        let res = client.get("/api/metrics").send().await;
+        assert_eq!(res.0.status(), 200);
+        let res = client
+            .get("/api/metrics")
+            .header(
+                "Authorization",
+                format!("Bearer {}", create_token("group0", Role::Group)),
+            )
+            .send()
+            .await;
+        assert_eq!(res.0.status(), 403);
+        let res = client.get("/api/notifications").send().await;
+        assert_eq!(res.0.status(), 401);
+    }
+
+    #[test]
+    fn security_schemes_are_documented() {
+        let spec: serde_json::Value =
+            serde_json::from_str(&OpenApiService::new(Api, "Hello World", "1.0").spec()).unwrap();
+        let schemes = &spec["components"]["securitySchemes"];
+        assert_eq!(schemes["BearerToken"]["scheme"], "bearer");
+        assert_eq!(schemes["QueryToken"]["name"], "token");
+        assert_eq!(
+            spec["paths"]["/metrics"]["get"]["security"],
+            json!([{"BearerToken": []}, {"QueryToken": []}])
+        );
+    }
+
+    #[tokio::test]
+    async fn post_notify_throttled() {
+        let metrics = Metrics::new();
+        let rate_limiter = RateLimiter::new(Some(Limit::new(0.01, 1.0).unwrap()));
+        let client = get_client_with(
+            metrics.clone(),
+            RequireAuth(false),
+            PublicMetrics(false),
+            rate_limiter,
+            Arc::new(MemoryStore::default()),
+        );
+        let body = json!({
+            "notification_type": "OutOfRange",
+            "researcher": "d.landau@uu.nl",
+            "measurement_id": "1234",
+            "experiment_id": "5678",
+            "cipher_data": create_hash_data().encrypt(&SECRET_KEY.parse().unwrap())
+        });
+        let res = client.post("/api/notify").body_json(&body).send().await;
+        assert_eq!(res.0.status(), 200);
+        let res = client.post("/api/notify").body_json(&body).send().await;
+        assert_eq!(res.0.status(), 429);
+        assert_eq!(res.0.headers()["Retry-After"], "100");
+        // Groups have buckets of their own
+        let res = client
+            .post("/api/notify")
+            .header(
+                "Authorization",
+                format!("Bearer {}", create_token("group0", Role::Group)),
+            )
+            .body_json(&body)
+            .send()
+            .await;
+        assert_eq!(res.0.status(), 200);
+        assert_eq!(
+            metrics
+                .response_count
+                .get_or_create(&ResponseCountLabels {
+                    group: None,
+                    response_type: ResponseType::Throttled,
+                    reason: Some(Reason::Throttled),
+                })
+                .get(),
+            1
+        );
+    }
+
+    #[tokio::test]
+    async fn post_notify_batch_throttled() {
+        let rate_limiter = RateLimiter::new(Some(Limit::new(0.01, 2.0).unwrap()));
+        let client = get_client_with(
+            Metrics::new(),
+            RequireAuth(false),
+            PublicMetrics(false),
+            rate_limiter,
+            Arc::new(MemoryStore::default()),
+        );
+        let key = SECRET_KEY.parse().unwrap();
+        let mut hash_data = create_hash_data();
+        let batch: Vec<_> = ["1", "2", "3"]
+            .into_iter()
+            .map(|measurement_id| {
+                hash_data.measurement_id = measurement_id.into();
+                json!({
+                    "notification_type": "OutOfRange",
+                    "researcher": "d.landau@uu.nl",
+                    "measurement_id": measurement_id,
+                    "experiment_id": "5678",
+                    "cipher_data": hash_data.encrypt(&key)
+                })
+            })
+            .collect();
+        let mut res = client
+            .post("/api/notify/batch")
+            .body_json(&batch)
+            .send()
+            .await;
+        assert_eq!(res.0.status(), 400);
+        let message = res.0.take_body().into_string().await.unwrap();
+        assert!(message.contains("burst of 2"), "{}", message);
+        // The rejected batch took nothing from the bucket
+        let res = client
+            .post("/api/notify/batch")
+            .body_json(&&batch[..2])
+            .send()
+            .await;
+        assert_eq!(res.0.status(), 200);
+        let res = client
+            .post("/api/notify/batch")
+            .body_json(&&batch[2..])
+            .send()
+            .await;
+        assert_eq!(res.0.status(), 429);
+        assert_eq!(res.0.headers()["Retry-After"], "100");
+    }
+
+    /// Fails every query.
+    struct FailingStore;
+
+    #[async_trait::async_trait]
+    impl NotificationStore for FailingStore {
+        async fn insert_notification(
+            &self,
+            _notification: &Notification,
+            _received_at: f64,
+        ) -> Result<Option<f64>, sqlx::Error> {
+            Err(sqlx::Error::PoolClosed)
+        }
+
+        async fn insert_notifications(
+            &self,
+            _notifications: &[(&Notification, f64)],
+        ) -> Result<Vec<Option<f64>>, sqlx::Error> {
+            Err(sqlx::Error::PoolClosed)
+        }
+
+        async fn select_notifications(
+            &self,
+            _filter: &store::NotificationFilter<'_>,
+            _cursor: i64,
+            _limit: i64,
+        ) -> Result<Vec<store::NotificationRecord>, sqlx::Error> {
+            Err(sqlx::Error::PoolClosed)
+        }
+
+        async fn insert_delivery(
+            &self,
+            _group_id: Option<&str>,
+            _experiment_id: &str,
+            _measurement_id: &str,
+            _channel: &str,
+            _error: Option<&str>,
+        ) -> Result<(), sqlx::Error> {
+            Err(sqlx::Error::PoolClosed)
+        }
+
+        async fn select_ground_truth(
+            &self,
+            _from: Option<f64>,
+            _to: Option<f64>,
+        ) -> Result<Vec<store::GroundTruthRecord>, sqlx::Error> {
+            Err(sqlx::Error::PoolClosed)
+        }
+    }
+
+    #[tokio::test]
+    async fn failed_batch_insert_answers_each_accepted_notification() {
+        let client = get_client_with(
+            Metrics::new(),
+            RequireAuth(false),
+            PublicMetrics(false),
+            RateLimiter::default(),
+            Arc::new(FailingStore),
+        );
+        let cipher_data = create_hash_data().encrypt(&SECRET_KEY.parse().unwrap());
+        let valid = json!({
+            "notification_type": "OutOfRange",
+            "researcher": "d.landau@uu.nl",
+            "measurement_id": "1234",
+            "experiment_id": "5678",
+            "cipher_data": cipher_data
+        });
+        let mut invalid = valid.clone();
+        invalid["cipher_data"] = "~8n76xYE4v/AUk1X.5hM".into();
+
+        let mut res = client
+            .post("/api/notify/batch")
+            .body_json(&json!([valid, invalid, valid]))
+            .send()
+            .await;
+        assert_eq!(res.0.status(), 200);
+        let items: serde_json::Value =
+            serde_json::from_str(&res.0.take_body().into_string().await.unwrap()).unwrap();
+        let statuses: Vec<_> = items
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|item| item["status"].as_u64().unwrap())
+            .collect();
+        assert_eq!(statuses, [500, 400, 409]);
+    }
+
+    #[tokio::test]
+    async fn post_notify_batch() {
+        let client = get_client();
+        let body = |measurement_id: &str, cipher_data: String| {
+            json!({
+                "notification_type": "OutOfRange",
+                "researcher": "d.landau@uu.nl",
+                "measurement_id": measurement_id,
+                "experiment_id": "5678",
+                "cipher_data": cipher_data
+            })
+        };
+        let key = SECRET_KEY.parse().unwrap();
+        let mut hash_data = create_hash_data();
+        let first = body("1234", hash_data.encrypt(&key));
+        hash_data.measurement_id = "4321".into();
+        let second = body("4321", hash_data.encrypt(&key));
+        let invalid = body("1234", "~8n76xYE4v/AUk1X.5hM".into());
+
+        let mut res = client.post("/api/notify").body_json(&invalid).send().await;
+        let invalid_message = res.0.take_body().into_string().await.unwrap();
+        let mut res = client
+            .post("/api/notify/batch")
+            .body_json(&json!([first, invalid, first, second]))
+            .send()
+            .await;
+        assert_eq!(res.0.status(), 200);
+        let items: serde_json::Value =
+            serde_json::from_str(&res.0.take_body().into_string().await.unwrap()).unwrap();
+        let statuses: Vec<_> = items
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|item| item["status"].as_u64().unwrap())
+            .collect();
+        assert_eq!(statuses, [200, 400, 409, 200]);
+        assert_eq!(items[1]["message"], invalid_message);
+        assert!(items[0]["message"].as_str().unwrap().parse::<f64>().is_ok());
+
+        let mut res = client
+            .post("/api/notify/batch")
+            .body_json(&json!([second]))
+            .send()
+            .await;
+        let items: serde_json::Value =
+            serde_json::from_str(&res.0.take_body().into_string().await.unwrap()).unwrap();
+        assert_eq!(items[0]["status"], 409);
+        let res = client
+            .post("/api/notify/batch")
+            .body_json(&json!([]))
+            .send()
+            .await;
+        assert_eq!(res.0.status(), 400);
+    }
+
+    #[tokio::test]
+    async fn post_notify_expired() {
+        let client = get_client();
+        let hash_data = HashData {
+            issued_at: Some(1692029115.4314),
+            expires_at: Some(1692029175.4314),
+            ..create_hash_data()
+        };
+        let body = json!({
+            "notification_type": "OutOfRange",
+            "researcher": "d.landau@uu.nl",
+            "measurement_id": "1234",
+            "experiment_id": "5678",
+            "cipher_data": hash_data.encrypt(&SECRET_KEY.parse().unwrap())
+        });
+        let res = client.post("/api/notify").body_json(&body).send().await;
+        assert_eq!(res.0.status(), 410);
+    }
+
+    #[test]
+    fn hashes_expire_after_max_age() {
+        let max_age = MaxHashAge(Some(Duration::from_secs(30)));
+        let issued = HashData {
+            issued_at: Some(1692029115.0),
+            expires_at: Some(1692029175.0),
+            ..create_hash_data()
+        };
+        assert!(check_expiry(&issued, MaxHashAge::default(), 1692029175.0).is_ok());
+        assert!(check_expiry(&issued, max_age, 1692029145.0).is_ok());
+        assert!(check_expiry(&issued, max_age, 1692029146.0).is_err());
+
+        // Without `issued_at`, the age counts from the measurement
+        let undated = create_hash_data();
+        assert!(check_expiry(&undated, MaxHashAge::default(), f64::MAX).is_ok());
+        assert!(check_expiry(&undated, max_age, undated.timestamp + 30.0).is_ok());
+        assert!(check_expiry(&undated, max_age, undated.timestamp + 31.0).is_err());
+    }
+
+    #[tokio::test]
+    async fn get_notifications_invalid_notification_type() {
+        let client = get_client();
+        let res = client
+            .get("/api/notifications")
+            .header(
+                "Authorization",
+                format!("Bearer {}", create_token("landau", Role::Admin)),
+            )
+            .query("notification_type", &"Invalid")
+            .send()
+            .await;
+        assert_eq!(res.0.status(), 400);
+    }
+
+    async fn notifications_are_stored(store: Arc<dyn NotificationStore>) {
+        let client = get_client_with(
+            Metrics::new(),
+            RequireAuth(false),
+            PublicMetrics(false),
+            RateLimiter::default(),
+            store,
+        );
+        let group0 = format!("Bearer {}", create_token("group0", Role::Group));
+        let admin = format!("Bearer {}", create_token("landau", Role::Admin));
+        let key = SECRET_KEY.parse().unwrap();
+        let mut hash_data = create_hash_data();
+        let mut batch = vec![];
+        for measurement_id in ["1", "2", "3"] {
+            hash_data.measurement_id = measurement_id.into();
+            batch.push(json!({
+                "notification_type": "OutOfRange",
+                "researcher": "d.landau@uu.nl",
+                "measurement_id": measurement_id,
+                "experiment_id": "5678",
+                "cipher_data": hash_data.encrypt(&key)
+            }));
+        }
+        let res = client
+            .post("/api/notify/batch")
+            .header("Authorization", &group0)
+            .body_json(&batch)
+            .send()
+            .await;
+        assert_eq!(res.0.status(), 200);
+        let res = client.post("/api/notify").body_json(&batch[0]).send().await;
+        assert_eq!(res.0.status(), 200);
+        let res = client
+            .post("/api/notify")
+            .header("Authorization", &group0)
+            .body_json(&batch[0])
+            .send()
+            .await;
+        assert_eq!(res.0.status(), 409);
+
+        let get = |path: String| {
+            let (client, admin) = (&client, &admin);
+            async move {
+                let mut res = client.get(path).header("Authorization", admin).send().await;
+                assert_eq!(res.0.status(), 200);
+                serde_json::from_str::<serde_json::Value>(
+                    &res.0.take_body().into_string().await.unwrap(),
+                )
+                .unwrap()
+            }
+        };
+        let page = get("/api/notifications?limit=3".into()).await;
+        let measurement_ids: Vec<_> = page["notifications"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|notification| notification["measurement_id"].as_str().unwrap())
+            .collect();
+        assert_eq!(measurement_ids, ["1", "2", "3"]);
+        assert_eq!(page["notifications"][0]["group"], "group0");
+        let page = get(format!(
+            "/api/notifications?limit=3&cursor={}",
+            page["next_cursor"]
+        ))
+        .await;
+        assert_eq!(page["notifications"].as_array().unwrap().len(), 1);
+        assert_eq!(page["notifications"][0]["group"], serde_json::Value::Null);
+        assert_eq!(page["next_cursor"], serde_json::Value::Null);
+        let page = get("/api/notifications?group=group0&experiment_id=5678".into()).await;
+        assert_eq!(page["notifications"].as_array().unwrap().len(), 3);
+
+        let score = get("/api/groups/group0/score".into()).await;
+        assert_eq!(score["received"], 3);
+        assert_eq!(score["unexpected"], 3);
+    }
+
+    #[tokio::test]
+    async fn notifications_are_stored_in_memory() {
+        notifications_are_stored(Arc::new(MemoryStore::default())).await;
+    }
+
+    #[tokio::test]
+    async fn notifications_are_stored_in_sqlite() {
+        let store = SqliteStore::connect("sqlite::memory:").await.unwrap();
+        notifications_are_stored(Arc::new(store)).await;
+    }
+
     #[tokio::test]
     async fn post_notify_invalid_cipher_composition() {
         let client = get_client();
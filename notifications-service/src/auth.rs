@@ -0,0 +1,100 @@
+use poem::{Request, RequestBody, Result};
+use poem_openapi::{
+    auth::{ApiKey, Bearer},
+    registry::Registry,
+    ApiExtractor, ApiExtractorType, ExtractParamOptions, SecurityScheme,
+};
+
+use crate::jwt::{Claims, JwtError, Verifier};
+
+/// `Authorization: Bearer <token>`, signed by generate-jwt-token
+#[derive(SecurityScheme)]
+#[oai(ty = "bearer", bearer_format = "JWT")]
+struct BearerToken(Bearer);
+
+/// `?token=<token>`, accepted as well for the clients sending it before the `Authorization`
+/// header was supported
+#[derive(SecurityScheme)]
+#[oai(ty = "api_key", key_name = "token", key_in = "query")]
+struct QueryToken(ApiKey);
+
+#[derive(SecurityScheme)]
+enum TokenScheme {
+    Bearer(BearerToken),
+    Query(QueryToken),
+}
+
+/// Whether requests without a token are rejected, set with `--require-auth`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RequireAuth(pub bool);
+
+/// Whether `/metrics` may be scraped without a token, set with `--public-metrics`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct PublicMetrics(pub bool);
+
+/// The token of the request, from either security scheme. Unlike the schemes themselves it
+/// lets requests without a token through, for `verify` to accept or reject them.
+pub struct Token(pub Option<String>);
+
+#[derive(Debug)]
+pub enum AuthError {
+    Missing,
+    Token(JwtError),
+}
+
+impl std::fmt::Display for AuthError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            AuthError::Missing => write!(f, "Token required"),
+            AuthError::Token(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl Token {
+    /// Claims of the token, or `None` for a request without a token unless it is required.
+    pub fn verify(
+        self,
+        verifier: &Verifier,
+        require_auth: RequireAuth,
+    ) -> Result<Option<Claims>, AuthError> {
+        match self.0 {
+            Some(token) => verifier
+                .decode(&token)
+                .map(|token_data| Some(token_data.claims))
+                .map_err(AuthError::Token),
+            None if require_auth.0 => Err(AuthError::Missing),
+            None => Ok(None),
+        }
+    }
+}
+
+#[poem::async_trait]
+impl<'a> ApiExtractor<'a> for Token {
+    const TYPES: &'static [ApiExtractorType] = &[ApiExtractorType::SecurityScheme];
+
+    type ParamType = ();
+    type ParamRawType = ();
+
+    fn register(registry: &mut Registry) {
+        TokenScheme::register(registry);
+    }
+
+    fn security_schemes() -> Vec<&'static str> {
+        TokenScheme::security_schemes()
+    }
+
+    async fn from_request(
+        request: &'a Request,
+        body: &mut RequestBody,
+        _param_opts: ExtractParamOptions<Self::ParamType>,
+    ) -> Result<Self> {
+        let token =
+            match TokenScheme::from_request(request, body, ExtractParamOptions::default()).await {
+                Ok(TokenScheme::Bearer(BearerToken(bearer))) => Some(bearer.token),
+                Ok(TokenScheme::Query(QueryToken(api_key))) => Some(api_key.key),
+                Err(_) => None,
+            };
+        Ok(Token(token))
+    }
+}
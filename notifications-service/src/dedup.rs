@@ -0,0 +1,73 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::Mutex,
+};
+
+type Key = (Option<String>, String, String);
+
+/// Notifications seen without a database, by group, experiment and measurement. Forgets the
+/// oldest notification once `capacity` are kept.
+pub struct DuplicateCache {
+    capacity: usize,
+    seen: Mutex<(HashMap<Key, f64>, VecDeque<Key>)>,
+}
+
+impl DuplicateCache {
+    pub const DEFAULT_CAPACITY: usize = 100_000;
+
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            seen: Mutex::new((HashMap::new(), VecDeque::new())),
+        }
+    }
+
+    /// Records the notification, or returns when it was first seen if it is a duplicate.
+    pub fn check(
+        &self,
+        group: Option<&str>,
+        experiment_id: &str,
+        measurement_id: &str,
+        received_at: f64,
+    ) -> Option<f64> {
+        let key = (
+            group.map(String::from),
+            experiment_id.to_string(),
+            measurement_id.to_string(),
+        );
+        let mut seen = self.seen.lock().unwrap();
+        let (first_seen, order) = &mut *seen;
+        if let Some(first_seen) = first_seen.get(&key) {
+            return Some(*first_seen);
+        }
+        if order.len() == self.capacity {
+            if let Some(oldest) = order.pop_front() {
+                first_seen.remove(&oldest);
+            }
+        }
+        first_seen.insert(key.clone(), received_at);
+        order.push_back(key);
+        None
+    }
+}
+
+impl Default for DuplicateCache {
+    fn default() -> Self {
+        Self::new(Self::DEFAULT_CAPACITY)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn duplicates_are_detected_within_capacity() {
+        let cache = DuplicateCache::new(2);
+        assert_eq!(cache.check(Some("group0"), "5678", "1", 1.0), None);
+        assert_eq!(cache.check(Some("group0"), "5678", "1", 2.0), Some(1.0));
+        assert_eq!(cache.check(Some("group1"), "5678", "1", 3.0), None);
+        assert_eq!(cache.check(None, "5678", "1", 4.0), None);
+        assert_eq!(cache.check(Some("group0"), "5678", "1", 5.0), None);
+    }
+}
@@ -1,7 +1,8 @@
-use clap::{command, Arg, ArgAction};
+use clap::{command, value_parser, Arg, ArgAction};
+use std::time::Duration;
 
 mod jwt;
-use crate::jwt::{encode, Claims};
+use crate::jwt::{Claims, Role, Signer};
 
 fn main() {
     let mut matches = command!() // requires `cargo` feature
@@ -13,9 +14,52 @@ fn main() {
                 .action(ArgAction::Set)
                 .default_value("group0"),
         )
+        .arg(
+            Arg::new("private-key")
+                .long("private-key")
+                .action(ArgAction::Set)
+                .default_value("notifications-service/ca.key")
+                .help("RSA private key PEM file, or `env:<VARIABLE>` holding the PEM"),
+        )
+        .arg(
+            Arg::new("key-id")
+                .long("key-id")
+                .action(ArgAction::Set)
+                .help("`kid` of the token header, to pick the key from the JWKS"),
+        )
+        .arg(
+            Arg::new("role")
+                .long("role")
+                .action(ArgAction::Set)
+                .value_parser(value_parser!(Role))
+                .default_value("group")
+                .help("`group`, or `admin` to read the data of every group"),
+        )
+        .arg(
+            Arg::new("expires-in")
+                .long("expires-in")
+                .action(ArgAction::Set)
+                .value_parser(value_parser!(u64))
+                .default_value("7776000")
+                .help("Seconds until the token expires"),
+        )
         .get_matches();
 
-    let claims = Claims::new(matches.remove_one::<String>("client-id").expect("required"));
-    let token = encode(&claims).unwrap();
+    let signer = Signer::new(
+        &matches
+            .remove_one::<String>("private-key")
+            .expect("default"),
+        matches.remove_one::<String>("key-id"),
+    )
+    .unwrap_or_else(|err| {
+        eprintln!("{}", err);
+        std::process::exit(1);
+    });
+    let claims = Claims::new(
+        matches.remove_one::<String>("client-id").expect("required"),
+        matches.remove_one::<Role>("role").expect("default"),
+        Duration::from_secs(matches.remove_one::<u64>("expires-in").expect("default")),
+    );
+    let token = signer.encode(&claims).unwrap();
     println!("{}", token);
 }
@@ -1,36 +1,374 @@
-use jsonwebtoken::{self, Algorithm, DecodingKey, EncodingKey, Header, TokenData, Validation};
+use base64::{engine::general_purpose, Engine as _};
+use jsonwebtoken::{
+    self,
+    errors::ErrorKind,
+    jwk::{
+        AlgorithmParameters, CommonParameters, Jwk, JwkSet, PublicKeyUse, RSAKeyParameters,
+        RSAKeyType,
+    },
+    Algorithm, DecodingKey, EncodingKey, Header, TokenData, Validation,
+};
 use serde::{Deserialize, Serialize};
+use simple_asn1::ASN1Block;
+use std::{
+    collections::HashSet,
+    env, fs,
+    path::PathBuf,
+    sync::RwLock,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// What a token may access: a group only its own notifications and score, an admin those of
+/// every group and the metrics.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    #[default]
+    Group,
+    Admin,
+}
+
+impl std::str::FromStr for Role {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "group" => Ok(Role::Group),
+            "admin" => Ok(Role::Admin),
+            _ => Err(format!("Unknown role `{}`, expected `group` or `admin`", s)),
+        }
+    }
+}
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Claims {
-    exp: usize,
+    exp: u64,
+    #[serde(default)]
+    iat: u64,
     pub sub: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub jti: Option<String>,
+    /// Tokens issued before roles existed are group tokens.
+    #[serde(default)]
+    pub role: Role,
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("Time went backwards")
+        .as_secs()
 }
 
 impl Claims {
     #[allow(dead_code)]
-    pub fn new(subject: String) -> Self {
+    pub fn new(subject: String, role: Role, expires_in: Duration) -> Self {
+        let iat = now();
         Self {
-            exp: 1704129818,
+            exp: iat + expires_in.as_secs(),
+            iat,
             sub: subject,
+            jti: Some(format!("{:032x}", rand::random::<u128>())),
+            role,
         }
     }
+
+    #[allow(dead_code)]
+    pub fn is_admin(&self) -> bool {
+        self.role == Role::Admin
+    }
 }
 
-#[allow(dead_code)]
-pub fn encode(claims: &Claims) -> Result<String, jsonwebtoken::errors::Error> {
-    jsonwebtoken::encode(
-        &Header::new(Algorithm::RS256),
-        claims,
-        &EncodingKey::from_rsa_pem(include_bytes!("../ca.key")).unwrap(),
-    )
+#[derive(Debug)]
+pub enum JwtError {
+    InvalidKey(String),
+    InvalidRevocationList(String),
+    Token(jsonwebtoken::errors::Error),
+    Revoked,
+}
+
+impl std::error::Error for JwtError {}
+
+impl std::fmt::Display for JwtError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            JwtError::InvalidKey(err) => write!(f, "Invalid key {}", err),
+            JwtError::InvalidRevocationList(err) => write!(f, "Invalid revocation list {}", err),
+            JwtError::Token(err) => write!(f, "Invalid token: {}", err),
+            JwtError::Revoked => write!(f, "Token is revoked"),
+        }
+    }
+}
+
+impl From<jsonwebtoken::errors::Error> for JwtError {
+    fn from(e: jsonwebtoken::errors::Error) -> Self {
+        JwtError::Token(e)
+    }
+}
+
+impl JwtError {
+    #[allow(dead_code)]
+    pub fn is_expired(&self) -> bool {
+        matches!(self, JwtError::Token(e) if *e.kind() == ErrorKind::ExpiredSignature)
+    }
+}
+
+/// Reads a PEM from `env:<VARIABLE>`, or from the file at `source` otherwise.
+fn read_pem(source: &str) -> Result<Vec<u8>, JwtError> {
+    match source.strip_prefix("env:") {
+        Some(variable) => env::var(variable)
+            .map(String::into_bytes)
+            .map_err(|err| JwtError::InvalidKey(format!("`{}`: {}", source, err))),
+        None => {
+            fs::read(source).map_err(|err| JwtError::InvalidKey(format!("`{}`: {}", source, err)))
+        }
+    }
+}
+
+/// Modulus and exponent of the RSA key of a public key, `RSA PUBLIC KEY` or certificate.
+fn rsa_components(blocks: &[ASN1Block]) -> Option<(Vec<u8>, Vec<u8>)> {
+    blocks.iter().find_map(|block| match block {
+        ASN1Block::Sequence(_, items) => match &items[..] {
+            [ASN1Block::Integer(_, n), ASN1Block::Integer(_, e)] => {
+                Some((n.to_bytes_be().1, e.to_bytes_be().1))
+            }
+            items => rsa_components(items),
+        },
+        ASN1Block::BitString(_, _, bytes) => rsa_components(&simple_asn1::from_der(bytes).ok()?),
+        ASN1Block::Explicit(_, _, _, inner) => rsa_components(std::slice::from_ref(inner)),
+        _ => None,
+    })
+}
+
+/// Issues tokens with the RSA private key.
+pub struct Signer {
+    key: EncodingKey,
+    key_id: Option<String>,
+}
+
+impl Signer {
+    #[allow(dead_code)]
+    pub fn new(source: &str, key_id: Option<String>) -> Result<Self, JwtError> {
+        let key = EncodingKey::from_rsa_pem(&read_pem(source)?)
+            .map_err(|err| JwtError::InvalidKey(format!("`{}`: {}", source, err)))?;
+        Ok(Self { key, key_id })
+    }
+
+    #[allow(dead_code)]
+    pub fn encode(&self, claims: &Claims) -> Result<String, JwtError> {
+        let mut header = Header::new(Algorithm::RS256);
+        header.kid = self.key_id.clone();
+        Ok(jsonwebtoken::encode(&header, claims, &self.key)?)
+    }
+}
+
+/// Revoked token IDs and subjects, e.g. `{"jti": ["..."], "sub": ["group3"]}`.
+#[derive(Default, Deserialize)]
+struct Revocations {
+    #[serde(default)]
+    jti: HashSet<String>,
+    #[serde(default)]
+    sub: HashSet<String>,
+}
+
+struct RevocationList {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+    revocations: Revocations,
+}
+
+/// Verifies tokens with the RSA public key, and rejects revoked ones.
+pub struct Verifier {
+    key: DecodingKey,
+    jwk: Jwk,
+    revocation_list: Option<RwLock<RevocationList>>,
 }
 
 #[allow(dead_code)]
-pub fn decode(token: &str) -> Result<TokenData<Claims>, jsonwebtoken::errors::Error> {
-    jsonwebtoken::decode::<Claims>(
-        &token,
-        &DecodingKey::from_rsa_pem(include_bytes!("../ca.crt")).unwrap(),
-        &Validation::new(Algorithm::RS256),
-    )
+impl Verifier {
+    /// `source` is a public key or certificate PEM.
+    pub fn new(source: &str, key_id: Option<String>) -> Result<Self, JwtError> {
+        let pem = read_pem(source)?;
+        let key = DecodingKey::from_rsa_pem(&pem)
+            .map_err(|err| JwtError::InvalidKey(format!("`{}`: {}", source, err)))?;
+        let (n, e) = pem::parse(&pem)
+            .ok()
+            .and_then(|pem| simple_asn1::from_der(&pem.contents).ok())
+            .and_then(|blocks| rsa_components(&blocks))
+            .ok_or_else(|| JwtError::InvalidKey(format!("`{}`: Not an RSA public key", source)))?;
+        let jwk = Jwk {
+            common: CommonParameters {
+                public_key_use: Some(PublicKeyUse::Signature),
+                algorithm: Some(Algorithm::RS256),
+                key_id,
+                ..Default::default()
+            },
+            algorithm: AlgorithmParameters::RSA(RSAKeyParameters {
+                key_type: RSAKeyType::RSA,
+                n: general_purpose::URL_SAFE_NO_PAD.encode(n),
+                e: general_purpose::URL_SAFE_NO_PAD.encode(e),
+            }),
+        };
+        Ok(Self {
+            key,
+            jwk,
+            revocation_list: None,
+        })
+    }
+
+    pub fn with_revocation_list(mut self, path: PathBuf) -> Result<Self, JwtError> {
+        self.revocation_list = Some(RwLock::new(RevocationList {
+            path,
+            modified: None,
+            revocations: Revocations::default(),
+        }));
+        self.reload_revocations()?;
+        Ok(self)
+    }
+
+    /// Re-reads the revocation list if it changed since it was last read. Returns whether it
+    /// was read.
+    pub fn reload_revocations(&self) -> Result<bool, JwtError> {
+        let Some(revocation_list) = &self.revocation_list else {
+            return Ok(false);
+        };
+        let path = revocation_list.read().unwrap().path.clone();
+        let invalid = |err: &dyn std::fmt::Display| {
+            JwtError::InvalidRevocationList(format!("`{}`: {}", path.display(), err))
+        };
+        let modified = fs::metadata(&path)
+            .and_then(|metadata| metadata.modified())
+            .map_err(|err| invalid(&err))?;
+        if revocation_list.read().unwrap().modified == Some(modified) {
+            return Ok(false);
+        }
+
+        let contents = fs::read_to_string(&path).map_err(|err| invalid(&err))?;
+        let revocations = serde_json::from_str(&contents).map_err(|err| invalid(&err))?;
+        let mut revocation_list = revocation_list.write().unwrap();
+        revocation_list.modified = Some(modified);
+        revocation_list.revocations = revocations;
+        Ok(true)
+    }
+
+    pub fn jwks(&self) -> JwkSet {
+        JwkSet {
+            keys: vec![self.jwk.clone()],
+        }
+    }
+
+    pub fn decode(&self, token: &str) -> Result<TokenData<Claims>, JwtError> {
+        let token_data =
+            jsonwebtoken::decode::<Claims>(token, &self.key, &Validation::new(Algorithm::RS256))?;
+        if let Some(revocation_list) = &self.revocation_list {
+            let revocations = &revocation_list.read().unwrap().revocations;
+            let claims = &token_data.claims;
+            if revocations.sub.contains(&claims.sub)
+                || claims
+                    .jti
+                    .as_ref()
+                    .is_some_and(|jti| revocations.jti.contains(jti))
+            {
+                return Err(JwtError::Revoked);
+            }
+        }
+        Ok(token_data)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const PRIVATE_KEY: &str = "testdata/jwt.key";
+    const PUBLIC_KEY: &str = "testdata/jwt.pub";
+
+    #[test]
+    fn tokens_are_verified_until_they_expire() {
+        let signer = Signer::new(PRIVATE_KEY, Some("2023-10".into())).unwrap();
+        let verifier = Verifier::new(PUBLIC_KEY, Some("2023-10".into())).unwrap();
+
+        let token = signer
+            .encode(&Claims::new(
+                "group0".into(),
+                Role::Group,
+                Duration::from_secs(60),
+            ))
+            .unwrap();
+        let token_data = verifier.decode(&token).unwrap();
+        assert_eq!(token_data.claims.sub, "group0");
+        assert_eq!(token_data.claims.role, Role::Group);
+        assert_eq!(token_data.header.kid.as_deref(), Some("2023-10"));
+
+        let token = signer
+            .encode(&Claims::new(
+                "landau".into(),
+                Role::Admin,
+                Duration::from_secs(60),
+            ))
+            .unwrap();
+        assert!(verifier.decode(&token).unwrap().claims.is_admin());
+
+        let mut expired = Claims::new("group0".into(), Role::Group, Duration::ZERO);
+        expired.exp -= 3600;
+        let token = signer.encode(&expired).unwrap();
+        assert!(verifier.decode(&token).unwrap_err().is_expired());
+    }
+
+    #[test]
+    fn revocations_are_reloaded() {
+        let path = std::env::temp_dir().join("notifications-service-revocations.json");
+        fs::write(&path, "{}").unwrap();
+        let signer = Signer::new(PRIVATE_KEY, None).unwrap();
+        let verifier = Verifier::new(PUBLIC_KEY, None)
+            .unwrap()
+            .with_revocation_list(path.clone())
+            .unwrap();
+        let claims = Claims::new("group0".into(), Role::Group, Duration::from_secs(60));
+        let jti = claims.jti.clone().unwrap();
+        let token = signer.encode(&claims).unwrap();
+        assert!(verifier.decode(&token).is_ok());
+        assert!(!verifier.reload_revocations().unwrap());
+
+        fs::write(&path, format!(r#"{{"jti": ["{}"]}}"#, jti)).unwrap();
+        assert!(verifier.reload_revocations().unwrap());
+        assert!(matches!(verifier.decode(&token), Err(JwtError::Revoked)));
+
+        fs::write(&path, r#"{"sub": ["group0", "group1"]}"#).unwrap();
+        verifier.reload_revocations().unwrap();
+        let token = signer
+            .encode(&Claims::new(
+                "group0".into(),
+                Role::Group,
+                Duration::from_secs(60),
+            ))
+            .unwrap();
+        fs::remove_file(&path).unwrap();
+        assert!(matches!(verifier.decode(&token), Err(JwtError::Revoked)));
+    }
+
+    #[test]
+    fn jwks_holds_the_public_key() {
+        for source in [PUBLIC_KEY, "ca.crt"] {
+            let jwks = serde_json::to_value(Verifier::new(source, None).unwrap().jwks()).unwrap();
+            assert_eq!(jwks["keys"][0]["kty"], "RSA");
+            assert_eq!(jwks["keys"][0]["alg"], "RS256");
+            assert_eq!(jwks["keys"][0]["e"], "AQAB");
+        }
+
+        let jwks = Verifier::new(PUBLIC_KEY, None).unwrap().jwks();
+        let signer = Signer::new(PRIVATE_KEY, None).unwrap();
+        let token = signer
+            .encode(&Claims::new(
+                "group0".into(),
+                Role::Group,
+                Duration::from_secs(60),
+            ))
+            .unwrap();
+        let key = DecodingKey::from_jwk(&jwks.keys[0]).unwrap();
+        assert!(
+            jsonwebtoken::decode::<Claims>(&token, &key, &Validation::new(Algorithm::RS256))
+                .is_ok()
+        );
+    }
 }
@@ -1,5 +1,4 @@
 use clap::Parser;
-use dotenv;
 use metric::Metrics;
 use poem::{listener::TcpListener, EndpointExt, Route};
 use poem_openapi::OpenApiService;
@@ -7,24 +6,118 @@ use prometheus_client::registry::Registry;
 use sqlx::postgres::PgPoolOptions;
 use std::{
     env,
+    path::PathBuf,
     sync::{Arc, Mutex},
+    time::Duration,
 };
-use tracing::{info, Level};
+use tracing::{info, warn, Level};
 
 mod api;
+mod auth;
+mod dedup;
+mod delivery;
 mod jwt;
+mod memory_store;
 mod metric;
+mod ratelimit;
+mod score;
+mod sqlite_store;
 mod store;
+mod template;
 
-use api::{Api, SecretKey};
+use api::{Api, MaxHashAge};
+use auth::{PublicMetrics, RequireAuth};
+use dedup::DuplicateCache;
+use delivery::{Delivery, FileChannel, SmtpChannel, WebhookChannel};
+use event_hash::{Keyring, SecretKey};
+use jwt::Verifier;
+use memory_store::MemoryStore;
+use ratelimit::{Limit, RateLimiter};
+use sqlite_store::SqliteStore;
+use store::{NotificationStore, PostgresStore};
+use template::Templates;
+
+const REVOCATION_RELOAD_INTERVAL: Duration = Duration::from_secs(5);
 
 #[derive(Parser, Debug)]
 struct CliArgs {
-    #[arg(short, long)]
-    secret_key: String,
+    /// 32 character key, or `hex:<key>`, `base64:<key>`, `file:<path>` or `passphrase:<passphrase>`
+    #[arg(short, long, required_unless_present = "keyring_file")]
+    secret_key: Option<SecretKey>,
+
+    /// JSON keyring accepted instead of --secret-key, to decrypt across a key rotation
+    #[arg(long)]
+    keyring_file: Option<String>,
 
     #[arg(short, long)]
     external_ip: String,
+
+    /// Emails the researcher of every notification, e.g. `smtp://localhost:1025`
+    #[arg(long, requires = "smtp_from")]
+    smtp_url: Option<String>,
+
+    /// Sender address of the emails
+    #[arg(long)]
+    smtp_from: Option<String>,
+
+    /// POSTs every notification as JSON to the URL. Can be repeated
+    #[arg(long)]
+    webhook_url: Vec<String>,
+
+    /// Appends every notification as a JSON line to the file, or `-` for stdout
+    #[arg(long)]
+    delivery_file: Option<String>,
+
+    /// Notifications delivered at once; the others wait their turn
+    #[arg(long, default_value_t = Delivery::DEFAULT_MAX_CONCURRENT)]
+    max_concurrent_deliveries: usize,
+
+    /// Notifications kept in memory when DATABASE_URL is unset
+    #[arg(long, default_value_t = DuplicateCache::DEFAULT_CAPACITY)]
+    dedup_capacity: usize,
+
+    /// RSA public key or certificate PEM verifying the tokens, or `env:<VARIABLE>` holding it
+    #[arg(long, default_value = "notifications-service/ca.crt")]
+    jwt_public_key: String,
+
+    /// `kid` of the public key in the JWKS
+    #[arg(long)]
+    jwt_key_id: Option<String>,
+
+    /// JSON of revoked token IDs and subjects, e.g. `{"jti": ["..."], "sub": ["group3"]}`,
+    /// re-read when it changes
+    #[arg(long)]
+    revocation_file: Option<PathBuf>,
+
+    /// Rejects requests without a token, notifications included
+    #[arg(long)]
+    require_auth: bool,
+
+    /// Lets Prometheus scrape the metrics without a token. Otherwise they need an admin token
+    #[arg(long, conflicts_with = "require_auth")]
+    public_metrics: bool,
+
+    /// Notifications per second per token subject, and for all anonymous requests together.
+    /// Unlimited if unset
+    #[arg(long)]
+    rate_limit: Option<f64>,
+
+    /// Notifications accepted at once before --rate-limit applies [default: --rate-limit]
+    #[arg(long, requires = "rate_limit")]
+    rate_limit_burst: Option<f64>,
+
+    /// JSON of the limits of specific subjects, e.g. `{"group3": {"rate": 50, "burst": 100}}`
+    #[arg(long)]
+    rate_limit_file: Option<PathBuf>,
+
+    /// Seconds after it was issued that a measurement hash expires, even without an expiry of
+    /// its own. Hashes without an issue time age from their measurement. Unlimited if unset
+    #[arg(long)]
+    max_hash_age: Option<u64>,
+
+    /// Directory of `OutOfRange.txt` and `Stabilized.txt` message templates
+    #[arg(long)]
+    template_dir: Option<PathBuf>,
 }
 
 #[tokio::main]
@@ -33,7 +126,55 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     dotenv::from_filename("notifications-service/.env")?;
 
     let args = CliArgs::parse();
-    let secret_key = SecretKey(args.secret_key);
+    let keyring = match (&args.keyring_file, &args.secret_key) {
+        (Some(keyring_file), _) => Keyring::from_file(keyring_file)?,
+        (None, Some(secret_key)) => Keyring::from(secret_key.clone()),
+        (None, None) => unreachable!("clap requires one of them"),
+    };
+
+    let mut verifier = Verifier::new(&args.jwt_public_key, args.jwt_key_id.clone())?;
+    if let Some(revocation_file) = &args.revocation_file {
+        verifier = verifier.with_revocation_list(revocation_file.clone())?;
+    }
+    let verifier = Arc::new(verifier);
+    if args.revocation_file.is_some() {
+        let verifier = verifier.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(REVOCATION_RELOAD_INTERVAL);
+            loop {
+                interval.tick().await;
+                match verifier.reload_revocations() {
+                    Ok(true) => info!("Reloaded the revocation list"),
+                    Ok(false) => {}
+                    Err(e) => warn!("{}", e),
+                }
+            }
+        });
+    }
+
+    let limit = match args.rate_limit {
+        Some(rate) => Some(Limit::new(rate, args.rate_limit_burst.unwrap_or(rate))?),
+        None => None,
+    };
+    let mut rate_limiter = RateLimiter::new(limit);
+    if let Some(rate_limit_file) = &args.rate_limit_file {
+        rate_limiter = rate_limiter.with_overrides_file(rate_limit_file)?;
+    }
+
+    let templates = match &args.template_dir {
+        Some(template_dir) => Templates::from_dir(template_dir)?,
+        None => Templates::default(),
+    };
+    let mut delivery = Delivery::new(templates, args.max_concurrent_deliveries);
+    if let (Some(smtp_url), Some(smtp_from)) = (&args.smtp_url, &args.smtp_from) {
+        delivery.add_channel(SmtpChannel::new(smtp_url, smtp_from)?);
+    }
+    for webhook_url in &args.webhook_url {
+        delivery.add_channel(WebhookChannel::new(webhook_url));
+    }
+    if let Some(delivery_file) = &args.delivery_file {
+        delivery.add_channel(FileChannel::new(delivery_file));
+    }
 
     let metrics = Metrics::new();
     let mut registry = <Registry>::default();
@@ -42,21 +183,51 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         "Count of response",
         metrics.response_count.clone(),
     );
+    registry.register(
+        "notifications_service_group_score",
+        "Score of the notifications of a group against the ground truth",
+        metrics.group_score.clone(),
+    );
+    registry.register(
+        "notifications_service_latency_seconds",
+        "Latency of the valid notifications",
+        metrics.latency.clone(),
+    );
+    registry.register(
+        "notifications_service_late_count",
+        "Count of valid notifications over the 10 s latency requirement",
+        metrics.late_count.clone(),
+    );
+    registry.register(
+        "notifications_service_insert_duration_seconds",
+        "Duration of the notification inserts",
+        metrics.insert_duration.clone(),
+    );
+    registry.register(
+        "notifications_service_in_flight_requests",
+        "Requests being handled",
+        metrics.in_flight.clone(),
+    );
     let state = Arc::new(Mutex::new(registry));
 
-    let pool = match env::var("DATABASE_URL") {
+    let store: Arc<dyn NotificationStore> = match env::var("DATABASE_URL") {
+        Ok(database_url) if database_url.starts_with("sqlite:") => {
+            let store = SqliteStore::connect(&database_url)
+                .await
+                .expect("Unable to open database provided in DATABASE_URL");
+            info!("Opened SQLite database");
+            Arc::new(store)
+        }
         Ok(database_url) => {
-            let pool = Some(
-                PgPoolOptions::new()
-                    .max_connections(5)
-                    .connect(&database_url)
-                    .await
-                    .expect("Unable to connect to database provided in DATABASE_URL"),
-            );
+            let pool = PgPoolOptions::new()
+                .max_connections(5)
+                .connect(&database_url)
+                .await
+                .expect("Unable to connect to database provided in DATABASE_URL");
             info!("Created connection pool to database");
-            pool
+            Arc::new(PostgresStore::new(pool))
         }
-        _ => None,
+        _ => Arc::new(MemoryStore::new(args.dedup_capacity)),
     };
 
     let external_ip = args.external_ip;
@@ -67,10 +238,16 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let app = Route::new()
         .nest("/api", api_service)
         .nest("/", ui)
-        .data(secret_key)
+        .data(keyring)
         .data(state)
         .data(metrics.clone())
-        .data(pool);
+        .data(Arc::new(delivery))
+        .data(verifier)
+        .data(RequireAuth(args.require_auth))
+        .data(PublicMetrics(args.public_metrics))
+        .data(Arc::new(rate_limiter))
+        .data(MaxHashAge(args.max_hash_age.map(Duration::from_secs)))
+        .data(store);
 
     Ok(poem::Server::new(TcpListener::bind("0.0.0.0:3000"))
         .run(app)
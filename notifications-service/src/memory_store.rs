@@ -0,0 +1,171 @@
+use async_trait::async_trait;
+use std::{collections::VecDeque, sync::Mutex};
+
+use crate::dedup::DuplicateCache;
+use crate::delivery::Notification;
+use crate::store::{GroundTruthRecord, NotificationFilter, NotificationRecord, NotificationStore};
+
+/// Keeps the last `capacity` notifications in memory, without a database. It holds no ground
+/// truth, and does not record deliveries.
+pub struct MemoryStore {
+    capacity: usize,
+    duplicates: DuplicateCache,
+    /// Last `id`, and the notifications in the order of their `id`
+    notifications: Mutex<(i64, VecDeque<NotificationRecord>)>,
+}
+
+impl MemoryStore {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            duplicates: DuplicateCache::new(capacity),
+            notifications: Mutex::new((0, VecDeque::new())),
+        }
+    }
+}
+
+impl Default for MemoryStore {
+    fn default() -> Self {
+        Self::new(DuplicateCache::DEFAULT_CAPACITY)
+    }
+}
+
+#[async_trait]
+impl NotificationStore for MemoryStore {
+    async fn insert_notification(
+        &self,
+        notification: &Notification,
+        received_at: f64,
+    ) -> Result<Option<f64>, sqlx::Error> {
+        let first_seen = self.duplicates.check(
+            notification.group.as_deref(),
+            &notification.experiment_id,
+            &notification.measurement_id,
+            received_at,
+        );
+        if first_seen.is_some() {
+            return Ok(first_seen);
+        }
+
+        let mut notifications = self.notifications.lock().unwrap();
+        let (last_id, records) = &mut *notifications;
+        *last_id += 1;
+        if records.len() == self.capacity {
+            records.pop_front();
+        }
+        records.push_back(NotificationRecord {
+            id: *last_id,
+            group_id: notification.group.clone(),
+            experiment_id: notification.experiment_id.clone(),
+            measurement_id: notification.measurement_id.clone(),
+            notification_type: notification.type_name().into(),
+            researcher: notification.researcher.clone(),
+            received_at,
+            latency: notification.latency,
+        });
+        Ok(None)
+    }
+
+    async fn insert_notifications(
+        &self,
+        notifications: &[(&Notification, f64)],
+    ) -> Result<Vec<Option<f64>>, sqlx::Error> {
+        let mut first_seen = Vec::with_capacity(notifications.len());
+        for (notification, received_at) in notifications {
+            first_seen.push(self.insert_notification(notification, *received_at).await?);
+        }
+        Ok(first_seen)
+    }
+
+    async fn select_notifications(
+        &self,
+        filter: &NotificationFilter<'_>,
+        cursor: i64,
+        limit: i64,
+    ) -> Result<Vec<NotificationRecord>, sqlx::Error> {
+        let notifications = self.notifications.lock().unwrap();
+        let records = &notifications.1;
+        let start = records.partition_point(|record| record.id <= cursor);
+        Ok(records
+            .range(start..)
+            .filter(|record| filter.matches(record))
+            .take(limit as usize)
+            .cloned()
+            .collect())
+    }
+
+    async fn insert_delivery(
+        &self,
+        _group_id: Option<&str>,
+        _experiment_id: &str,
+        _measurement_id: &str,
+        _channel: &str,
+        _error: Option<&str>,
+    ) -> Result<(), sqlx::Error> {
+        Ok(())
+    }
+
+    async fn select_ground_truth(
+        &self,
+        _from: Option<f64>,
+        _to: Option<f64>,
+    ) -> Result<Vec<GroundTruthRecord>, sqlx::Error> {
+        Ok(vec![])
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use event_hash::NotificationType;
+
+    fn notification(group: &str, measurement_id: &str) -> Notification {
+        Notification {
+            group: Some(group.into()),
+            researcher: "d.landau@uu.nl".into(),
+            experiment_id: "5678".into(),
+            measurement_id: measurement_id.into(),
+            notification_type: NotificationType::OutOfRange,
+            latency: 0.5,
+        }
+    }
+
+    #[tokio::test]
+    async fn notifications_are_kept_within_capacity() {
+        let store = MemoryStore::new(2);
+        for (measurement_id, received_at) in [("1", 1.0), ("2", 2.0), ("3", 3.0)] {
+            let notification = notification("group0", measurement_id);
+            assert_eq!(
+                store
+                    .insert_notification(&notification, received_at)
+                    .await
+                    .unwrap(),
+                None
+            );
+        }
+        assert_eq!(
+            store
+                .insert_notification(&notification("group0", "3"), 4.0)
+                .await
+                .unwrap(),
+            Some(3.0)
+        );
+
+        let filter = NotificationFilter::default();
+        let records = store.select_notifications(&filter, 0, 10).await.unwrap();
+        let ids: Vec<_> = records.iter().map(|record| record.id).collect();
+        assert_eq!(ids, [2, 3]);
+        let records = store.select_notifications(&filter, 2, 10).await.unwrap();
+        assert_eq!(records[0].measurement_id, "3");
+
+        let filter = NotificationFilter {
+            group_id: Some("group1"),
+            ..Default::default()
+        };
+        assert!(store
+            .select_notifications(&filter, 0, 10)
+            .await
+            .unwrap()
+            .is_empty());
+    }
+}
@@ -1,14 +1,39 @@
 use crate::api::NotifyErrorResponse;
-use event_hash::DecryptError;
+use crate::auth::AuthError;
+use crate::jwt::JwtError;
+use event_hash::{DecryptError, ValidationError};
 use prometheus_client::{
     encoding::{EncodeLabelSet, EncodeLabelValue},
-    metrics::{counter::Counter, family::Family},
+    metrics::{
+        counter::Counter,
+        family::Family,
+        gauge::Gauge,
+        histogram::{exponential_buckets, Histogram},
+    },
 };
+use std::sync::atomic::AtomicU64;
 
 #[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
 pub struct ResponseCountLabels {
     pub group: Option<String>,
     pub response_type: ResponseType,
+    pub reason: Option<Reason>,
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+pub struct GroupLabels {
+    pub group: String,
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+pub struct LatencyLabels {
+    pub group: Option<String>,
+    pub notification_type: String,
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+pub struct EndpointLabels {
+    pub endpoint: &'static str,
 }
 
 #[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelValue)]
@@ -18,6 +43,40 @@ pub enum ResponseType {
     InsertError,
     JwtError,
     InvalidData,
+    Expired,
+    Duplicate,
+    Throttled,
+}
+
+/// Why a notification was rejected, finer than its `ResponseType`.
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelValue)]
+pub enum Reason {
+    MalformedHashDataString,
+    MalformedB64Nonce,
+    MalformedB64Ciphertext,
+    DecryptionError,
+    Utf8DecodingError,
+    JsonDeserializationError,
+    UnknownKeyId,
+    MalformedBinaryPayload,
+    UnknownAlgorithm,
+    UnexpectedMeasurementId,
+    UnexpectedExperimentId,
+    UnexpectedResearcher,
+    UnexpectedNotification,
+    UnexpectedNotificationType,
+    MissingToken,
+    InvalidToken,
+    ExpiredToken,
+    RevokedToken,
+    InsertError,
+    Expired,
+    Duplicate,
+    Throttled,
+    /// Of a 400, 401 or 500 response counted without the error it was built from.
+    BadRequest,
+    Unauthorized,
+    InternalError,
 }
 
 impl From<&DecryptError> for ResponseType {
@@ -32,27 +91,146 @@ impl From<&sqlx::Error> for ResponseType {
     }
 }
 
-impl From<&jsonwebtoken::errors::Error> for ResponseType {
-    fn from(_e: &jsonwebtoken::errors::Error) -> Self {
+impl From<&JwtError> for ResponseType {
+    fn from(_e: &JwtError) -> Self {
         ResponseType::JwtError
     }
 }
 
-impl From<&NotifyErrorResponse> for ResponseType {
-    fn from(_e: &NotifyErrorResponse) -> Self {
+impl From<&AuthError> for ResponseType {
+    fn from(_e: &AuthError) -> Self {
+        ResponseType::JwtError
+    }
+}
+
+impl From<&ValidationError> for ResponseType {
+    fn from(_e: &ValidationError) -> Self {
         ResponseType::InvalidData
     }
 }
 
+impl From<&NotifyErrorResponse> for ResponseType {
+    fn from(e: &NotifyErrorResponse) -> Self {
+        match e {
+            NotifyErrorResponse::Expired(_) => ResponseType::Expired,
+            NotifyErrorResponse::Conflict(_) => ResponseType::Duplicate,
+            NotifyErrorResponse::TooManyRequests(..) => ResponseType::Throttled,
+            _ => ResponseType::InvalidData,
+        }
+    }
+}
+
+impl From<&DecryptError> for Reason {
+    fn from(e: &DecryptError) -> Self {
+        match e {
+            DecryptError::MalformedHashDataString => Reason::MalformedHashDataString,
+            DecryptError::MalformedB64Nonce => Reason::MalformedB64Nonce,
+            DecryptError::MalformedB64Ciphertext => Reason::MalformedB64Ciphertext,
+            DecryptError::DecryptionError => Reason::DecryptionError,
+            DecryptError::Utf8DecodingError => Reason::Utf8DecodingError,
+            DecryptError::JsonDeserializationError => Reason::JsonDeserializationError,
+            DecryptError::UnknownKeyId => Reason::UnknownKeyId,
+            DecryptError::MalformedBinaryPayload => Reason::MalformedBinaryPayload,
+            DecryptError::UnknownAlgorithm => Reason::UnknownAlgorithm,
+        }
+    }
+}
+
+impl From<&ValidationError> for Reason {
+    fn from(e: &ValidationError) -> Self {
+        match e {
+            ValidationError::UnexpectedMeasurementId { .. } => Reason::UnexpectedMeasurementId,
+            ValidationError::UnexpectedExperimentId { .. } => Reason::UnexpectedExperimentId,
+            ValidationError::UnexpectedResearcher { .. } => Reason::UnexpectedResearcher,
+            ValidationError::UnexpectedNotification(_) => Reason::UnexpectedNotification,
+            ValidationError::UnexpectedNotificationType { .. } => {
+                Reason::UnexpectedNotificationType
+            }
+        }
+    }
+}
+
+impl From<&sqlx::Error> for Reason {
+    fn from(_e: &sqlx::Error) -> Self {
+        Reason::InsertError
+    }
+}
+
+impl From<&JwtError> for Reason {
+    fn from(e: &JwtError) -> Self {
+        match e {
+            e if e.is_expired() => Reason::ExpiredToken,
+            JwtError::Revoked => Reason::RevokedToken,
+            _ => Reason::InvalidToken,
+        }
+    }
+}
+
+impl From<&AuthError> for Reason {
+    fn from(e: &AuthError) -> Self {
+        match e {
+            AuthError::Missing => Reason::MissingToken,
+            AuthError::Token(e) => Reason::from(e),
+        }
+    }
+}
+
+impl From<&NotifyErrorResponse> for Reason {
+    fn from(e: &NotifyErrorResponse) -> Self {
+        match e {
+            NotifyErrorResponse::BadRequest(_) => Reason::BadRequest,
+            NotifyErrorResponse::Unauthorized(_) => Reason::Unauthorized,
+            NotifyErrorResponse::Conflict(_) => Reason::Duplicate,
+            NotifyErrorResponse::Expired(_) => Reason::Expired,
+            NotifyErrorResponse::TooManyRequests(..) => Reason::Throttled,
+            NotifyErrorResponse::InternalServerError(_) => Reason::InternalError,
+        }
+    }
+}
+
+fn latency_histogram() -> Histogram {
+    Histogram::new([0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 20.0, 30.0, 60.0].into_iter())
+}
+
 #[derive(Clone)]
 pub struct Metrics {
     pub response_count: Family<ResponseCountLabels, Counter>,
+    /// Last score computed by `/groups/{group}/score`
+    pub group_score: Family<GroupLabels, Gauge<f64, AtomicU64>>,
+    pub latency: Family<LatencyLabels, Histogram, fn() -> Histogram>,
+    /// Notifications over `score::LATENCY_REQUIREMENT`
+    pub late_count: Family<LatencyLabels, Counter>,
+    pub insert_duration: Histogram,
+    pub in_flight: Family<EndpointLabels, Gauge>,
 }
 
 impl Metrics {
     pub fn new() -> Self {
         Self {
             response_count: Family::<ResponseCountLabels, Counter>::default(),
+            group_score: Family::<GroupLabels, Gauge<f64, AtomicU64>>::default(),
+            latency: Family::new_with_constructor(latency_histogram),
+            late_count: Family::<LatencyLabels, Counter>::default(),
+            insert_duration: Histogram::new(exponential_buckets(0.001, 2.0, 12)),
+            in_flight: Family::<EndpointLabels, Gauge>::default(),
         }
     }
+
+    /// Counts a request to `endpoint` as in flight until the guard is dropped.
+    pub fn in_flight(&self, endpoint: &'static str) -> InFlight {
+        let gauge = self
+            .in_flight
+            .get_or_create(&EndpointLabels { endpoint })
+            .clone();
+        gauge.inc();
+        InFlight(gauge)
+    }
+}
+
+pub struct InFlight(Gauge);
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.0.dec();
+    }
 }
@@ -0,0 +1,174 @@
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    fs,
+    path::Path,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// `rate` requests per second, refilling a bucket of up to `burst` requests.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+pub struct Limit {
+    pub rate: f64,
+    pub burst: f64,
+}
+
+#[derive(Debug)]
+pub enum RateLimitError {
+    ReadError(String),
+    InvalidLimit(String),
+}
+
+impl std::error::Error for RateLimitError {}
+
+impl std::fmt::Display for RateLimitError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            RateLimitError::ReadError(err) => write!(f, "Could not read rate limits {}", err),
+            RateLimitError::InvalidLimit(err) => write!(f, "Invalid rate limit {}", err),
+        }
+    }
+}
+
+impl Limit {
+    pub fn new(rate: f64, burst: f64) -> Result<Self, RateLimitError> {
+        let limit = Self { rate, burst };
+        limit.validate()?;
+        Ok(limit)
+    }
+
+    fn validate(&self) -> Result<(), RateLimitError> {
+        if self.rate > 0.0 && self.burst >= 1.0 {
+            Ok(())
+        } else {
+            Err(RateLimitError::InvalidLimit(format!(
+                "{:?}: rate must be positive and burst at least 1",
+                self
+            )))
+        }
+    }
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+/// Token buckets limiting the notifications per token subject, with one bucket shared by the
+/// anonymous requests. Subjects without a limit are not limited.
+#[derive(Default)]
+pub struct RateLimiter {
+    limit: Option<Limit>,
+    overrides: HashMap<String, Limit>,
+    buckets: Mutex<HashMap<Option<String>, Bucket>>,
+}
+
+impl RateLimiter {
+    pub fn new(limit: Option<Limit>) -> Self {
+        Self {
+            limit,
+            ..Default::default()
+        }
+    }
+
+    /// Reads the limits of specific subjects, e.g. `{"group3": {"rate": 50, "burst": 100}}`.
+    pub fn with_overrides_file(mut self, path: &Path) -> Result<Self, RateLimitError> {
+        let contents = fs::read_to_string(path)
+            .map_err(|err| RateLimitError::ReadError(format!("`{}`: {}", path.display(), err)))?;
+        let overrides: HashMap<String, Limit> = serde_json::from_str(&contents)
+            .map_err(|err| RateLimitError::ReadError(format!("`{}`: {}", path.display(), err)))?;
+        for limit in overrides.values() {
+            limit.validate()?;
+        }
+        self.overrides = overrides;
+        Ok(self)
+    }
+
+    /// The limit of `subject`, `None` when it is not limited.
+    pub fn limit(&self, subject: Option<&str>) -> Option<&Limit> {
+        subject
+            .and_then(|subject| self.overrides.get(subject))
+            .or(self.limit.as_ref())
+    }
+
+    /// Takes `requests` requests from the bucket of `subject`, or none of them and returns how
+    /// long until the bucket holds them all. More requests than the burst never fit.
+    pub fn acquire(&self, subject: Option<&str>, requests: usize) -> Result<(), Duration> {
+        self.acquire_at(subject, requests, Instant::now())
+    }
+
+    fn acquire_at(
+        &self,
+        subject: Option<&str>,
+        requests: usize,
+        now: Instant,
+    ) -> Result<(), Duration> {
+        let Some(limit) = self.limit(subject) else {
+            return Ok(());
+        };
+        let mut buckets = self.buckets.lock().unwrap();
+        let bucket = buckets.entry(subject.map(String::from)).or_insert(Bucket {
+            tokens: limit.burst,
+            updated: now,
+        });
+        let elapsed = now.saturating_duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * limit.rate).min(limit.burst);
+        bucket.updated = now;
+        let requests = requests as f64;
+        if bucket.tokens >= requests {
+            bucket.tokens -= requests;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64(
+                (requests - bucket.tokens) / limit.rate,
+            ))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn buckets_refill_per_subject() {
+        let path = std::env::temp_dir().join("notifications-service-rate-limits.json");
+        fs::write(&path, r#"{"group1": {"rate": 10, "burst": 1}}"#).unwrap();
+        let limiter = RateLimiter::new(Some(Limit::new(1.0, 2.0).unwrap()))
+            .with_overrides_file(&path)
+            .unwrap();
+        fs::remove_file(&path).unwrap();
+        let now = Instant::now();
+
+        assert!(limiter.acquire_at(Some("group0"), 1, now).is_ok());
+        assert!(limiter.acquire_at(Some("group0"), 1, now).is_ok());
+        assert_eq!(
+            limiter.acquire_at(Some("group0"), 1, now),
+            Err(Duration::from_secs(1))
+        );
+        assert!(limiter.acquire_at(None, 1, now).is_ok());
+        assert!(limiter
+            .acquire_at(Some("group0"), 1, now + Duration::from_secs(1))
+            .is_ok());
+
+        // A batch takes all of its requests, or none when the bucket cannot cover them
+        assert_eq!(
+            limiter.acquire_at(Some("group2"), 3, now),
+            Err(Duration::from_secs(1))
+        );
+        assert!(limiter.acquire_at(Some("group2"), 2, now).is_ok());
+
+        assert!(limiter.acquire_at(Some("group1"), 1, now).is_ok());
+        assert_eq!(
+            limiter.acquire_at(Some("group1"), 1, now),
+            Err(Duration::from_millis(100))
+        );
+        assert!(limiter
+            .acquire_at(Some("group1"), 1, now + Duration::from_millis(100))
+            .is_ok());
+
+        assert!(RateLimiter::default().acquire(Some("group0"), 1).is_ok());
+        assert!(Limit::new(0.0, 1.0).is_err());
+    }
+}
@@ -0,0 +1,158 @@
+use poem_openapi::Object;
+use std::collections::HashMap;
+
+use crate::store::{GroundTruthRecord, NotificationRecord};
+
+/// Latency of the non-functional requirement, in seconds.
+pub const LATENCY_REQUIREMENT: f64 = 10.0;
+
+/// How the notifications of a group compare to the ground truth.
+#[derive(Debug, Object, PartialEq)]
+pub struct Score {
+    expected: usize,
+    received: usize,
+    /// Expected, of the expected type and within the latency requirement
+    on_time: usize,
+    /// Expected but never received
+    missed: usize,
+    /// Received but not expected
+    unexpected: usize,
+    /// Expected, but of another type
+    wrong_type: usize,
+    /// Expected and of the expected type, but over the latency requirement
+    late: usize,
+    latency_p50: Option<f64>,
+    latency_p90: Option<f64>,
+    latency_p99: Option<f64>,
+    /// `on_time / (expected + unexpected)`, or 1 when there is neither
+    pub score: f64,
+}
+
+/// Nearest-rank percentile of sorted latencies.
+fn percentile(latencies: &[f64], p: f64) -> Option<f64> {
+    if latencies.is_empty() {
+        return None;
+    }
+    let rank = (p * latencies.len() as f64).ceil() as usize;
+    Some(latencies[rank.saturating_sub(1)])
+}
+
+pub fn grade(expected: &[GroundTruthRecord], received: &[NotificationRecord]) -> Score {
+    let expected_types: HashMap<(&str, &str), Option<&str>> = expected
+        .iter()
+        .map(|record| {
+            (
+                (
+                    record.experiment_id.as_str(),
+                    record.measurement_id.as_str(),
+                ),
+                record.notification_type.as_deref(),
+            )
+        })
+        .collect();
+
+    let (mut on_time, mut unexpected, mut wrong_type, mut late, mut matched) = (0, 0, 0, 0, 0);
+    for notification in received {
+        let key = (
+            notification.experiment_id.as_str(),
+            notification.measurement_id.as_str(),
+        );
+        match expected_types.get(&key) {
+            None => unexpected += 1,
+            Some(expected_type) => {
+                matched += 1;
+                match expected_type {
+                    Some(expected_type) if *expected_type != notification.notification_type => {
+                        wrong_type += 1
+                    }
+                    _ if notification.latency > LATENCY_REQUIREMENT => late += 1,
+                    _ => on_time += 1,
+                }
+            }
+        }
+    }
+
+    let mut latencies: Vec<f64> = received
+        .iter()
+        .map(|notification| notification.latency)
+        .collect();
+    latencies.sort_by(f64::total_cmp);
+    let total = expected_types.len() + unexpected;
+    Score {
+        expected: expected_types.len(),
+        received: received.len(),
+        on_time,
+        missed: expected_types.len() - matched,
+        unexpected,
+        wrong_type,
+        late,
+        latency_p50: percentile(&latencies, 0.5),
+        latency_p90: percentile(&latencies, 0.9),
+        latency_p99: percentile(&latencies, 0.99),
+        score: match total {
+            0 => 1.0,
+            total => on_time as f64 / total as f64,
+        },
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn expected(measurement_id: &str, notification_type: &str) -> GroundTruthRecord {
+        GroundTruthRecord {
+            experiment_id: "5678".into(),
+            measurement_id: measurement_id.into(),
+            notification_type: Some(notification_type.into()),
+        }
+    }
+
+    fn received(measurement_id: &str, notification_type: &str, latency: f64) -> NotificationRecord {
+        NotificationRecord {
+            id: 0,
+            group_id: Some("group0".into()),
+            experiment_id: "5678".into(),
+            measurement_id: measurement_id.into(),
+            notification_type: notification_type.into(),
+            researcher: "d.landau@uu.nl".into(),
+            received_at: 1692029115.4314,
+            latency,
+        }
+    }
+
+    #[test]
+    fn notifications_are_graded_against_the_ground_truth() {
+        let score = grade(
+            &[
+                expected("1", "OutOfRange"),
+                expected("2", "Stabilized"),
+                expected("3", "OutOfRange"),
+                expected("4", "Stabilized"),
+            ],
+            &[
+                received("1", "OutOfRange", 0.5),
+                received("2", "OutOfRange", 1.5),
+                received("3", "OutOfRange", 12.0),
+                received("5", "Stabilized", 2.5),
+            ],
+        );
+        assert_eq!(
+            score,
+            Score {
+                expected: 4,
+                received: 4,
+                on_time: 1,
+                missed: 1,
+                unexpected: 1,
+                wrong_type: 1,
+                late: 1,
+                latency_p50: Some(1.5),
+                latency_p90: Some(12.0),
+                latency_p99: Some(12.0),
+                score: 0.2,
+            }
+        );
+        assert_eq!(grade(&[], &[]).score, 1.0);
+    }
+}
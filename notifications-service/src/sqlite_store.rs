@@ -0,0 +1,272 @@
+use async_trait::async_trait;
+use sqlx::{
+    sqlite::{SqliteConnectOptions, SqlitePoolOptions},
+    Executor, Pool, Sqlite, SqliteConnection,
+};
+use std::str::FromStr;
+
+use crate::delivery::Notification;
+use crate::store::{GroundTruthRecord, NotificationFilter, NotificationRecord, NotificationStore};
+
+const SCHEMA: &str = include_str!("../../database/ddl/sqlite.sql");
+
+/// The schema of `database/ddl/sqlite.sql` in a SQLite database, for local development.
+pub struct SqliteStore {
+    pool: Pool<Sqlite>,
+}
+
+impl SqliteStore {
+    /// Opens `url`, e.g. `sqlite:notifications.db` or `sqlite::memory:`, and creates the
+    /// database and its tables if missing.
+    pub async fn connect(url: &str) -> Result<Self, sqlx::Error> {
+        let options = SqliteConnectOptions::from_str(url)?.create_if_missing(true);
+        // A single connection that is kept open: SQLite serializes writes anyway, and every
+        // connection to `sqlite::memory:` opens a database of its own
+        let pool = SqlitePoolOptions::new()
+            .max_connections(1)
+            .idle_timeout(None)
+            .max_lifetime(None)
+            .connect_with(options)
+            .await?;
+        pool.execute(SCHEMA).await?;
+        Ok(Self { pool })
+    }
+}
+
+async fn insert_notification(
+    connection: &mut SqliteConnection,
+    notification: &Notification,
+    received_at: f64,
+) -> Result<Option<f64>, sqlx::Error> {
+    let inserted: Option<(i64,)> = sqlx::query_as(
+        "
+            INSERT INTO
+                notification (experiment_id, measurement_id, group_id, notification_type, researcher, received_at, latency)
+            VALUES
+                (?1, ?2, ?3, ?4, ?5, ?6, ?7)
+            ON CONFLICT
+                DO NOTHING
+            RETURNING
+                id;
+            ",
+    )
+    .bind(&notification.experiment_id)
+    .bind(&notification.measurement_id)
+    .bind(notification.group.as_deref())
+    .bind(notification.type_name())
+    .bind(&notification.researcher)
+    .bind(received_at)
+    .bind(notification.latency)
+    .fetch_optional(&mut *connection)
+    .await?;
+    if inserted.is_some() {
+        return Ok(None);
+    }
+
+    let (first_seen,): (f64,) = sqlx::query_as(
+        "
+            SELECT
+                received_at
+            FROM
+                notification
+            WHERE
+                experiment_id = ?1
+                AND measurement_id = ?2
+                AND group_id IS ?3;
+            ",
+    )
+    .bind(&notification.experiment_id)
+    .bind(&notification.measurement_id)
+    .bind(notification.group.as_deref())
+    .fetch_one(&mut *connection)
+    .await?;
+    Ok(Some(first_seen))
+}
+
+#[async_trait]
+impl NotificationStore for SqliteStore {
+    async fn insert_notification(
+        &self,
+        notification: &Notification,
+        received_at: f64,
+    ) -> Result<Option<f64>, sqlx::Error> {
+        let mut connection = self.pool.acquire().await?;
+        insert_notification(&mut connection, notification, received_at).await
+    }
+
+    /// One transaction rather than one statement; SQLite runs in process.
+    async fn insert_notifications(
+        &self,
+        notifications: &[(&Notification, f64)],
+    ) -> Result<Vec<Option<f64>>, sqlx::Error> {
+        let mut transaction = self.pool.begin().await?;
+        let mut first_seen = Vec::with_capacity(notifications.len());
+        for (notification, received_at) in notifications {
+            first_seen
+                .push(insert_notification(&mut transaction, notification, *received_at).await?);
+        }
+        transaction.commit().await?;
+        Ok(first_seen)
+    }
+
+    async fn select_notifications(
+        &self,
+        filter: &NotificationFilter<'_>,
+        cursor: i64,
+        limit: i64,
+    ) -> Result<Vec<NotificationRecord>, sqlx::Error> {
+        sqlx::query_as(
+            "
+            SELECT
+                id, group_id, experiment_id, measurement_id, notification_type, researcher, received_at, latency
+            FROM
+                notification
+            WHERE
+                (?1 IS NULL OR group_id = ?1)
+                AND (?2 IS NULL OR experiment_id = ?2)
+                AND (?3 IS NULL OR researcher = ?3)
+                AND (?4 IS NULL OR notification_type = ?4)
+                AND (?5 IS NULL OR received_at >= ?5)
+                AND (?6 IS NULL OR received_at < ?6)
+                AND id > ?7
+            ORDER BY
+                id
+            LIMIT
+                ?8;
+            ",
+        )
+        .bind(filter.group_id)
+        .bind(filter.experiment_id)
+        .bind(filter.researcher)
+        .bind(filter.notification_type)
+        .bind(filter.received_after)
+        .bind(filter.received_before)
+        .bind(cursor)
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    async fn insert_delivery(
+        &self,
+        group_id: Option<&str>,
+        experiment_id: &str,
+        measurement_id: &str,
+        channel: &str,
+        error: Option<&str>,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            "
+            INSERT INTO
+                notification_delivery (experiment_id, measurement_id, group_id, channel, error)
+            VALUES
+                (?1, ?2, ?3, ?4, ?5);
+            ",
+        )
+        .bind(experiment_id)
+        .bind(measurement_id)
+        .bind(group_id)
+        .bind(channel)
+        .bind(error)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn select_ground_truth(
+        &self,
+        from: Option<f64>,
+        to: Option<f64>,
+    ) -> Result<Vec<GroundTruthRecord>, sqlx::Error> {
+        sqlx::query_as(
+            "
+            SELECT
+                experiment_id, measurement_id, notification_type
+            FROM
+                notification_ground_truth
+            WHERE
+                (?1 IS NULL OR insert_timestamp >= ?1)
+                AND (?2 IS NULL OR insert_timestamp < ?2);
+            ",
+        )
+        .bind(from)
+        .bind(to)
+        .fetch_all(&self.pool)
+        .await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use event_hash::NotificationType;
+
+    fn notification(group: Option<&str>, measurement_id: &str) -> Notification {
+        Notification {
+            group: group.map(String::from),
+            researcher: "d.landau@uu.nl".into(),
+            experiment_id: "5678".into(),
+            measurement_id: measurement_id.into(),
+            notification_type: NotificationType::Stabilized,
+            latency: 0.5,
+        }
+    }
+
+    #[tokio::test]
+    async fn notifications_round_trip() {
+        let store = SqliteStore::connect("sqlite::memory:").await.unwrap();
+        let first = notification(Some("group0"), "1");
+        let anonymous = notification(None, "1");
+        let inserted = store
+            .insert_notifications(&[(&first, 1.0), (&anonymous, 2.0)])
+            .await
+            .unwrap();
+        assert_eq!(inserted, [None, None]);
+        assert_eq!(
+            store.insert_notification(&first, 3.0).await.unwrap(),
+            Some(1.0)
+        );
+        assert_eq!(
+            store.insert_notification(&anonymous, 4.0).await.unwrap(),
+            Some(2.0)
+        );
+
+        let filter = NotificationFilter {
+            group_id: Some("group0"),
+            notification_type: Some("Stabilized"),
+            received_before: Some(2.0),
+            ..Default::default()
+        };
+        let records = store.select_notifications(&filter, 0, 10).await.unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].group_id.as_deref(), Some("group0"));
+        assert_eq!(records[0].received_at, 1.0);
+        let records = store
+            .select_notifications(&NotificationFilter::default(), records[0].id, 10)
+            .await
+            .unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].group_id, None);
+
+        store
+            .insert_delivery(Some("group0"), "5678", "1", "file", None)
+            .await
+            .unwrap();
+        sqlx::query(
+            "INSERT INTO notification_ground_truth (experiment_id, measurement_id) VALUES ('5678', '1')",
+        )
+        .execute(&store.pool)
+        .await
+        .unwrap();
+        assert_eq!(
+            store.select_ground_truth(None, None).await.unwrap().len(),
+            1
+        );
+        assert!(store
+            .select_ground_truth(Some(f64::MAX), None)
+            .await
+            .unwrap()
+            .is_empty());
+    }
+}
@@ -1,28 +1,397 @@
-use sqlx::{Pool, Postgres};
+use async_trait::async_trait;
+use sqlx::{FromRow, Pool, Postgres};
 
-pub async fn insert_latency(
+use crate::delivery::Notification;
+
+/// A notification as received by `/notify`. `received_at` is seconds since the epoch.
+#[derive(Clone, Debug, FromRow)]
+pub struct NotificationRecord {
+    pub id: i64,
+    pub group_id: Option<String>,
+    pub experiment_id: String,
+    pub measurement_id: String,
+    pub notification_type: String,
+    pub researcher: String,
+    pub received_at: f64,
+    pub latency: f64,
+}
+
+/// A notification the experiment producer expects. `notification_type` is absent in rows
+/// loaded before it was recorded.
+#[derive(FromRow)]
+pub struct GroundTruthRecord {
+    pub experiment_id: String,
+    pub measurement_id: String,
+    pub notification_type: Option<String>,
+}
+
+/// Filters of `select_notifications`, all optional. The time window is `[received_after,
+/// received_before)`.
+#[derive(Default)]
+pub struct NotificationFilter<'a> {
+    pub group_id: Option<&'a str>,
+    pub experiment_id: Option<&'a str>,
+    pub researcher: Option<&'a str>,
+    pub notification_type: Option<&'a str>,
+    pub received_after: Option<f64>,
+    pub received_before: Option<f64>,
+}
+
+impl NotificationFilter<'_> {
+    pub fn matches(&self, record: &NotificationRecord) -> bool {
+        self.group_id.map_or(true, |group_id| {
+            record.group_id.as_deref() == Some(group_id)
+        }) && self
+            .experiment_id
+            .map_or(true, |experiment_id| record.experiment_id == experiment_id)
+            && self
+                .researcher
+                .map_or(true, |researcher| record.researcher == researcher)
+            && self.notification_type.map_or(true, |notification_type| {
+                record.notification_type == notification_type
+            })
+            && self
+                .received_after
+                .map_or(true, |received_after| record.received_at >= received_after)
+            && self
+                .received_before
+                .map_or(true, |received_before| record.received_at < received_before)
+    }
+}
+
+/// Where the notifications, their deliveries and the ground truth are kept: Postgres, SQLite
+/// or memory, after `DATABASE_URL`.
+#[async_trait]
+pub trait NotificationStore: Send + Sync {
+    /// Keeps the first notification of a group per measurement. Returns when the first one
+    /// was received if `notification` is a duplicate.
+    async fn insert_notification(
+        &self,
+        notification: &Notification,
+        received_at: f64,
+    ) -> Result<Option<f64>, sqlx::Error>;
+
+    /// `insert_notification` of every notification, in one round trip. The notifications must
+    /// not duplicate each other.
+    async fn insert_notifications(
+        &self,
+        notifications: &[(&Notification, f64)],
+    ) -> Result<Vec<Option<f64>>, sqlx::Error>;
+
+    /// Notifications matching `filter` with an `id` over `cursor`, oldest first.
+    async fn select_notifications(
+        &self,
+        filter: &NotificationFilter<'_>,
+        cursor: i64,
+        limit: i64,
+    ) -> Result<Vec<NotificationRecord>, sqlx::Error>;
+
+    async fn insert_delivery(
+        &self,
+        group_id: Option<&str>,
+        experiment_id: &str,
+        measurement_id: &str,
+        channel: &str,
+        error: Option<&str>,
+    ) -> Result<(), sqlx::Error>;
+
+    /// Expected notifications inserted in `[from, to)`, in seconds since the epoch.
+    async fn select_ground_truth(
+        &self,
+        from: Option<f64>,
+        to: Option<f64>,
+    ) -> Result<Vec<GroundTruthRecord>, sqlx::Error>;
+}
+
+/// The `demo` schema of `database/ddl`, shared with the experiment producer.
+pub struct PostgresStore {
+    pool: Pool<Postgres>,
+}
+
+impl PostgresStore {
+    pub fn new(pool: Pool<Postgres>) -> Self {
+        Self { pool }
+    }
+}
+
+#[async_trait]
+impl NotificationStore for PostgresStore {
+    async fn insert_notification(
+        &self,
+        notification: &Notification,
+        received_at: f64,
+    ) -> Result<Option<f64>, sqlx::Error> {
+        insert_notification(&self.pool, notification, received_at).await
+    }
+
+    async fn insert_notifications(
+        &self,
+        notifications: &[(&Notification, f64)],
+    ) -> Result<Vec<Option<f64>>, sqlx::Error> {
+        insert_notifications(&self.pool, notifications).await
+    }
+
+    async fn select_notifications(
+        &self,
+        filter: &NotificationFilter<'_>,
+        cursor: i64,
+        limit: i64,
+    ) -> Result<Vec<NotificationRecord>, sqlx::Error> {
+        select_notifications(&self.pool, filter, cursor, limit).await
+    }
+
+    async fn insert_delivery(
+        &self,
+        group_id: Option<&str>,
+        experiment_id: &str,
+        measurement_id: &str,
+        channel: &str,
+        error: Option<&str>,
+    ) -> Result<(), sqlx::Error> {
+        insert_delivery(
+            &self.pool,
+            group_id,
+            experiment_id,
+            measurement_id,
+            channel,
+            error,
+        )
+        .await
+    }
+
+    async fn select_ground_truth(
+        &self,
+        from: Option<f64>,
+        to: Option<f64>,
+    ) -> Result<Vec<GroundTruthRecord>, sqlx::Error> {
+        select_ground_truth(&self.pool, from, to).await
+    }
+}
+
+/// Keeps the first notification of a group per measurement. Returns when the first one was
+/// received if `notification` is a duplicate.
+async fn insert_notification(
     pool: &Pool<Postgres>,
-    group_id: &str,
+    notification: &Notification,
+    received_at: f64,
+) -> Result<Option<f64>, sqlx::Error> {
+    let inserted = sqlx::query!(
+        "
+            INSERT INTO 
+                demo.notification (experiment_id, measurement_id, group_id, notification_type, researcher, received_at, latency) 
+            VALUES 
+                ($1, $2, $3, $4, $5, $6, $7)
+            ON CONFLICT
+                DO NOTHING
+            RETURNING
+                id;
+            ",
+        notification.experiment_id,
+        notification.measurement_id,
+        notification.group.as_deref(),
+        notification.type_name(),
+        notification.researcher,
+        received_at,
+        notification.latency,
+    )
+    .fetch_optional(pool)
+    .await?;
+    if inserted.is_some() {
+        return Ok(None);
+    }
+
+    let first = sqlx::query!(
+        "
+            SELECT 
+                received_at
+            FROM 
+                demo.notification
+            WHERE 
+                experiment_id = $1
+                AND measurement_id = $2
+                AND group_id IS NOT DISTINCT FROM $3;
+            ",
+        notification.experiment_id,
+        notification.measurement_id,
+        notification.group.as_deref(),
+    )
+    .fetch_one(pool)
+    .await?;
+
+    Ok(Some(first.received_at))
+}
+
+/// Inserts the notifications in one round trip, and returns when each was first received if
+/// it is a duplicate. The notifications must not duplicate each other.
+async fn insert_notifications(
+    pool: &Pool<Postgres>,
+    notifications: &[(&Notification, f64)],
+) -> Result<Vec<Option<f64>>, sqlx::Error> {
+    let mut experiment_ids = Vec::with_capacity(notifications.len());
+    let mut measurement_ids = Vec::with_capacity(notifications.len());
+    let mut group_ids = Vec::with_capacity(notifications.len());
+    let mut notification_types = Vec::with_capacity(notifications.len());
+    let mut researchers = Vec::with_capacity(notifications.len());
+    let mut received_ats = Vec::with_capacity(notifications.len());
+    let mut latencies = Vec::with_capacity(notifications.len());
+    for (notification, received_at) in notifications {
+        experiment_ids.push(notification.experiment_id.as_str());
+        measurement_ids.push(notification.measurement_id.as_str());
+        group_ids.push(notification.group.as_deref());
+        notification_types.push(notification.type_name());
+        researchers.push(notification.researcher.as_str());
+        received_ats.push(*received_at);
+        latencies.push(notification.latency);
+    }
+
+    let rows = sqlx::query!(
+        r#"
+            WITH input AS (
+                SELECT
+                    *
+                FROM
+                    UNNEST($1::TEXT[], $2::TEXT[], $3::TEXT[], $4::TEXT[], $5::TEXT[], $6::FLOAT8[], $7::FLOAT8[])
+                    WITH ORDINALITY AS t (experiment_id, measurement_id, group_id, notification_type, researcher, received_at, latency, position)
+            ), inserted AS (
+                INSERT INTO 
+                    demo.notification (experiment_id, measurement_id, group_id, notification_type, researcher, received_at, latency) 
+                SELECT
+                    experiment_id, measurement_id, group_id, notification_type, researcher, received_at, latency
+                FROM
+                    input
+                ON CONFLICT
+                    DO NOTHING
+                RETURNING
+                    experiment_id, measurement_id, group_id
+            )
+            SELECT
+                input.position AS "position!",
+                inserted.measurement_id IS NOT NULL AS "inserted!",
+                existing.received_at AS "first_seen?"
+            FROM
+                input
+                LEFT JOIN inserted
+                    ON inserted.experiment_id = input.experiment_id
+                    AND inserted.measurement_id = input.measurement_id
+                    AND inserted.group_id IS NOT DISTINCT FROM input.group_id
+                LEFT JOIN demo.notification existing
+                    ON existing.experiment_id = input.experiment_id
+                    AND existing.measurement_id = input.measurement_id
+                    AND existing.group_id IS NOT DISTINCT FROM input.group_id
+            ORDER BY
+                input.position;
+            "#,
+        &experiment_ids as &[&str],
+        &measurement_ids as &[&str],
+        &group_ids as &[Option<&str>],
+        &notification_types as &[&str],
+        &researchers as &[&str],
+        &received_ats,
+        &latencies,
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows
+        .into_iter()
+        .map(|row| match row.inserted {
+            true => None,
+            // Not yet visible when inserted concurrently by another request
+            false => Some(
+                row.first_seen
+                    .unwrap_or(notifications[row.position as usize - 1].1),
+            ),
+        })
+        .collect())
+}
+
+/// Up to `limit` notifications with an ID after `cursor`, in the order they were received.
+async fn select_notifications(
+    pool: &Pool<Postgres>,
+    filter: &NotificationFilter<'_>,
+    cursor: i64,
+    limit: i64,
+) -> Result<Vec<NotificationRecord>, sqlx::Error> {
+    sqlx::query_as!(
+        NotificationRecord,
+        "
+            SELECT 
+                id, group_id, experiment_id, measurement_id, notification_type, researcher, received_at, latency
+            FROM 
+                demo.notification
+            WHERE 
+                ($1::TEXT IS NULL OR group_id = $1)
+                AND ($2::TEXT IS NULL OR experiment_id = $2)
+                AND ($3::TEXT IS NULL OR researcher = $3)
+                AND ($4::TEXT IS NULL OR notification_type = $4)
+                AND ($5::FLOAT8 IS NULL OR received_at >= $5)
+                AND ($6::FLOAT8 IS NULL OR received_at < $6)
+                AND id > $7
+            ORDER BY 
+                id
+            LIMIT 
+                $8;
+            ",
+        filter.group_id,
+        filter.experiment_id,
+        filter.researcher,
+        filter.notification_type,
+        filter.received_after,
+        filter.received_before,
+        cursor,
+        limit,
+    )
+    .fetch_all(pool)
+    .await
+}
+
+async fn insert_delivery(
+    pool: &Pool<Postgres>,
+    group_id: Option<&str>,
     experiment_id: &str,
     measurement_id: &str,
-    latency: &f64,
+    channel: &str,
+    error: Option<&str>,
 ) -> Result<(), sqlx::Error> {
     sqlx::query!(
         "
             INSERT INTO 
-                demo.notification (experiment_id, measurement_id, group_id, latency) 
+                demo.notification_delivery (experiment_id, measurement_id, group_id, channel, error) 
             VALUES 
-                ($1, $2, $3, $4)
-            ON CONFLICT
-                DO NOTHING;
+                ($1, $2, $3, $4, $5);
             ",
         experiment_id,
         measurement_id,
         group_id,
-        latency,
+        channel,
+        error,
     )
     .execute(pool)
     .await?;
 
     Ok(())
 }
+
+/// Expected notifications inserted in `[from, to)`, in seconds since the epoch.
+async fn select_ground_truth(
+    pool: &Pool<Postgres>,
+    from: Option<f64>,
+    to: Option<f64>,
+) -> Result<Vec<GroundTruthRecord>, sqlx::Error> {
+    sqlx::query_as!(
+        GroundTruthRecord,
+        "
+            SELECT 
+                experiment_id, measurement_id, notification_type
+            FROM 
+                demo.notification_ground_truth
+            WHERE 
+                ($1::FLOAT8 IS NULL OR EXTRACT(EPOCH FROM insert_timestamp) >= $1)
+                AND ($2::FLOAT8 IS NULL OR EXTRACT(EPOCH FROM insert_timestamp) < $2);
+            ",
+        from,
+        to,
+    )
+    .fetch_all(pool)
+    .await
+}
@@ -0,0 +1,93 @@
+use event_hash::NotificationType;
+use serde::Serialize;
+use std::{fs, io, path::Path};
+use tinytemplate::TinyTemplate;
+
+const OUT_OF_RANGE: &str = "\
+Experiment {experiment_id}: temperature out of range
+
+Dear {researcher},
+
+The temperature of experiment {experiment_id} left the configured range at measurement {measurement_id}.
+";
+
+const STABILIZED: &str = "\
+Experiment {experiment_id}: temperature stabilized
+
+Dear {researcher},
+
+The temperature of experiment {experiment_id} stabilized at measurement {measurement_id}.
+";
+
+/// Values a template can refer to, e.g. `{researcher}`.
+#[derive(Serialize)]
+pub struct TemplateContext<'a> {
+    pub researcher: &'a str,
+    pub experiment_id: &'a str,
+    pub measurement_id: &'a str,
+    pub notification_type: &'a str,
+    pub latency: f64,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct RenderedMessage {
+    pub subject: String,
+    pub body: String,
+}
+
+/// A message template per notification type. The first line of a template is the subject
+/// and the rest the body.
+#[derive(Clone, Debug)]
+pub struct Templates {
+    out_of_range: String,
+    stabilized: String,
+}
+
+impl Default for Templates {
+    fn default() -> Self {
+        Self {
+            out_of_range: OUT_OF_RANGE.into(),
+            stabilized: STABILIZED.into(),
+        }
+    }
+}
+
+impl Templates {
+    /// Reads `OutOfRange.txt` and `Stabilized.txt` from `dir`, keeping the default template
+    /// of a missing file.
+    pub fn from_dir(dir: &Path) -> io::Result<Self> {
+        let mut templates = Self::default();
+        for (file_name, template) in [
+            ("OutOfRange.txt", &mut templates.out_of_range),
+            ("Stabilized.txt", &mut templates.stabilized),
+        ] {
+            match fs::read_to_string(dir.join(file_name)) {
+                Ok(contents) => *template = contents,
+                Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(templates)
+    }
+
+    pub fn render(
+        &self,
+        notification_type: &NotificationType,
+        context: &TemplateContext,
+    ) -> Result<RenderedMessage, tinytemplate::error::Error> {
+        let template = match notification_type {
+            NotificationType::OutOfRange => &self.out_of_range,
+            NotificationType::Stabilized => &self.stabilized,
+        };
+        let mut tt = TinyTemplate::new();
+        tt.set_default_formatter(&tinytemplate::format_unescaped);
+        tt.add_template("message", template)?;
+        let rendered = tt.render("message", context)?;
+
+        let (subject, body) = rendered.split_once('\n').unwrap_or((&rendered, ""));
+        Ok(RenderedMessage {
+            subject: subject.trim().into(),
+            body: body.trim_start_matches('\n').into(),
+        })
+    }
+}
@@ -1,6 +1,7 @@
 use apache_avro::{from_value, Reader};
 use clap::ArgMatches;
-use event_hash::{HashData, NotificationType};
+use event_hash::{DecryptError, HashData, Keyring, NotificationType, SecretKey};
+use event_sequence::{SequenceStatus, SequenceTracker};
 use rdkafka::{
     client::ClientContext,
     config::ClientConfig,
@@ -11,7 +12,7 @@ use rdkafka::{
 use reqwest::Client;
 use rand::Rng;
 use serde::Deserialize;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use tokio::time::{self, Duration};
 
 #[derive(Deserialize, Debug)]
@@ -22,6 +23,76 @@ struct SensorTemperatureMeasured {
     timestamp: f64,
     temperature: f32,
     measurement_hash: String,
+    sequence: u64,
+    sensor_sequence: u64,
+}
+
+impl SensorTemperatureMeasured {
+    /// Decrypts the measurement hash, which v2 envelopes bind to the experiment and
+    /// measurement of the reading.
+    fn hash_data(&self, keyring: &Keyring) -> Result<HashData, DecryptError> {
+        HashData::decrypt_with(
+            keyring,
+            &self.measurement_hash,
+            &self.experiment,
+            &self.measurement_id,
+        )
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct ExperimentTerminated {
+    experiment: String,
+}
+
+/// Reports lost, duplicated and reordered measurements.
+#[derive(Default)]
+struct MeasurementSequences {
+    experiment: SequenceTracker<String>,
+    sensor: SequenceTracker<(String, String)>,
+    /// Experiments that terminated, so that a late measurement does not start their
+    /// sequences over. Only their IDs are kept.
+    terminated: HashSet<String>,
+}
+
+impl MeasurementSequences {
+    /// Returns the reports of the sequences the measurement is out of.
+    fn check(&mut self, measurement: &SensorTemperatureMeasured) -> Vec<String> {
+        if self.terminated.contains(&measurement.experiment) {
+            return vec![format!(
+                "Experiment {} measurement {} arrived after it terminated",
+                measurement.experiment, measurement.measurement_id
+            )];
+        }
+        let mut reports = vec![];
+        let status = self
+            .experiment
+            .check(measurement.experiment.clone(), measurement.sequence);
+        if status != SequenceStatus::InOrder {
+            reports.push(format!(
+                "Experiment {} sequence: {:?}",
+                measurement.experiment, status
+            ));
+        }
+        let status = self.sensor.check(
+            (measurement.experiment.clone(), measurement.sensor.clone()),
+            measurement.sensor_sequence,
+        );
+        if status != SequenceStatus::InOrder {
+            reports.push(format!(
+                "Experiment {} sensor {} sequence: {:?}",
+                measurement.experiment, measurement.sensor, status
+            ));
+        }
+        reports
+    }
+
+    /// Forgets the sequences of a terminated experiment, which sends no more measurements.
+    fn terminate(&mut self, experiment: &str) {
+        self.experiment.remove(&experiment.to_string());
+        self.sensor.retain(|(key, _)| key != experiment);
+        self.terminated.insert(experiment.into());
+    }
 }
 
 struct CustomContext;
@@ -35,7 +106,7 @@ impl ConsumerContext for CustomContext {
 }
 
 pub struct ConsumeConfiguration {
-    secret_key: String,
+    keyring: Keyring,
     group_id: String,
     brokers: String,
     topic: String,
@@ -44,14 +115,21 @@ pub struct ConsumeConfiguration {
 
 impl From<&mut ArgMatches> for ConsumeConfiguration {
     fn from(args: &mut ArgMatches) -> Self {
-        let secret_key = args.remove_one::<String>("secret-key").expect("Required");
+        let keyring = match args.remove_one::<String>("keyring-file") {
+            Some(keyring_file) => Keyring::from_file(&keyring_file),
+            None => Ok(Keyring::from(
+                args.remove_one::<SecretKey>("secret-key")
+                    .expect("Required"),
+            )),
+        }
+        .unwrap_or_else(|err| panic!("{}", err));
         let brokers = args.remove_one::<String>("broker-list").expect("Required");
         let group_id = args.remove_one::<String>("group-id").expect("Required");
         let topic = args.remove_one::<String>("topic").expect("Required");
         let notifications_host = args.remove_one::<String>("notifications-host").expect("Required");
 
         ConsumeConfiguration {
-            secret_key,
+            keyring,
             group_id,
             brokers,
             topic,
@@ -64,6 +142,7 @@ pub struct Consume {
     config: ConsumeConfiguration,
     consumer: StreamConsumer<CustomContext>,
     client: Client,
+    sequences: MeasurementSequences,
 }
 
 impl Consume {
@@ -91,17 +170,18 @@ impl Consume {
             config,
             consumer,
             client,
+            sequences: MeasurementSequences::default(),
         }
     }
 
-    pub async fn start(&self) {
+    pub async fn start(&mut self) {
         self.consumer
             .subscribe(&[self.config.topic.as_str()])
             .expect("Can't subscribe to specified topics");
         self.read_loop().await;
     }
 
-    async fn read_loop(&self) {
+    async fn read_loop(&mut self) {
         loop {
             match self.consumer.recv().await {
                 Err(e) => println!("Kafka error: {}", e),
@@ -114,6 +194,14 @@ impl Consume {
                     let headers = headers.unwrap();
                     let record_name =
                         String::from_utf8(headers.get(0).unwrap().1.to_vec()).expect("Valid utf-8");
+                    if record_name == "experiment_terminated" {
+                        for value in Reader::new(m.payload().unwrap()).unwrap() {
+                            let terminated: ExperimentTerminated =
+                                from_value(&value.unwrap()).expect("Received invalid event");
+                            self.sequences.terminate(&terminated.experiment);
+                        }
+                        continue;
+                    }
                     if record_name != "sensor_temperature_measured" {
                         continue;
                     }
@@ -123,12 +211,13 @@ impl Consume {
                             from_value::<SensorTemperatureMeasured>(&value.unwrap())
                                 .expect("Received invalid event")
                                 .into();
+                        for report in self.sequences.check(&sensor_measurement) {
+                            println!("{}", report);
+                        }
 
-                        let hash_data = HashData::decrypt(
-                            self.config.secret_key.as_bytes(),
-                            &sensor_measurement.measurement_hash,
-                        )
-                        .expect("Valid measurement_hash");
+                        let hash_data = sensor_measurement
+                            .hash_data(&self.config.keyring)
+                            .expect("Valid measurement_hash");
                         if hash_data.notification_type.is_none() {
                             break;
                         }
@@ -189,3 +278,84 @@ impl Consume {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use event_hash::Envelope;
+
+    fn reading(
+        keyring: &Keyring,
+        experiment: &str,
+        sensor: &str,
+        sequence: u64,
+    ) -> SensorTemperatureMeasured {
+        let measurement_id = format!("{}-{}", experiment, sequence);
+        let hash_data = HashData {
+            notification_type: None,
+            researcher: "d.landau@uu.nl".into(),
+            experiment_id: experiment.into(),
+            measurement_id: measurement_id.clone(),
+            timestamp: 1692029115.4314,
+            issued_at: None,
+            expires_at: None,
+        };
+        SensorTemperatureMeasured {
+            experiment: experiment.into(),
+            sensor: sensor.into(),
+            measurement_id,
+            timestamp: hash_data.timestamp,
+            temperature: 26.0,
+            measurement_hash: hash_data.encrypt_with(keyring),
+            sequence,
+            sensor_sequence: sequence,
+        }
+    }
+
+    #[test]
+    fn reused_sensor_reports_for_each_experiment() {
+        let mut keyring = Keyring::new(b"QJUHsPhnA0eiqHuJqsPgzhDozYO4f1zh").unwrap();
+        keyring.set_envelope(Envelope::V2);
+        let mut sequences = MeasurementSequences::default();
+
+        // The room's sensor reports for one experiment, then for the next one in the room
+        for experiment in ["a", "b"] {
+            for sequence in 0..3 {
+                let reading = reading(&keyring, experiment, "sensor-0", sequence);
+                assert_eq!(sequences.check(&reading), Vec::<String>::new());
+                let hash_data = reading.hash_data(&keyring).unwrap();
+                assert_eq!(hash_data.experiment_id, experiment);
+            }
+        }
+
+        // A reading claimed by the other experiment does not open
+        let mut reading = reading(&keyring, "a", "sensor-0", 3);
+        reading.experiment = "b".into();
+        assert!(reading.hash_data(&keyring).is_err());
+    }
+
+    #[test]
+    fn late_measurements_of_terminated_experiments_are_reported() {
+        let keyring = Keyring::new(b"QJUHsPhnA0eiqHuJqsPgzhDozYO4f1zh").unwrap();
+        let mut sequences = MeasurementSequences::default();
+        for sequence in 0..2 {
+            assert!(sequences
+                .check(&reading(&keyring, "a", "sensor-0", sequence))
+                .is_empty());
+        }
+        sequences.terminate("a");
+
+        let late = reading(&keyring, "a", "sensor-0", 5);
+        assert_eq!(
+            sequences.check(&late),
+            vec!["Experiment a measurement a-5 arrived after it terminated"]
+        );
+        assert_eq!(
+            sequences.check(&late),
+            vec!["Experiment a measurement a-5 arrived after it terminated"]
+        );
+        assert!(sequences
+            .check(&reading(&keyring, "b", "sensor-0", 0))
+            .is_empty());
+    }
+}
@@ -1,4 +1,6 @@
 use clap::{command, Arg, ArgAction};
+use event_hash::SecretKey;
+use std::str::FromStr;
 use crate::{consume::{ConsumeConfiguration, Consume}};
 
 mod consume;
@@ -12,7 +14,14 @@ async fn main() {
             .long("secret-key")
             .action(ArgAction::Set)
             .default_value("QJUHsPhnA0eiqHuJqsPgzhDozYO4f1zh")
-            .help("<key> is a 32 character string that must match the key being passed to the notifications-service")
+            .value_parser(SecretKey::from_str)
+            .help("<key> is a 32 character string, or `hex:<key>`, `base64:<key>`, `file:<path>` or `passphrase:<passphrase>`, that must match the key being passed to the notifications-service")
+        )
+        .arg(Arg::new("keyring-file")
+            .required(false)
+            .long("keyring-file")
+            .action(ArgAction::Set)
+            .help("JSON keyring used instead of --secret-key, to decrypt measurement hashes across a key rotation")
         )
         .arg(Arg::new("broker-list")
             .required(true)
@@ -40,6 +49,6 @@ async fn main() {
         .get_matches();
 
     let consume_config = ConsumeConfiguration::from(&mut matches);
-    let consume = Consume::new(consume_config);
+    let mut consume = Consume::new(consume_config);
     consume.start().await;
 }
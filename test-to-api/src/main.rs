@@ -1,5 +1,6 @@
 use clap::{command, Arg, ArgAction};
 use dashmap::DashMap;
+use event_hash::SecretKey;
 use futures::future;
 use poem::{
     listener::TcpListener,
@@ -9,7 +10,7 @@ use poem::{
 use poem_openapi::{payload::PlainText, OpenApi, OpenApiService};
 use rand::Rng;
 use serde::Deserialize;
-use std::sync::Arc;
+use std::{str::FromStr, sync::Arc};
 
 mod consumer;
 
@@ -125,7 +126,8 @@ async fn main() {
             .long("secret-key")
             .action(ArgAction::Set)
             .default_value("QJUHsPhnA0eiqHuJqsPgzhDozYO4f1zh")
-            .help("<key> is a 32 character string that must match the key being passed to the notifications-service")
+            .value_parser(SecretKey::from_str)
+            .help("<key> is a 32 character string, or `hex:<key>`, `base64:<key>`, `file:<path>` or `passphrase:<passphrase>`, that must match the key being passed to the notifications-service")
         )
         .arg(Arg::new("broker-list")
             .required(true)